@@ -3,16 +3,55 @@
 //! This module defines the data structures used to parse command line
 //! arguments when running the program.
 
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::corpus::{ExportFormat, Layout, filter::PairFilter, schema::Features};
 
 /// This struct represents the top-level CLI entry point for the tool.
 #[derive(Parser)]
 #[command(about = "Manages the corpus of C-Rust program pairs", long_about = None)]
 pub struct Cli {
+    #[command(flatten)]
+    pub directories: DirectoryArgs,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+/// Arguments that choose where the corpus is read from and written to.
+///
+/// Each overrides the same setting in `corpus.config.json`, which in turn
+/// overrides the default layout under the root directory.
+#[derive(Args)]
+pub struct DirectoryArgs {
+    /// The directory holding `corpus.config.json` and `corpus.lock.json`,
+    /// and, by default, the metadata, output and cache directories.
+    #[arg(long, global = true, env = "CORPUS_ROOT")]
+    pub root: Option<PathBuf>,
+
+    /// A directory of metadata files of the full corpus, instead of
+    /// `metadata/project/` and `metadata/individual/`.  May be repeated.
+    #[arg(
+        long = "metadata-dir",
+        global = true,
+        env = "CORPUS_METADATA_DIRS",
+        value_delimiter = ','
+    )]
+    pub metadata_directories: Vec<PathBuf>,
+
+    /// The directory program pairs are downloaded into, instead of
+    /// `program_pairs/`.
+    #[arg(long = "output-dir", global = true, env = "CORPUS_OUTPUT_DIR")]
+    pub output_directory: Option<PathBuf>,
+
+    /// The directory repositories are cloned into, instead of
+    /// `repository_clones/`.
+    #[arg(long = "cache-dir", global = true, env = "CORPUS_CACHE_DIR")]
+    pub cache_directory: Option<PathBuf>,
+}
+
 /// This struct represents the different commands available.
 #[derive(Subcommand)]
 pub enum Commands {
@@ -20,8 +59,244 @@ pub enum Commands {
     Demo,
 
     /// Downloads all C-Rust program pairs.
-    Download,
+    Download(DownloadArgs),
+
+    /// Fetches the cached clones and copies again only the program pairs
+    /// that changed, removing those no longer in the metadata.
+    Update(UpdateArgs),
+
+    /// Delete the output and cache directories, `program_pairs` and
+    /// `repository_clones` by default, or selected pairs or clones.
+    Delete(DeleteArgs),
+
+    /// Removes the cached clones that no metadata file uses.
+    Gc(GcArgs),
+
+    /// Checks all metadata files without downloading anything.
+    Validate,
+
+    /// Checks that every source path exists in the cached clones, and
+    /// suggests close matches for those that do not.
+    CheckPaths(CheckPathsArgs),
+
+    /// Lists the program pairs in the metadata as a table.
+    List(ListArgs),
+
+    /// Shows the full metadata of one program pair.
+    Show(ShowArgs),
+
+    /// Packs the downloaded program pairs into a single file.
+    Export(ExportArgs),
+
+    /// Unpacks a corpus created by `export` into the output directory.
+    Import(ImportArgs),
+}
+
+/// Arguments for the `download` command.
+#[derive(Args)]
+pub struct DownloadArgs {
+    /// Reproduce the corpus recorded in `corpus.lock.json`, failing if any
+    /// commit or file hash differs.
+    #[arg(long)]
+    pub locked: bool,
+
+    /// How files are arranged under `c-program/` and `rust-program/`.
+    #[arg(long, value_enum, default_value_t = Layout::Flat)]
+    pub layout: Layout,
+
+    /// The number of repositories cloned, or programs copied, at the same
+    /// time.  Zero uses one job per CPU.
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+
+    /// Build the corpus from `repository_clones/` only, never touching the
+    /// network, and fail if any pair cannot be built from it.
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Fetch repositories without their file contents, and check out only
+    /// the `source_paths` of the programs copied from them.  Needs `git`.
+    #[arg(long)]
+    pub sparse: bool,
+
+    /// Only download the pair with this program name.  May be repeated.
+    #[arg(long = "pair")]
+    pub pairs: Vec<String>,
+
+    /// Read this metadata file, or directory of them, instead of
+    /// `metadata/project/` and `metadata/individual/`.  May be repeated.
+    #[arg(long)]
+    pub metadata: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Write a JSON report of every metadata file and pair to this file.
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    /// Exit successfully even if some pairs failed to download.
+    #[arg(long)]
+    pub keep_going: bool,
+}
+
+/// Arguments for the `update` command.
+#[derive(Args)]
+pub struct UpdateArgs {
+    /// How files are arranged under `c-program/` and `rust-program/`.
+    #[arg(long, value_enum, default_value_t = Layout::Flat)]
+    pub layout: Layout,
+
+    /// The number of repositories fetched, or programs copied, at the same
+    /// time.  Zero uses one job per CPU.
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+
+    /// Fetch repositories without their file contents, and check out only
+    /// the `source_paths` of the programs copied from them.  Needs `git`.
+    #[arg(long)]
+    pub sparse: bool,
+}
+
+/// Arguments for the `delete` command.
+#[derive(Args)]
+pub struct DeleteArgs {
+    /// Only delete the downloaded pair with this program name.  May be
+    /// repeated.
+    #[arg(long = "pair", conflicts_with = "cache_only")]
+    pub pairs: Vec<String>,
+
+    /// Only delete the cached clone of the repository with this URL.  May be
+    /// repeated.
+    #[arg(long = "repository", conflicts_with = "outputs_only")]
+    pub repositories: Vec<String>,
+
+    /// Only delete the output directory, keeping the cached clones.
+    #[arg(long, conflicts_with = "cache_only")]
+    pub outputs_only: bool,
+
+    /// Only delete the cache directory, keeping the downloaded pairs.
+    #[arg(long)]
+    pub cache_only: bool,
+
+    /// List what would be deleted, and the space it takes up, without
+    /// deleting anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Delete directories even if they lack the marker file of directories
+    /// created by this tool.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for the `gc` command.
+#[derive(Args)]
+pub struct GcArgs {
+    /// List the clones that would be removed, and the space they take up,
+    /// without removing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Remove clones even if the cache directory lacks the marker file of
+    /// directories created by this tool.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for the `check-paths` command.
+#[derive(Args)]
+pub struct CheckPathsArgs {
+    /// Check the demo program pairs instead of all program pairs.
+    #[arg(long)]
+    pub demo: bool,
+}
+
+/// Arguments for the `export` command.
+#[derive(Args)]
+pub struct ExportArgs {
+    /// The file format of the export.
+    #[arg(long, value_enum, default_value_t = ExportFormat::TarGz)]
+    pub format: ExportFormat,
+
+    /// Where to write the export.  Defaults to `corpus.tar.gz` or
+    /// `corpus.jsonl`, depending on the format.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// For `jsonl`, write one record per source file instead of one per
+    /// program pair.
+    #[arg(long)]
+    pub split_files: bool,
+
+    /// For `jsonl`, skip records whose line is longer than this many bytes,
+    /// listing each skipped record and the pairs left out of the dataset.
+    #[arg(long, value_name = "BYTES")]
+    pub skip_records_over_bytes: Option<usize>,
+}
+
+/// Arguments for the `import` command.
+#[derive(Args)]
+pub struct ImportArgs {
+    /// The `tar.gz` file created by `export`.
+    pub archive: PathBuf,
+}
+
+/// Arguments that select program pairs.
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Only select pairs from this project, such as `coreutils`.  May be
+    /// repeated.
+    #[arg(long = "project")]
+    pub projects: Vec<String>,
+
+    /// Only select pairs with this feature relationship.  May be repeated.
+    #[arg(long = "feature", value_enum)]
+    pub features: Vec<Features>,
+
+    /// Only select pairs translated with this tool, such as `c2rust`.  May
+    /// be repeated.
+    #[arg(long = "tool")]
+    pub tools: Vec<String>,
+}
+
+impl From<FilterArgs> for PairFilter {
+    fn from(arguments: FilterArgs) -> Self {
+        PairFilter {
+            names: Vec::new(),
+            projects: arguments.projects,
+            features: arguments.features,
+            tools: arguments.tools,
+        }
+    }
+}
+
+/// Arguments for the `list` command.
+#[derive(Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// List the demo program pairs instead of all program pairs.
+    #[arg(long)]
+    pub demo: bool,
+
+    /// Print the pairs as JSON instead of a table.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Arguments for the `show` command.
+#[derive(Args)]
+pub struct ShowArgs {
+    /// The name of the program pair.
+    pub program_name: String,
+
+    /// Look the pair up in the demo program pairs.
+    #[arg(long)]
+    pub demo: bool,
 
-    /// Delete the `program_pairs` and `repository_clones` directories.
-    Delete,
+    /// Print the pair as JSON.
+    #[arg(long)]
+    pub json: bool,
 }
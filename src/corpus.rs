@@ -2,14 +2,33 @@
 //!
 //! This module transforms schema files into strongly-typed Rust structs.
 
+mod archive;
+pub mod check_paths;
+pub mod config;
 mod delete;
 pub mod downloader;
 pub mod errors;
+pub mod export;
+pub mod filter;
+mod git;
+mod loader;
+pub mod lockfile;
+pub mod manifest;
 mod metadata_structs;
 pub mod parser;
+pub mod patterns;
+pub mod report;
 pub mod schema;
+mod sparse;
 mod utils;
+pub mod validate;
 
-pub use delete::delete;
-pub use downloader::download_program_pairs;
+pub use check_paths::check_paths;
+pub use delete::{DeleteOptions, Removal, delete, gc};
+pub use downloader::{
+    DownloadOptions, Layout, UpdateSummary, download_program_pairs, update_program_pairs,
+};
+pub use export::{ExportFormat, ExportOptions, export, import};
+pub use loader::{Corpus, PairFiles, ProgramFiles};
 pub use parser::parse;
+pub use validate::validate;
@@ -0,0 +1,410 @@
+//! # Release Archives
+//!
+//! A program may be downloaded from a release archive, such as
+//! `coreutils-9.5.tar.gz`, instead of cloning its repository.  The archive is
+//! checked against its SHA-256 checksum, unpacked into the clone cache, and
+//! its files are committed to a new git repository there.  The rest of the
+//! downloader then treats it like any other clone: its commit is recorded in
+//! the lockfile, and `source_paths` are resolved within it.
+//!
+//! Release archives usually hold a single directory, such as
+//! `coreutils-9.5/`, whose contents become the root of the repository.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use flate2::read::GzDecoder;
+use git2::{IndexAddOption, Oid, Repository, Signature, Time};
+use sha2::{Digest, Sha256};
+
+use ureq::tls::{PemItem, RootCerts, TlsConfig};
+
+use crate::corpus::{config::NetworkConfig, errors::DownloaderError, git, schema::Archive, utils};
+
+/// The reference the commit of an unpacked archive is stored under, which
+/// is where the default branch of a clone is looked up.
+const ARCHIVE_REFERENCE: &str = "refs/remotes/origin/HEAD";
+
+/// The extension of the directory an archive is unpacked into before it is
+/// moved into the clone cache.
+pub(crate) const STAGING_EXTENSION: &str = "unpacking";
+
+/// The author and committer name of the commit of an unpacked archive.
+const ARCHIVE_AUTHOR: &str = "c-rust-program-pairs";
+
+/// The author and committer email of the commit of an unpacked archive.
+const ARCHIVE_EMAIL: &str = "archive@c-rust-program-pairs.invalid";
+
+/// Downloads a release archive and unpacks it into a new repository.
+///
+/// The archive is unpacked next to `repository_path` and only moved there
+/// once it has been committed, so an interrupted download never leaves a
+/// partial repository in the cache.
+///
+/// # Arguments
+///
+/// - `repository_path` - Where the repository is created.
+/// - `archive` - The archive to download.
+/// - `fetch_url` - The URL or local path the archive is downloaded from,
+///   after applying the configured rewrites.
+/// - `network` - The proxy and certificate authorities used to download
+///   the archive, and whether to commit it with the user's and system's git
+///   configuration.
+/// - `received_bytes` - Incremented by the size of the archive.
+///
+/// # Returns
+///
+/// The new [`Repository`], whose `origin` remote is the archive URL and whose
+/// default branch holds a single commit of the archive's files, on success,
+/// or a [`DownloaderError`] on failure.
+pub fn unpack_archive(
+    repository_path: &Path,
+    archive: &Archive,
+    fetch_url: &str,
+    network: &NetworkConfig,
+    received_bytes: &AtomicU64,
+) -> Result<Repository, DownloaderError> {
+    let contents = read_archive(&archive.url, fetch_url, network)?;
+    received_bytes.fetch_add(contents.len() as u64, Ordering::Relaxed);
+    let actual = format!("{:x}", Sha256::digest(&contents));
+    if actual != archive.sha256 {
+        return Err(DownloaderError::ArchiveChecksum {
+            archive_url: archive.url.clone(),
+            expected: archive.sha256.clone(),
+            actual,
+        });
+    }
+
+    let to_unpack_error = |error| DownloaderError::UnpackArchive {
+        archive_url: archive.url.clone(),
+        error,
+    };
+    let staging_path = utils::staging_path(repository_path, STAGING_EXTENSION);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).map_err(to_unpack_error)?;
+    }
+    tar::Archive::new(GzDecoder::new(contents.as_slice()))
+        .unpack(&staging_path)
+        .map_err(to_unpack_error)?;
+    let root = archive_root(&staging_path).map_err(to_unpack_error)?;
+
+    let to_git_error = |error| DownloaderError::CloneRepository {
+        repository_url: archive.url.clone(),
+        error,
+    };
+    commit_files(&root, &archive.url, network.use_git_config).map_err(to_git_error)?;
+    if let Some(parent) = repository_path.parent() {
+        fs::create_dir_all(parent).map_err(|error| DownloaderError::IoCreate {
+            path: parent.to_path_buf(),
+            error,
+        })?;
+    }
+    fs::rename(&root, repository_path).map_err(to_unpack_error)?;
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).map_err(to_unpack_error)?;
+    }
+    git::open(repository_path, network.use_git_config).map_err(to_git_error)
+}
+
+/// Reads a release archive from a local path or over HTTP(S).
+///
+/// # Arguments
+///
+/// - `archive_url` - The archive URL given in the metadata, used in errors.
+/// - `fetch_url` - The URL or local path to read the archive from.
+/// - `network` - The proxy and certificate authorities to use, how long
+///   the download may take, and how failed downloads are retried.
+///
+/// # Returns
+///
+/// The contents of the archive on success, or
+/// [`DownloaderError::DownloadArchive`] on failure.
+fn read_archive(
+    archive_url: &str,
+    fetch_url: &str,
+    network: &NetworkConfig,
+) -> Result<Vec<u8>, DownloaderError> {
+    let to_error = |reason: String| DownloaderError::DownloadArchive {
+        archive_url: archive_url.to_string(),
+        reason,
+    };
+    if utils::is_local_url(fetch_url) {
+        let path = fetch_url.strip_prefix("file://").unwrap_or(fetch_url);
+        return fs::read(path).map_err(|error| to_error(error.to_string()));
+    }
+
+    let agent = http_agent(network)?;
+    let attempt = || -> Result<Vec<u8>, ureq::Error> {
+        let mut contents = Vec::new();
+        agent
+            .get(fetch_url)
+            .call()?
+            .into_body()
+            .into_reader()
+            .read_to_end(&mut contents)?;
+        Ok(contents)
+    };
+    network
+        .retry(attempt, is_transient, |_, _, _| {})
+        .map_err(|error| to_error(error.to_string()))
+}
+
+/// Checks whether an archive download failed for a reason that may go away
+/// when it is retried, such as a dropped connection or a server error,
+/// rather than one that will not, such as a missing archive.
+///
+/// # Arguments
+///
+/// - `error` - The error of the failed attempt.
+///
+/// # Returns
+///
+/// True if the download should be retried.
+fn is_transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::StatusCode(status) => matches!(status, 408 | 429 | 500..=599),
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::HostNotFound
+        | ureq::Error::ConnectionFailed => true,
+        _ => false,
+    }
+}
+
+/// Creates the HTTP agent archives are downloaded with.
+///
+/// Without a configured proxy, the agent uses the proxy given by the
+/// environment, if any.
+///
+/// # Arguments
+///
+/// - `network` - The proxy and certificate authorities to use, and how long
+///   a download may take.
+///
+/// # Returns
+///
+/// The [`ureq::Agent`] on success, or [`DownloaderError::CaBundle`] if the
+/// certificate authorities cannot be loaded, or
+/// [`DownloaderError::DownloadArchive`] if the proxy is invalid.
+fn http_agent(network: &NetworkConfig) -> Result<ureq::Agent, DownloaderError> {
+    let mut config = ureq::Agent::config_builder().timeout_global(network.clone_timeout());
+    if let Some(proxy) = &network.proxy {
+        let proxy = ureq::Proxy::new(proxy).map_err(|error| DownloaderError::DownloadArchive {
+            archive_url: proxy.clone(),
+            reason: format!("invalid proxy: {error}"),
+        })?;
+        config = config.proxy(Some(proxy));
+    }
+    if let Some(ca_bundle) = &network.ca_bundle {
+        let to_error = |reason: String| DownloaderError::CaBundle {
+            path: ca_bundle.clone(),
+            reason,
+        };
+        let pem = fs::read(ca_bundle).map_err(|error| to_error(error.to_string()))?;
+        let certificates = ureq::tls::parse_pem(&pem)
+            .filter_map(|item| match item {
+                Ok(PemItem::Certificate(certificate)) => Some(Ok(certificate)),
+                Ok(_) => None,
+                Err(error) => Some(Err(to_error(error.to_string()))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let tls_config = TlsConfig::builder()
+            .root_certs(RootCerts::new_with_certs(&certificates))
+            .build();
+        config = config.tls_config(tls_config);
+    }
+    Ok(config.build().new_agent())
+}
+
+/// Finds the directory whose contents become the root of the repository.
+///
+/// # Arguments
+///
+/// - `directory` - The directory the archive was unpacked into.
+///
+/// # Returns
+///
+/// The single directory the archive holds, if it holds nothing else, or
+/// `directory` itself otherwise.
+fn archive_root(directory: &Path) -> io::Result<PathBuf> {
+    let entries = fs::read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+    match entries.as_slice() {
+        [entry] if entry.file_type()?.is_dir() => Ok(entry.path()),
+        _ => Ok(directory.to_path_buf()),
+    }
+}
+
+/// Commits every file in a directory to a new repository in it.
+///
+/// The commit has a fixed author and time, so its ID only depends on the
+/// archive's files and URL, and the lockfile is reproducible.
+///
+/// # Arguments
+///
+/// - `directory` - The unpacked archive.
+/// - `archive_url` - The archive URL, used as the `origin` remote.
+/// - `use_git_config` - Whether to commit with the user's and system's git
+///   configuration.
+///
+/// # Returns
+///
+/// The ID of the commit on success, or a [`git2::Error`] on failure.
+fn commit_files(
+    directory: &Path,
+    archive_url: &str,
+    use_git_config: bool,
+) -> Result<Oid, git2::Error> {
+    let repository = git::init(directory, use_git_config)?;
+    repository.remote("origin", archive_url)?;
+
+    // Add files the archive's own `.gitignore` ignores too, such as
+    // generated sources shipped in release archives.
+    let mut index = repository.index()?;
+    index.add_all(["*"], IndexAddOption::FORCE, None)?;
+    index.write()?;
+    let tree = repository.find_tree(index.write_tree()?)?;
+
+    let signature = Signature::new(ARCHIVE_AUTHOR, ARCHIVE_EMAIL, &Time::new(0, 0))?;
+    let commit = repository.commit(
+        Some(ARCHIVE_REFERENCE),
+        &signature,
+        &signature,
+        &format!("Unpack {archive_url}"),
+        &tree,
+        &[],
+    )?;
+    repository.set_head_detached(commit)?;
+    Ok(commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Write, net::TcpListener, thread};
+
+    use flate2::{Compression, write::GzEncoder};
+    use tempfile::TempDir;
+
+    use crate::corpus::downloader;
+
+    /// Creates a `.tar.gz` archive holding the given files.
+    fn create_archive(path: &Path, files: &[(&str, &str)]) -> Archive {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (file, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, file, contents.as_bytes())
+                .unwrap();
+        }
+        let contents = builder.into_inner().unwrap().finish().unwrap();
+        fs::write(path, &contents).unwrap();
+        Archive {
+            url: path.to_str().unwrap().to_string(),
+            sha256: format!("{:x}", Sha256::digest(&contents)),
+        }
+    }
+
+    /// Tests that an archive's single directory becomes the root of the
+    /// repository, that ignored files are committed, and that the commit
+    /// only depends on the archive.
+    #[test]
+    fn test_unpack_archive() {
+        let directory = TempDir::new().unwrap();
+        let archive = create_archive(
+            &directory.path().join("coreutils-9.5.tar.gz"),
+            &[
+                ("coreutils-9.5/.gitignore", "*.c\n"),
+                ("coreutils-9.5/src/ls.c", "int main() {}\n"),
+            ],
+        );
+
+        let mut commits = Vec::new();
+        for clone in ["first", "second"] {
+            let repository_path = directory.path().join(clone).join("coreutils");
+            let repository = unpack_archive(
+                &repository_path,
+                &archive,
+                &archive.url,
+                &NetworkConfig::default(),
+                &AtomicU64::new(0),
+            )
+            .unwrap();
+            assert!(repository_path.join("src/ls.c").is_file());
+            assert!(!utils::staging_path(&repository_path, STAGING_EXTENSION).exists());
+            assert!(downloader::verify_origin(&repository, &repository_path, &archive.url).is_ok());
+            commits.push(downloader::find_revision(&repository, None).unwrap().id());
+        }
+        assert_eq!(commits[0], commits[1]);
+    }
+
+    /// Tests that an archive download is retried after a server error, but
+    /// not after the archive is not found.
+    #[test]
+    fn test_read_archive_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ls.tar.gz", listener.local_addr().unwrap());
+        // Serves the archive after the 404 too, which a retry would receive.
+        thread::spawn(move || {
+            for status in [
+                "503 Service Unavailable",
+                "200 OK",
+                "404 Not Found",
+                "200 OK",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: 7\r\nConnection: close\r\n\r\narchive"
+                )
+                .unwrap();
+            }
+        });
+        let network = NetworkConfig {
+            retries: 2,
+            retry_delay_ms: 0,
+            ..NetworkConfig::default()
+        };
+
+        assert_eq!(read_archive(&url, &url, &network).unwrap(), b"archive");
+        let result = read_archive(&url, &url, &network);
+        assert!(matches!(
+            result,
+            Err(DownloaderError::DownloadArchive { .. })
+        ));
+    }
+
+    /// Tests that an archive with the wrong checksum is not unpacked.
+    #[test]
+    fn test_unpack_archive_checksum() {
+        let directory = TempDir::new().unwrap();
+        let mut archive = create_archive(
+            &directory.path().join("ls.tar.gz"),
+            &[("src/ls.c", "int main() {}\n")],
+        );
+        archive.sha256 = "0".repeat(64);
+
+        let repository_path = directory.path().join("ls");
+        let result = unpack_archive(
+            &repository_path,
+            &archive,
+            &archive.url,
+            &NetworkConfig::default(),
+            &AtomicU64::new(0),
+        );
+        assert!(matches!(
+            result,
+            Err(DownloaderError::ArchiveChecksum { .. })
+        ));
+        assert!(!repository_path.exists());
+    }
+}
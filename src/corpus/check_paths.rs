@@ -0,0 +1,292 @@
+//! # Source Path Checking
+//!
+//! The main entry point is [`check_paths`], which checks that every
+//! `source_paths` entry exists in the cached clone of its repository, at the
+//! revision the metadata asks for, and suggests close matches for those
+//! that do not.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    path::{Path, PathBuf},
+};
+
+use git2::{Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+
+use crate::corpus::{
+    downloader,
+    errors::DownloaderError,
+    patterns::{Include, SourcePatterns},
+    schema::{Language, Program, ProgramPair},
+};
+
+/// The most suggestions given for a missing source path.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The lowest similarity, between 0 and 1, of the file name of a path in the
+/// repository to that of a missing source path for it to be suggested.
+const MIN_SIMILARITY: f64 = 0.85;
+
+/// The result of checking the source paths of one program.
+#[derive(Debug)]
+pub struct ProgramCheck {
+    /// The name of the program pair.
+    pub program_name: String,
+    /// Whether the C or the Rust program was checked.
+    pub language: Language,
+    /// The source paths missing from the repository, or the error that
+    /// prevented checking them.
+    pub missing_paths: Result<Vec<MissingPath>, DownloaderError>,
+}
+
+/// A `source_paths` entry that does not exist in its repository, or a glob
+/// pattern that matches nothing in it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MissingPath {
+    /// The missing source path, as written in the metadata.
+    pub source_path: String,
+    /// Paths in the repository that the source path may have been renamed or
+    /// moved to, most likely first.  Empty for glob patterns.
+    pub suggestions: Vec<String>,
+}
+
+/// Checks the source paths of the given program pairs against the cached
+/// clones of their repositories.
+///
+/// Nothing is fetched, so the repositories must have been downloaded first.
+///
+/// # Arguments
+///
+/// - `pairs` - The program pairs to check.
+/// - `cache_directory` - The directory the repositories were cloned into.
+///
+/// # Returns
+///
+/// One [`ProgramCheck`] per program, C first, in the order of `pairs`.
+pub fn check_paths(pairs: &[ProgramPair], cache_directory: &Path) -> Vec<ProgramCheck> {
+    // The paths in each repository tree, only listed when a source path in
+    // it is missing.
+    let mut repository_trees = HashMap::new();
+
+    pairs
+        .iter()
+        .flat_map(|pair| [&pair.c_program, &pair.rust_program].map(|program| (pair, program)))
+        .map(|(pair, program)| ProgramCheck {
+            program_name: pair.program_name.clone(),
+            language: program.language.clone(),
+            missing_paths: check_program(program, cache_directory, &mut repository_trees),
+        })
+        .collect()
+}
+
+/// Checks the source paths of a single program.
+///
+/// # Arguments
+///
+/// - `program` - The program to check.
+/// - `cache_directory` - The directory the repositories were cloned into.
+/// - `repository_trees` - The paths in each repository tree listed so far;
+///   updated if this program has missing source paths.
+///
+/// # Returns
+///
+/// The missing source paths on success, or a [`DownloaderError`] if the
+/// repository or revision has not been downloaded.
+fn check_program(
+    program: &Program,
+    cache_directory: &Path,
+    repository_trees: &mut HashMap<(PathBuf, Oid), Vec<String>>,
+) -> Result<Vec<MissingPath>, DownloaderError> {
+    let repository_url = program.source_url();
+    let repository_path = downloader::repository_path(cache_directory, program)?;
+    let repository =
+        Repository::open(&repository_path).map_err(|_| DownloaderError::NotCloned {
+            repository_url: repository_url.to_string(),
+            path: repository_path.clone(),
+        })?;
+    downloader::verify_origin(&repository, &repository_path, repository_url)?;
+
+    let missing_revision = |error| DownloaderError::MissingRevision {
+        repository_url: repository_url.to_string(),
+        revision: downloader::describe_revision(program.revision.as_ref()),
+        error,
+    };
+    let commit = downloader::find_revision(&repository, program.revision.as_ref())
+        .map_err(missing_revision)?;
+    let tree = commit.tree().map_err(missing_revision)?;
+
+    let patterns = SourcePatterns::new(&program.source_paths)?;
+    let tree_key = (repository_path, commit.id());
+    let mut missing_paths = Vec::new();
+    for include in patterns.includes() {
+        match include {
+            Include::Path(source_path) => {
+                if tree
+                    .get_path(Path::new(source_path.trim_end_matches('/')))
+                    .is_err()
+                {
+                    let tree_paths =
+                        tree_paths(repository_trees, &tree_key, &tree).map_err(missing_revision)?;
+                    missing_paths.push(MissingPath {
+                        source_path: source_path.clone(),
+                        suggestions: suggest(source_path, tree_paths),
+                    });
+                }
+            }
+            Include::Glob {
+                pattern, matcher, ..
+            } => {
+                let tree_paths =
+                    tree_paths(repository_trees, &tree_key, &tree).map_err(missing_revision)?;
+                if !tree_paths.iter().any(|path| matcher.is_match(path)) {
+                    missing_paths.push(MissingPath {
+                        source_path: pattern.clone(),
+                        suggestions: Vec::new(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(missing_paths)
+}
+
+/// Returns the paths in a repository tree, listing them on first use.
+///
+/// # Arguments
+///
+/// - `repository_trees` - The paths in each repository tree listed so far.
+/// - `key` - The clone and commit of the tree.
+/// - `tree` - The tree of the commit.
+///
+/// # Returns
+///
+/// The paths in the tree on success, or a [`git2::Error`] on failure.
+fn tree_paths<'a>(
+    repository_trees: &'a mut HashMap<(PathBuf, Oid), Vec<String>>,
+    key: &(PathBuf, Oid),
+    tree: &Tree,
+) -> Result<&'a Vec<String>, git2::Error> {
+    match repository_trees.entry(key.clone()) {
+        Entry::Occupied(entry) => Ok(entry.into_mut()),
+        Entry::Vacant(entry) => Ok(entry.insert(list_tree(tree)?)),
+    }
+}
+
+/// Lists every file and directory in a git tree.
+///
+/// # Arguments
+///
+/// - `tree` - The tree of a commit.
+///
+/// # Returns
+///
+/// The paths, relative to the root of the tree, on success, or a
+/// [`git2::Error`] on failure.
+fn list_tree(tree: &Tree) -> Result<Vec<String>, git2::Error> {
+    let mut paths = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if let Ok(name) = entry.name() {
+            paths.push(format!("{root}{name}"));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(paths)
+}
+
+/// Suggests paths that a missing source path may have been renamed or moved
+/// to.
+///
+/// A path is suggested if its file name is the same as, or similar to, that
+/// of the source path.  Names are compared with the Jaro-Winkler similarity,
+/// which favours names sharing a prefix, and ties are broken by the
+/// similarity of the whole path.
+///
+/// # Arguments
+///
+/// - `source_path` - The missing source path.
+/// - `paths` - Every path in the repository.
+///
+/// # Returns
+///
+/// At most [`MAX_SUGGESTIONS`] paths, most likely first.
+fn suggest(source_path: &str, paths: &[String]) -> Vec<String> {
+    let source_path = source_path.trim_end_matches('/');
+    let source_name = file_name(source_path);
+
+    let mut scored: Vec<(f64, &String)> = paths
+        .iter()
+        .filter_map(|path| {
+            let similarity = strsim::jaro_winkler(source_name, file_name(path));
+            (similarity >= MIN_SIMILARITY)
+                .then(|| (similarity + strsim::jaro_winkler(source_path, path), path))
+        })
+        .collect();
+    scored.sort_by(|(a_score, a_path), (b_score, b_path)| {
+        b_score.total_cmp(a_score).then_with(|| a_path.cmp(b_path))
+    });
+
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Returns the last component of a `/`-separated path.
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use git2::Signature;
+    use tempfile::TempDir;
+
+    /// Tests that a moved file is suggested before a similarly named one,
+    /// and that unrelated paths are not suggested.
+    #[test]
+    fn test_suggest() {
+        let paths = [
+            "src".to_string(),
+            "src/cat.c".to_string(),
+            "src/cut.c".to_string(),
+            "src/uu/cat/cat.rs".to_string(),
+            "README.md".to_string(),
+        ];
+        assert_eq!(suggest("lib/cut.c", &paths), ["src/cut.c", "src/cat.c"]);
+        assert_eq!(
+            suggest("src/cat.rs", &paths),
+            ["src/uu/cat/cat.rs", "src/cat.c"]
+        );
+        assert_eq!(suggest("tests/unrelated.py", &paths), Vec::<String>::new());
+    }
+
+    /// Tests that every file and directory in a commit is listed, and that
+    /// files moved into a new directory are suggested.
+    #[test]
+    fn test_list_tree() {
+        let directory = TempDir::new().unwrap();
+        let repository = Repository::init(directory.path()).unwrap();
+        for file in ["src/ls.c", "src/ls/dired.c"] {
+            let path = directory.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let mut index = repository.index().unwrap();
+        index
+            .add_all(["src"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let commit = repository
+            .commit(None, &signature, &signature, "test", &tree, &[])
+            .unwrap();
+
+        let paths = list_tree(&repository.find_commit(commit).unwrap().tree().unwrap()).unwrap();
+        assert_eq!(paths, ["src", "src/ls.c", "src/ls", "src/ls/dired.c"]);
+        assert_eq!(suggest("src/dired.c", &paths), ["src/ls/dired.c"]);
+    }
+}
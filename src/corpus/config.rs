@@ -0,0 +1,477 @@
+//! # Configuration
+//!
+//! This module reads the optional `corpus.config.json` file, which holds
+//! settings that depend on the machine running the downloader rather than
+//! on the corpus itself.  The metadata always names the canonical upstream
+//! repositories; the configuration decides where they are fetched from, and
+//! where the corpus is read from and written to.
+//!
+//! The configuration file is read from the root directory, which is the
+//! working directory unless `--root` or `CORPUS_ROOT` is given.  Relative
+//! directories in it are resolved against the root.
+//!
+//! # Example
+//!
+//! ```json
+//! {
+//!   "url_rewrites": [
+//!     { "from": "https://github.com/", "to": "/srv/mirrors/github/" }
+//!   ],
+//!   "cache_directory": "/var/cache/repository_clones",
+//!   "network": {
+//!     "retries": 5,
+//!     "proxy": "http://proxy.example.com:3128",
+//!     "credentials": [
+//!       {
+//!         "url_prefix": "https://git.example.com/",
+//!         "username": "corpus",
+//!         "password_env": "CORPUS_GIT_TOKEN"
+//!       }
+//!     ]
+//!   }
+//! }
+//! ```
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    corpus::errors::DownloaderError,
+    paths::{
+        CONFIG_FILE, CORPUS_LOCKFILE, DEMO_METADATA_DIRECTORY, INDIVIDUAL_METADATA_DIRECTORY,
+        PROGRAM_PAIRS_DIRECTORY, PROJECT_METADATA_DIRECTORY, REPOSITORY_CLONES_DIRECTORY,
+    },
+};
+
+/// The downloader's configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Rewrites applied to repository URLs before they are fetched.
+    pub url_rewrites: Vec<UrlRewrite>,
+    /// The directories containing the metadata files of the full corpus,
+    /// instead of `metadata/project/` and `metadata/individual/`.
+    pub metadata_directories: Option<Vec<PathBuf>>,
+    /// The directory program pairs are downloaded into, instead of
+    /// `program_pairs/`.
+    pub output_directory: Option<PathBuf>,
+    /// The directory repositories are cloned into, instead of
+    /// `repository_clones/`.
+    pub cache_directory: Option<PathBuf>,
+    /// How repositories are fetched over the network.
+    pub network: NetworkConfig,
+}
+
+/// How repositories are fetched over the network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// How many times a clone, fetch or archive download that fails with a
+    /// network error is retried.
+    pub retries: u32,
+    /// How long to wait before the first retry, in milliseconds.  The delay
+    /// doubles with every further retry, up to a minute.
+    pub retry_delay_ms: u64,
+    /// How long one attempt at cloning or fetching a repository may take, in
+    /// seconds, before it is abandoned.  Zero never abandons an attempt.
+    /// Git only checks it as data arrives, so a connection that stalls is
+    /// left to libgit2's own timeouts.
+    pub clone_timeout_seconds: u64,
+    /// The proxy to connect through, such as `http://proxy.example.com:3128`.
+    /// Without one, the `https_proxy` and `http_proxy` environment variables
+    /// are used, as is git's `http.proxy` if `use_git_config` is set.
+    pub proxy: Option<String>,
+    /// A PEM file of the certificate authorities to trust instead of the
+    /// system's, for mirrors with private certificates.
+    pub ca_bundle: Option<PathBuf>,
+    /// The credentials of repositories that need them.
+    pub credentials: Vec<Credential>,
+    /// Whether to read the user's and system's git configuration, for its
+    /// `insteadOf` rewrites, proxy and credential helpers.  It is ignored by
+    /// default, so settings such as `core.autocrlf` cannot change the corpus.
+    pub use_git_config: bool,
+}
+
+/// The user name and password of the repositories under a URL prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Credential {
+    /// The prefix of the fetch URLs the credential is for, such as
+    /// `https://git.example.com/`.
+    pub url_prefix: String,
+    /// The user name.
+    pub username: String,
+    /// The environment variable holding the password or access token, so it
+    /// is never written to the configuration file.
+    pub password_env: String,
+}
+
+/// Where the corpus is read from and written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directories {
+    /// The directory holding the configuration file and the lockfile.
+    pub root: PathBuf,
+    /// The directories containing the metadata files of the full corpus.
+    pub metadata: Vec<PathBuf>,
+    /// The directory containing the metadata files of the demo.
+    pub demo_metadata: PathBuf,
+    /// The directory program pairs are downloaded into.
+    pub output: PathBuf,
+    /// The directory repositories are cloned into.
+    pub cache: PathBuf,
+}
+
+/// Replaces a prefix of repository URLs, to fetch them from a mirror.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlRewrite {
+    /// The prefix to replace, such as `https://github.com/`.
+    pub from: String,
+    /// The replacement, such as `/srv/mirrors/github/` or
+    /// `https://git.example.com/github/`.
+    pub to: String,
+}
+
+impl Config {
+    /// Reads the configuration from disk.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The configuration file to read.
+    ///
+    /// # Returns
+    ///
+    /// The [`Config`] on success, the default configuration if the file does
+    /// not exist, or a [`DownloaderError`] if it cannot be read or parsed.
+    pub fn read(path: &Path) -> Result<Self, DownloaderError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                return Err(DownloaderError::IoRead {
+                    path: path.to_path_buf(),
+                    error,
+                });
+            }
+        };
+        serde_json::from_str(&contents).map_err(|error| DownloaderError::DeserializeConfig {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Returns the directories the configuration selects.
+    ///
+    /// # Arguments
+    ///
+    /// - `root` - The root directory, which relative directories in the
+    ///   configuration are resolved against.
+    ///
+    /// # Returns
+    ///
+    /// The default [`Directories`] under `root`, with those the
+    /// configuration sets replaced.
+    pub fn directories(&self, root: &Path) -> Directories {
+        let mut directories = Directories::new(root);
+        if let Some(metadata) = &self.metadata_directories {
+            directories.metadata = metadata.iter().map(|path| root.join(path)).collect();
+        }
+        if let Some(output) = &self.output_directory {
+            directories.output = root.join(output);
+        }
+        if let Some(cache) = &self.cache_directory {
+            directories.cache = root.join(cache);
+        }
+        directories
+    }
+
+    /// Returns the URL a repository is fetched from.
+    ///
+    /// Like git's `url.<base>.insteadOf`, the rewrite with the longest
+    /// matching prefix is applied.
+    ///
+    /// # Arguments
+    ///
+    /// - `repository_url` - The repository URL given in the metadata.
+    ///
+    /// # Returns
+    ///
+    /// The rewritten URL, or `repository_url` itself if no rewrite applies.
+    pub fn fetch_url(&self, repository_url: &str) -> String {
+        self.url_rewrites
+            .iter()
+            .filter(|rewrite| repository_url.starts_with(&rewrite.from))
+            .max_by_key(|rewrite| rewrite.from.len())
+            .map_or_else(
+                || repository_url.to_string(),
+                |rewrite| format!("{}{}", rewrite.to, &repository_url[rewrite.from.len()..]),
+            )
+    }
+}
+
+impl NetworkConfig {
+    /// The longest delay between two retries.
+    const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+    /// Returns how long to wait before a retry.
+    ///
+    /// # Arguments
+    ///
+    /// - `retry` - The number of retries made so far.
+    ///
+    /// # Returns
+    ///
+    /// The configured delay doubled `retry` times, capped at a minute.
+    pub fn retry_delay(&self, retry: u32) -> Duration {
+        let factor = 1u64.checked_shl(retry).unwrap_or(u64::MAX);
+        Duration::from_millis(self.retry_delay_ms.saturating_mul(factor)).min(Self::MAX_RETRY_DELAY)
+    }
+
+    /// Makes attempts at a transfer until one succeeds, waiting with
+    /// exponential backoff before each retry.
+    ///
+    /// # Arguments
+    ///
+    /// - `attempt` - Makes one attempt.
+    /// - `is_transient` - Whether an attempt failed for a reason that may go
+    ///   away when it is retried.
+    /// - `on_retry` - Called with the error, the delay and the number of the
+    ///   retry before waiting for it.
+    ///
+    /// # Returns
+    ///
+    /// The result of the first attempt that succeeds, or the error of the
+    /// last attempt once the retries run out or an error is not transient.
+    pub fn retry<T, E>(
+        &self,
+        mut attempt: impl FnMut() -> Result<T, E>,
+        is_transient: impl Fn(&E) -> bool,
+        mut on_retry: impl FnMut(&E, Duration, u32),
+    ) -> Result<T, E> {
+        let mut retry = 0;
+        loop {
+            let error = match attempt() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if retry >= self.retries || !is_transient(&error) {
+                return Err(error);
+            }
+            let delay = self.retry_delay(retry);
+            retry += 1;
+            on_retry(&error, delay, retry);
+            thread::sleep(delay);
+        }
+    }
+
+    /// Returns how long one attempt at cloning or fetching may take, or
+    /// `None` if there is no limit.
+    pub fn clone_timeout(&self) -> Option<Duration> {
+        (self.clone_timeout_seconds > 0).then(|| Duration::from_secs(self.clone_timeout_seconds))
+    }
+
+    /// Returns the credential of a repository.
+    ///
+    /// Like [`Config::fetch_url`], the credential with the longest matching
+    /// prefix is used.
+    ///
+    /// # Arguments
+    ///
+    /// - `fetch_url` - The URL the repository is fetched from.
+    ///
+    /// # Returns
+    ///
+    /// The [`Credential`], or `None` if none matches.
+    pub fn credential(&self, fetch_url: &str) -> Option<&Credential> {
+        self.credentials
+            .iter()
+            .filter(|credential| fetch_url.starts_with(&credential.url_prefix))
+            .max_by_key(|credential| credential.url_prefix.len())
+    }
+}
+
+impl Credential {
+    /// Reads the password from its environment variable.
+    ///
+    /// # Returns
+    ///
+    /// The password, or `None` if the variable is not set.
+    pub fn password(&self) -> Option<String> {
+        env::var(&self.password_env).ok()
+    }
+}
+
+impl Default for NetworkConfig {
+    /// Retries three times, starting after a second, and abandons attempts
+    /// after half an hour.
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            retry_delay_ms: 1000,
+            clone_timeout_seconds: 1800,
+            proxy: None,
+            ca_bundle: None,
+            credentials: Vec::new(),
+            use_git_config: false,
+        }
+    }
+}
+
+impl Directories {
+    /// Returns the default directories under a root directory.
+    ///
+    /// # Arguments
+    ///
+    /// - `root` - The root directory.
+    ///
+    /// # Returns
+    ///
+    /// The [`Directories`] laid out as in this repository.
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            metadata: vec![
+                root.join(PROJECT_METADATA_DIRECTORY),
+                root.join(INDIVIDUAL_METADATA_DIRECTORY),
+            ],
+            demo_metadata: root.join(DEMO_METADATA_DIRECTORY),
+            output: root.join(PROGRAM_PAIRS_DIRECTORY),
+            cache: root.join(REPOSITORY_CLONES_DIRECTORY),
+        }
+    }
+
+    /// Returns the metadata directories of the full corpus or of the demo.
+    ///
+    /// # Arguments
+    ///
+    /// - `demo` - Whether to return the demo metadata directory.
+    pub fn metadata(&self, demo: bool) -> Vec<PathBuf> {
+        if demo {
+            vec![self.demo_metadata.clone()]
+        } else {
+            self.metadata.clone()
+        }
+    }
+
+    /// Returns the path of the configuration file.
+    pub fn config_file(&self) -> PathBuf {
+        self.root.join(CONFIG_FILE)
+    }
+
+    /// Returns the path of the corpus lockfile.
+    pub fn lockfile(&self) -> PathBuf {
+        self.root.join(CORPUS_LOCKFILE)
+    }
+}
+
+impl Default for Directories {
+    /// Returns the default directories, relative to the working directory.
+    fn default() -> Self {
+        Self::new(Path::new(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the longest matching prefix is rewritten, and that other
+    /// URLs are left alone.
+    #[test]
+    fn test_fetch_url() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "url_rewrites": [
+                    { "from": "https://github.com/", "to": "/srv/mirrors/github/" },
+                    { "from": "https://github.com/uutils/", "to": "file:///srv/uutils/" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "/srv/mirrors/github/coreutils/coreutils",
+            config.fetch_url("https://github.com/coreutils/coreutils")
+        );
+        assert_eq!(
+            "file:///srv/uutils/coreutils",
+            config.fetch_url("https://github.com/uutils/coreutils")
+        );
+        assert_eq!(
+            "https://gitlab.com/owner/repository",
+            config.fetch_url("https://gitlab.com/owner/repository")
+        );
+    }
+
+    /// Tests that configured directories replace the defaults, relative to
+    /// the root unless absolute.
+    #[test]
+    fn test_directories() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "metadata_directories": ["pairs"],
+                "cache_directory": "/var/cache/clones"
+            }"#,
+        )
+        .unwrap();
+
+        let directories = config.directories(Path::new("/srv/corpus"));
+        assert_eq!(directories.metadata, [Path::new("/srv/corpus/pairs")]);
+        assert_eq!(
+            directories.demo_metadata,
+            Path::new("/srv/corpus/metadata/demo")
+        );
+        assert_eq!(directories.output, Path::new("/srv/corpus/program_pairs"));
+        assert_eq!(directories.cache, Path::new("/var/cache/clones"));
+        assert_eq!(
+            directories.lockfile(),
+            Path::new("/srv/corpus/corpus.lock.json")
+        );
+    }
+
+    /// Tests that the retry delay doubles up to its cap, and that unset
+    /// network settings keep their defaults.
+    #[test]
+    fn test_network_config() {
+        let config: Config =
+            serde_json::from_str(r#"{ "network": { "retry_delay_ms": 500 } }"#).unwrap();
+        let network = &config.network;
+        assert_eq!(network.retries, NetworkConfig::default().retries);
+        assert_eq!(network.retry_delay(0), Duration::from_millis(500));
+        assert_eq!(network.retry_delay(2), Duration::from_secs(2));
+        assert_eq!(network.retry_delay(64), Duration::from_secs(60));
+        assert_eq!(network.clone_timeout(), Some(Duration::from_secs(1800)));
+    }
+
+    /// Tests that the credential with the longest matching prefix is used.
+    #[test]
+    fn test_credential() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "network": {
+                    "credentials": [
+                        { "url_prefix": "https://git.example.com/", "username": "a", "password_env": "A" },
+                        { "url_prefix": "https://git.example.com/private/", "username": "b", "password_env": "B" }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let username = |fetch_url| {
+            config
+                .network
+                .credential(fetch_url)
+                .map(|credential| credential.username.as_str())
+        };
+
+        assert_eq!(Some("b"), username("https://git.example.com/private/ls"));
+        assert_eq!(Some("a"), username("https://git.example.com/public/ls"));
+        assert_eq!(None, username("https://github.com/uutils/coreutils"));
+    }
+}
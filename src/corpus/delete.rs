@@ -2,22 +2,495 @@
 //!
 //! This module provides functionality for cleaning up downloaded program pairs
 //! and repository clones.
+//!
+//! Directories are only deleted if they hold the marker file this tool
+//! places in the output and cache directories it creates, so a misconfigured
+//! directory, such as a home directory given as `--output-dir`, is never
+//! removed by accident.
+
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs,
+    path::{Component, Path, PathBuf},
+};
 
-use std::{fs, io::Error, path::Path};
+use walkdir::WalkDir;
 
-use crate::paths::{PROGRAM_PAIRS_DIRECTORY, REPOSITORY_CLONES_DIRECTORY};
+use crate::{
+    corpus::{
+        archive, config::Directories, downloader, errors::DownloaderError, lockfile::Lockfile,
+        utils,
+    },
+    paths::DIRECTORY_MARKER_FILE,
+};
 
-/// Removes all downloaded program-pairs and repository clones.
+/// What to delete.
+#[derive(Debug, Default, Clone)]
+pub struct DeleteOptions {
+    /// Only delete the downloaded program pairs with these names.
+    pub pairs: Vec<String>,
+    /// Only delete the cached clones of the repositories with these URLs.
+    pub repositories: Vec<String>,
+    /// Only delete the output directory, keeping the clone cache.
+    pub outputs_only: bool,
+    /// Only delete the clone cache, keeping the output directory.
+    pub cache_only: bool,
+    /// List what would be deleted without deleting anything.
+    pub dry_run: bool,
+    /// Delete directories even if they lack the marker file.
+    pub force: bool,
+}
+
+/// A file or directory that was, or in a dry run would be, removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removal {
+    /// The removed path.
+    pub path: PathBuf,
+    /// The disk space it took up, in bytes.
+    pub bytes: u64,
+}
+
+/// Removes downloaded program pairs and repository clones.
 ///
-/// This deletes the directories specified by
-/// [`PROGRAM_PAIRS_DIRECTORY`] and [`REPOSITORY_CLONES_DIRECTORY`],
-/// along with all their contents, if they exist.
-pub fn delete() -> Result<(), Error> {
-    if Path::new(PROGRAM_PAIRS_DIRECTORY).exists() {
-        fs::remove_dir_all(PROGRAM_PAIRS_DIRECTORY)?;
-    };
-    if Path::new(REPOSITORY_CLONES_DIRECTORY).exists() {
-        fs::remove_dir_all(REPOSITORY_CLONES_DIRECTORY)?;
+/// Unless selected pairs or repositories are given, this deletes the output
+/// and cache directories, `program_pairs/` and `repository_clones/` by
+/// default, along with all their contents, if they exist.  Deleting the
+/// output directory also deletes the lockfile, and selected pairs are
+/// removed from it, so it keeps listing only downloaded pairs.
+///
+/// # Arguments
+///
+/// - `directories` - The output and cache directories.
+/// - `options` - What to delete.
+///
+/// # Returns
+///
+/// What was removed, or would be in a dry run, on success, or a
+/// [`DownloaderError`] on failure.  Nothing is removed if a directory lacks
+/// its marker file, unless forced, or if a selected pair or repository was
+/// not downloaded.
+pub fn delete(
+    directories: &Directories,
+    options: &DeleteOptions,
+) -> Result<Vec<Removal>, DownloaderError> {
+    let mut paths = Vec::new();
+    let delete_all = options.pairs.is_empty() && options.repositories.is_empty();
+    if delete_all {
+        if !options.cache_only {
+            paths.push(directories.output.clone());
+        }
+        if !options.outputs_only {
+            paths.push(directories.cache.clone());
+        }
+        paths.retain(|path| path.exists());
+        for path in &paths {
+            check_marked(path, options.force)?;
+        }
+    }
+
+    for program_name in &options.pairs {
+        // Only a plain name can select a directory inside the output
+        // directory.
+        let mut components = Path::new(program_name).components();
+        let pair_directory = match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => directories.output.join(program_name),
+            _ => PathBuf::new(),
+        };
+        if !pair_directory.is_dir() {
+            return Err(DownloaderError::UnknownPair {
+                program_name: program_name.clone(),
+            });
+        }
+        check_marked(&directories.output, options.force)?;
+        paths.push(pair_directory);
+    }
+
+    for repository_url in &options.repositories {
+        let repository_path = directories.cache.join(utils::cache_key(repository_url)?);
+        if !repository_path.is_dir() {
+            return Err(DownloaderError::NotCloned {
+                repository_url: repository_url.clone(),
+                path: repository_path,
+            });
+        }
+        check_marked(&directories.cache, options.force)?;
+        paths.push(repository_path);
+    }
+
+    // The lockfile is read before anything is removed, so a lockfile that
+    // cannot be updated leaves the pairs in place.
+    let lockfile_path = directories.lockfile();
+    let lockfile = if options.pairs.is_empty() || !lockfile_path.exists() {
+        None
+    } else {
+        Some(Lockfile::read(&lockfile_path)?)
     };
-    Ok(())
+
+    let mut removals = remove(paths, &directories.cache, options.dry_run)?;
+    if delete_all && !options.cache_only && lockfile_path.is_file() {
+        let bytes = fs::metadata(&lockfile_path).map_or(0, |metadata| metadata.len());
+        if !options.dry_run {
+            fs::remove_file(&lockfile_path).map_err(|error| DownloaderError::IoRemove {
+                path: lockfile_path.clone(),
+                error,
+            })?;
+        }
+        removals.push(Removal {
+            path: lockfile_path,
+            bytes,
+        });
+    } else if let Some(mut lockfile) = lockfile
+        && !options.dry_run
+    {
+        for program_name in &options.pairs {
+            lockfile.pairs.remove(program_name);
+        }
+        lockfile.write(&lockfile_path)?;
+    }
+    Ok(removals)
+}
+
+/// Removes the cached clones that no program pair in the metadata uses.
+///
+/// Clones left behind by interrupted downloads are removed too.  The
+/// metadata is the full corpus and the demo, and every metadata file must
+/// parse, so that no clone still in use is removed.
+///
+/// # Arguments
+///
+/// - `directories` - The metadata and cache directories.
+/// - `dry_run` - Whether to only list what would be removed.
+/// - `force` - Whether to remove clones even if the cache directory lacks
+///   its marker file.
+///
+/// # Returns
+///
+/// What was removed, or would be in a dry run, on success, or a
+/// [`DownloaderError`] on failure.
+pub fn gc(
+    directories: &Directories,
+    dry_run: bool,
+    force: bool,
+) -> Result<Vec<Removal>, DownloaderError> {
+    if !directories.cache.exists() {
+        return Ok(Vec::new());
+    }
+    check_marked(&directories.cache, force)?;
+
+    let mut metadata = directories.metadata(false);
+    metadata.extend(
+        directories
+            .metadata(true)
+            .into_iter()
+            .filter(|path| path.exists()),
+    );
+    let (pairs, metadata_files) = downloader::read_metadata(&metadata)?;
+    let failed = metadata_files
+        .iter()
+        .filter(|metadata_file| metadata_file.error.is_some())
+        .count();
+    if failed > 0 {
+        return Err(DownloaderError::IncompleteMetadata { failed });
+    }
+
+    let mut referenced = BTreeSet::new();
+    for pair in &pairs {
+        for program in [&pair.c_program, &pair.rust_program] {
+            referenced.insert(downloader::repository_path(&directories.cache, program)?);
+        }
+    }
+    let unreferenced = find_unreferenced_clones(&directories.cache, &referenced);
+    remove(unreferenced, &directories.cache, dry_run)
+}
+
+/// Finds the clones in the cache directory that are not referenced.
+///
+/// A clone is a directory holding a `.git` directory, or one a clone or
+/// archive is staged in before it is moved into the cache.
+///
+/// # Arguments
+///
+/// - `cache` - The cache directory.
+/// - `referenced` - The paths of the clones in use.
+///
+/// # Returns
+///
+/// The paths of the unreferenced clones, sorted.
+fn find_unreferenced_clones(cache: &Path, referenced: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut unreferenced = Vec::new();
+    let mut entries = WalkDir::new(cache)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = entries.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let is_staging = path.extension().is_some_and(|extension| {
+            [downloader::STAGING_EXTENSION, archive::STAGING_EXTENSION]
+                .map(OsStr::new)
+                .contains(&extension)
+        });
+        if !is_staging && !path.join(".git").is_dir() {
+            continue;
+        }
+        if is_staging || !referenced.contains(path) {
+            unreferenced.push(path.to_path_buf());
+        }
+        entries.skip_current_dir();
+    }
+    unreferenced
+}
+
+/// Checks that a directory holds the marker file of directories this tool
+/// created.
+///
+/// # Arguments
+///
+/// - `directory` - The output or cache directory.
+/// - `force` - Whether to skip the check.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the directory may be deleted, or
+/// [`DownloaderError::UnmarkedDirectory`] otherwise.
+fn check_marked(directory: &Path, force: bool) -> Result<(), DownloaderError> {
+    if force || utils::is_marked(directory) {
+        Ok(())
+    } else {
+        Err(DownloaderError::UnmarkedDirectory {
+            path: directory.to_path_buf(),
+            marker: DIRECTORY_MARKER_FILE.to_string(),
+        })
+    }
+}
+
+/// Removes directories, and the directories in the cache they leave empty.
+///
+/// # Arguments
+///
+/// - `paths` - The directories to remove.
+/// - `cache` - The cache directory, up to which empty parents of removed
+///   clones are removed.
+/// - `dry_run` - Whether to only measure the directories.
+///
+/// # Returns
+///
+/// Each directory with its size on success, or a [`DownloaderError`] on
+/// failure.
+fn remove(
+    paths: Vec<PathBuf>,
+    cache: &Path,
+    dry_run: bool,
+) -> Result<Vec<Removal>, DownloaderError> {
+    let mut removals = Vec::new();
+    for path in paths {
+        let bytes = directory_size(&path);
+        if !dry_run {
+            fs::remove_dir_all(&path).map_err(|error| DownloaderError::IoRemove {
+                path: path.clone(),
+                error,
+            })?;
+            let mut parent = path.parent();
+            while let Some(directory) = parent
+                && directory.starts_with(cache)
+                && directory != cache
+                && fs::remove_dir(directory).is_ok()
+            {
+                parent = directory.parent();
+            }
+        }
+        removals.push(Removal { path, bytes });
+    }
+    Ok(removals)
+}
+
+/// Returns the total size of the files in a directory and its
+/// subdirectories, without following symbolic links.
+///
+/// # Arguments
+///
+/// - `directory` - The directory to measure.
+///
+/// # Returns
+///
+/// The size in bytes.  Files that cannot be read are not counted.
+fn directory_size(directory: &Path) -> u64 {
+    WalkDir::new(directory)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::lockfile::{LockedProgram, LockedProgramPair};
+
+    use std::collections::BTreeMap;
+
+    use tempfile::TempDir;
+
+    /// Creates directories for a test, with the output and cache directories
+    /// marked if `marked` is set.
+    fn create_directories(root: &Path, marked: bool) -> Directories {
+        let directories = Directories::new(root);
+        for directory in [&directories.output, &directories.cache] {
+            fs::create_dir_all(directory).unwrap();
+            if marked {
+                utils::mark_directory(directory).unwrap();
+            }
+        }
+        fs::create_dir_all(directories.output.join("ls/c-program")).unwrap();
+        fs::write(directories.output.join("ls/c-program/ls.c"), "int main;").unwrap();
+        directories
+    }
+
+    /// Tests that unmarked directories are only deleted when forced.
+    #[test]
+    fn test_delete_unmarked() {
+        let root = TempDir::new().unwrap();
+        let directories = create_directories(root.path(), false);
+
+        let result = delete(&directories, &DeleteOptions::default());
+        assert!(matches!(
+            result,
+            Err(DownloaderError::UnmarkedDirectory { .. })
+        ));
+        assert!(directories.output.exists());
+
+        let options = DeleteOptions {
+            force: true,
+            ..DeleteOptions::default()
+        };
+        assert_eq!(delete(&directories, &options).unwrap().len(), 2);
+        assert!(!directories.output.exists());
+        assert!(!directories.cache.exists());
+    }
+
+    /// Tests that deleting the output directory also deletes the lockfile,
+    /// unless it is a dry run, and that deleting the cache keeps it.
+    #[test]
+    fn test_delete_outputs() {
+        let root = TempDir::new().unwrap();
+        let directories = create_directories(root.path(), true);
+        Lockfile::default().write(&directories.lockfile()).unwrap();
+
+        let mut options = DeleteOptions {
+            cache_only: true,
+            ..DeleteOptions::default()
+        };
+        delete(&directories, &options).unwrap();
+        assert!(!directories.cache.exists());
+        assert!(directories.lockfile().exists());
+
+        options = DeleteOptions {
+            outputs_only: true,
+            dry_run: true,
+            ..DeleteOptions::default()
+        };
+        let removals = delete(&directories, &options).unwrap();
+        assert_eq!(removals.len(), 2);
+        assert_eq!(removals[1].path, directories.lockfile());
+        assert!(directories.output.exists());
+        assert!(directories.lockfile().exists());
+
+        options.dry_run = false;
+        delete(&directories, &options).unwrap();
+        assert!(!directories.output.exists());
+        assert!(!directories.lockfile().exists());
+    }
+
+    /// Tests that a dry run measures a pair without removing it, that a
+    /// removed pair is also removed from the lockfile, and that only plain
+    /// pair names are accepted.
+    #[test]
+    fn test_delete_pair() {
+        let root = TempDir::new().unwrap();
+        let directories = create_directories(root.path(), true);
+        let locked_program = LockedProgram {
+            repository_url: "https://example.com/repository".to_string(),
+            commit: "0".repeat(40),
+            files: BTreeMap::new(),
+        };
+        let locked_pair = LockedProgramPair {
+            c_program: locked_program.clone(),
+            rust_program: locked_program,
+        };
+        let lockfile = Lockfile {
+            pairs: BTreeMap::from([
+                ("cat".to_string(), locked_pair.clone()),
+                ("ls".to_string(), locked_pair),
+            ]),
+        };
+        lockfile.write(&directories.lockfile()).unwrap();
+        let locked_pairs = || -> Vec<String> {
+            Lockfile::read(&directories.lockfile())
+                .unwrap()
+                .pairs
+                .into_keys()
+                .collect()
+        };
+
+        let mut options = DeleteOptions {
+            pairs: vec!["ls".to_string()],
+            dry_run: true,
+            ..DeleteOptions::default()
+        };
+        let removals = delete(&directories, &options).unwrap();
+        assert_eq!(
+            removals,
+            [Removal {
+                path: directories.output.join("ls"),
+                bytes: 9,
+            }]
+        );
+        assert!(directories.output.join("ls").exists());
+        assert_eq!(locked_pairs(), ["cat", "ls"]);
+
+        options.dry_run = false;
+        delete(&directories, &options).unwrap();
+        assert!(!directories.output.join("ls").exists());
+        assert!(utils::is_marked(&directories.output));
+        assert_eq!(locked_pairs(), ["cat"]);
+
+        options.pairs = vec!["../program_pairs".to_string()];
+        let result = delete(&directories, &options);
+        assert!(matches!(result, Err(DownloaderError::UnknownPair { .. })));
+    }
+
+    /// Tests that only clones, and staging directories, that are not
+    /// referenced are found.
+    #[test]
+    fn test_find_unreferenced_clones() {
+        let root = TempDir::new().unwrap();
+        let cache = root.path();
+        for clone in [
+            "github.com/uutils%2Fcoreutils",
+            "github.com/uutils%2Ffindutils",
+            "github.com/uutils%2Fsed.cloning",
+        ] {
+            fs::create_dir_all(cache.join(clone).join(".git")).unwrap();
+        }
+        fs::create_dir_all(cache.join("file/tmp%2Fls.tar.gz.unpacking/ls")).unwrap();
+        fs::create_dir_all(cache.join("github.com/uutils%2Fcoreutils/src/.git")).unwrap();
+
+        let referenced = BTreeSet::from([cache.join("github.com/uutils%2Fcoreutils")]);
+        assert_eq!(
+            find_unreferenced_clones(cache, &referenced),
+            [
+                cache.join("file/tmp%2Fls.tar.gz.unpacking"),
+                cache.join("github.com/uutils%2Ffindutils"),
+                cache.join("github.com/uutils%2Fsed.cloning"),
+            ]
+        );
+    }
 }
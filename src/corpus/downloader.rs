@@ -7,344 +7,1765 @@
 //! repository URLs provided in the metadata.
 
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
 };
 
-use git2::{build::RepoBuilder, opts, ConfigLevel, FetchOptions, RemoteCallbacks, Repository};
-use indicatif::{ProgressBar, ProgressStyle};
+use clap::ValueEnum;
+use git2::{
+    Commit, ErrorClass, ErrorCode, FetchOptions, Oid, RemoteCallbacks, Repository,
+    build::CheckoutBuilder,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::{ThreadPoolBuilder, prelude::*};
 
 use crate::{
     corpus::{
-        self,
+        self, archive,
+        config::{Config, Directories, NetworkConfig},
         errors::DownloaderError,
-        schema::{Language, Metadata, ProgramPair},
-        utils,
-    },
-    paths::{
-        DEMO_METADATA_DIRECTORY, INDIVIDUAL_METADATA_DIRECTORY, PROGRAM_PAIRS_DIRECTORY,
-        PROJECT_METADATA_DIRECTORY, REPOSITORY_CLONES_DIRECTORY,
+        filter::PairFilter,
+        git,
+        lockfile::{self, LockedProgramPair, Lockfile},
+        manifest::{FileManifest, PairManifest, ProgramManifest},
+        patterns::{self, Include, SourcePatterns},
+        report::{
+            DownloadReport, ErrorReport, MetadataFileReport, PairReport, PairStatus, ProgramReport,
+        },
+        schema::{Archive, Program, ProgramPair, Revision},
+        sparse, utils,
     },
+    paths::{CRATE_MANIFEST_FILE, PAIR_MANIFEST_FILE},
 };
 
-/// Reads all metadata files in `metadata/` and downloads all program pairs.
+/// Refspec used to fetch the default branch of a repository that was not
+/// cloned with it.
+const DEFAULT_BRANCH_REFSPEC: &str = "+HEAD:refs/remotes/origin/HEAD";
+
+/// The extension of the directory a repository is cloned into before it is
+/// moved into the clone cache.
+pub(crate) const STAGING_EXTENSION: &str = "cloning";
+
+/// The extension of the directory a changed pair is copied into, when
+/// updating, before it replaces the pair's directory.
+const PAIR_STAGING_EXTENSION: &str = "updating";
+
+/// Options that control how program pairs are downloaded.
+#[derive(Debug, Default, Clone)]
+pub struct DownloadOptions {
+    /// Reproduce the corpus recorded in the lockfile exactly, failing if any
+    /// commit or file hash differs.
+    pub locked: bool,
+    /// How files are arranged under `c-program/` and `rust-program/`.
+    pub layout: Layout,
+    /// The number of repositories cloned, or programs copied, at the same
+    /// time.  Zero uses one job per CPU.
+    pub jobs: usize,
+    /// Build the corpus from the clones in the cache directory only, never
+    /// fetching.  Pairs that cannot be built from them are reported as failed.
+    pub offline: bool,
+    /// Fetch repositories without their file contents, and check out only
+    /// the `source_paths` of the programs copied from them.  Repositories
+    /// whose server does not support this are cloned in full.
+    pub sparse: bool,
+    /// Where the configuration and lockfile are read from, and where
+    /// repositories are cloned and program pairs downloaded to.
+    pub directories: Directories,
+}
+
+/// How the downloaded files of a program are arranged under its
+/// `c-program/` or `rust-program/` directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Copy every file directly into the program directory.  Files found in
+    /// a listed directory have their path within that directory joined with
+    /// `-`, so `module/file.rs` becomes `module-file.rs`.
+    #[default]
+    Flat,
+    /// Copy every file to its path relative to the repository root, so
+    /// `src/uu/ls/src/ls.rs` stays `src/uu/ls/src/ls.rs`.
+    Mirror,
+}
+
+/// A repository in the clone cache, together with every revision of it that
+/// the program pairs being downloaded need.
+struct RepositoryJob {
+    /// The repository URL given in the metadata, or the archive URL if the
+    /// repository is unpacked from an archive.
+    repository_url: String,
+    /// The URL the repository is fetched from, after applying the
+    /// configured rewrites.
+    fetch_url: String,
+    /// The revisions to resolve, where `None` is the default branch.
+    revisions: BTreeSet<Option<Revision>>,
+    /// The release archive to unpack instead of cloning the repository.
+    archive: Option<Archive>,
+    /// Whether to fetch the repository without its file contents.
+    sparse: bool,
+}
+
+/// The commit, or the error, each revision of a repository resolved to.
+type ResolvedRevisions = Vec<(Option<Revision>, Result<Oid, DownloaderError>)>;
+
+/// One C or Rust program to copy out of its repository's clone.
+struct CopyJob {
+    /// The program, with its revision replaced by the locked commit in
+    /// locked mode.
+    program: Program,
+    /// Where the program's repository is cloned.
+    repository_path: PathBuf,
+    /// The `c-program/` or `rust-program/` directory to copy into.
+    program_directory: PathBuf,
+}
+
+/// Reads the given metadata and downloads the program pairs selected by a
+/// filter.
 ///
-/// A progress bar tracks the number of metadata files processed.
+/// Only the repositories the selected pairs use are cloned.  Afterwards the
+/// resolved commits and file hashes are written to `corpus.lock.json`;
+/// when only some of the corpus is downloaded, they are merged into the
+/// existing lockfile.  In locked mode the lockfile is read instead, and every
+/// selected pair must match it.
 ///
 /// # Arguments
 ///
-/// - `demo` - True if a demo is being run, in which case the function downloads
-///            only the program pairs specified `metadata/demo/`.
+/// - `metadata` - The metadata files, or directories of them, to read, such
+///   as those returned by [`metadata_directories`].
+/// - `filter` - Which of the program pairs to download.  The default filter
+///   selects every pair.
+/// - `options` - Options that control the download.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success, or a [`DownloaderError`] if any step fails.
-pub fn download_program_pairs(demo: bool) -> Result<(), DownloaderError> {
-    // Temporarily override the user's global and system Git configuration.
-    // This is to ensure reliability when using the clone operation to
-    // download repositories.
-    unsafe {
-        opts::set_search_path(ConfigLevel::Global, "/dev/null").unwrap();
-        opts::set_search_path(ConfigLevel::System, "/dev/null").unwrap();
+/// A [`DownloadReport`] of every metadata file and selected pair, including
+/// those that failed, on success, or a [`DownloaderError`] if a step that
+/// affects the whole download fails.  In locked mode, the first pair that
+/// fails is such an error.
+pub fn download_program_pairs(
+    metadata: &[PathBuf],
+    filter: &PairFilter,
+    options: &DownloadOptions,
+) -> Result<DownloadReport, DownloaderError> {
+    let directories = &options.directories;
+    let lockfile_path = &directories.lockfile();
+    let expected_lockfile = if options.locked {
+        Some(Lockfile::read(lockfile_path)?)
+    } else {
+        None
+    };
+
+    let (pairs, metadata_files) = read_metadata(metadata)?;
+    print_parse_errors(&metadata_files);
+    let pairs = filter.select(pairs)?;
+    let config = Config::read(&directories.config_file())?;
+    let (mut lockfile, pair_reports) =
+        download_pairs(&pairs, options, &config, expected_lockfile.as_ref(), None)?;
+
+    // Downloading part of the corpus leaves the other pairs, and their
+    // entries in the lockfile, as they were.
+    let subset = !filter.is_empty()
+        || (metadata != directories.metadata(false) && metadata != directories.metadata(true));
+    match &expected_lockfile {
+        Some(expected_lockfile) if !subset => expected_lockfile.verify_all_downloaded(&lockfile)?,
+        Some(_) => {}
+        None if subset && lockfile_path.exists() => {
+            let mut existing_lockfile = Lockfile::read(lockfile_path)?;
+            existing_lockfile.pairs.append(&mut lockfile.pairs);
+            existing_lockfile.write(lockfile_path)?;
+        }
+        None => lockfile.write(lockfile_path)?,
     }
+    Ok(DownloadReport {
+        metadata_files,
+        pairs: pair_reports,
+    })
+}
 
-    let directories = if demo {
-        vec![PathBuf::from(DEMO_METADATA_DIRECTORY)]
+/// What [`update_program_pairs`] did to each program pair, by program name.
+#[derive(Debug, Default, Clone)]
+pub struct UpdateSummary {
+    /// Pairs that were downloaded for the first time.
+    pub added: Vec<String>,
+    /// Pairs that were copied again because their metadata or commits
+    /// changed.
+    pub updated: Vec<String>,
+    /// Pairs whose metadata and commits did not change, so were left alone.
+    pub unchanged: Vec<String>,
+    /// Pairs that were removed from the output directory because they are
+    /// no longer in the metadata.
+    pub removed: Vec<String>,
+    /// Pairs that failed to download.
+    pub failed: Vec<String>,
+}
+
+/// Brings previously downloaded program pairs up to date with the metadata
+/// and with their repositories.
+///
+/// Cached clones are fetched, so default branches and branches resolve to
+/// their latest commits.  Each pair's metadata and resolved commits are then
+/// compared with its `pair.json` manifest, and only pairs that changed are
+/// copied again.  Pairs that are no longer in the metadata are removed from
+/// the output directory.  Afterwards the lockfile is rewritten.
+///
+/// Every metadata file must parse, so that the pairs of a file with a typo
+/// are not taken to be removed from the metadata.
+///
+/// # Arguments
+///
+/// - `metadata` - The metadata files, or directories of them, of the whole
+///   corpus.
+/// - `options` - Options that control the download.  Locked mode is ignored,
+///   since updating moves the corpus away from the lockfile.
+///
+/// # Returns
+///
+/// An [`UpdateSummary`] on success, or a [`DownloaderError`] if any step
+/// fails.  Nothing is downloaded or removed if a metadata file fails to
+/// parse.
+pub fn update_program_pairs(
+    metadata: &[PathBuf],
+    options: &DownloadOptions,
+) -> Result<UpdateSummary, DownloaderError> {
+    let directories = &options.directories;
+    let previous: BTreeMap<String, PairManifest> = if directories.output.exists() {
+        PairManifest::read_all(&directories.output)?
+            .into_iter()
+            .map(|manifest| (manifest.pair.program_name.clone(), manifest))
+            .collect()
     } else {
-        vec![
-            PathBuf::from(PROJECT_METADATA_DIRECTORY),
-            PathBuf::from(INDIVIDUAL_METADATA_DIRECTORY),
-        ]
+        BTreeMap::new()
     };
 
-    // Count total metadata files in the directories being processed.
-    let mut total_files = 0;
-    for directory in &directories {
-        total_files += utils::count_files(&directory)?;
+    let (pairs, metadata_files) = read_metadata(metadata)?;
+    print_parse_errors(&metadata_files);
+    let failed = metadata_files
+        .iter()
+        .filter(|metadata_file| metadata_file.error.is_some())
+        .count();
+    if failed > 0 {
+        return Err(DownloaderError::IncompleteMetadata { failed });
     }
+    let config = Config::read(&directories.config_file())?;
+    let (lockfile, _) = download_pairs(&pairs, options, &config, None, Some(&previous))?;
 
-    // Create a progress bar to track the number of metadata files that have
-    // been processed.
-    let progress_bar = ProgressBar::new(total_files as u64);
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{bar:40.white/white} {pos}/{len} {msg}")
-            .map_err(|error| DownloaderError::ProgressBar(error.to_string()))?
-            .progress_chars("##-"),
-    );
-    progress_bar.set_message(format!("Processing metadata files..."));
+    let mut summary = UpdateSummary::default();
+    for pair in &pairs {
+        let name = pair.program_name.clone();
+        match (previous.get(&name), lockfile.pairs.get(&name)) {
+            (_, None) => summary.failed.push(name),
+            (None, Some(_)) => summary.added.push(name),
+            (Some(manifest), Some(locked_pair))
+                if manifest.pair == *pair
+                    && manifest.c_program.commit == locked_pair.c_program.commit
+                    && manifest.rust_program.commit == locked_pair.rust_program.commit =>
+            {
+                summary.unchanged.push(name)
+            }
+            (Some(_), Some(_)) => summary.updated.push(name),
+        }
+    }
 
-    for directory in &directories {
-        download_from_metadata_directory(&directory, &progress_bar)?;
+    // Prune pairs that were removed from the metadata.
+    for name in previous.keys() {
+        if !pairs.iter().any(|pair| pair.program_name == *name) {
+            let pair_directory = directories.output.join(name);
+            fs::remove_dir_all(&pair_directory).map_err(|error| DownloaderError::IoRemove {
+                path: pair_directory,
+                error,
+            })?;
+            summary.removed.push(name.clone());
+        }
     }
 
-    progress_bar.finish_with_message("Downloaded all program pairs!");
-    Ok(())
+    lockfile.write(&directories.lockfile())?;
+    Ok(summary)
 }
 
-/// Download program pairs in the given metadata files.
-///
-/// The program iterates through each metadata JSON file, then parses and
-/// downloads the program pairs.
+/// Returns the metadata directories of the full corpus or of the demo.
 ///
 /// # Arguments
 ///
-/// - `directory` - The directory containing the metadata JSON files,
-///   typically `metadata/individual/` or `metadata/projects/`.
-/// - `progress_bar` - Update each time a metadata file is processed.
+/// - `demo` - Whether to return the demo metadata directory.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success, or a [`DownloaderError`] if directory reading
-/// fails.
-pub fn download_from_metadata_directory(
-    directory: &Path,
-    progress_bar: &ProgressBar,
-) -> Result<(), DownloaderError> {
-    let metadata_files = directory
-        .read_dir()
-        .map_err(|error| DownloaderError::IoRead {
-            path: directory.to_path_buf(),
-            error,
-        })?;
+/// `metadata/demo/` for the demo, and `metadata/project/` and
+/// `metadata/individual/` otherwise.
+pub fn metadata_directories(demo: bool) -> Vec<PathBuf> {
+    Directories::default().metadata(demo)
+}
 
-    for metadata_file in metadata_files {
-        let metadata_file = metadata_file.map_err(|error| DownloaderError::IoRead {
-            path: directory.to_path_buf(),
-            error,
-        })?;
+/// Reads the program pairs in the given metadata files and directories.
+///
+/// The program continues, rather than halts, if it fails to parse a
+/// metadata file.
+///
+/// # Arguments
+///
+/// - `paths` - Metadata JSON files, or directories containing them,
+///   typically `metadata/individual/` and `metadata/projects/`.
+///
+/// # Returns
+///
+/// The program pairs of every metadata file that was parsed, or a
+/// [`DownloaderError`] if directory reading fails.
+pub fn read_program_pairs(paths: &[PathBuf]) -> Result<Vec<ProgramPair>, DownloaderError> {
+    let (pairs, metadata_files) = read_metadata(paths)?;
+    print_parse_errors(&metadata_files);
+    Ok(pairs)
+}
 
-        // Parse the contents of `metadata_file`.
-        match corpus::parse(&metadata_file.path()) {
-            // Download the program pairs listed in the metadata file.
-            Ok(metadata) => download_from_metadata_file(&metadata, progress_bar),
+/// Reads the program pairs in the given metadata files and directories,
+/// recording the outcome of parsing each file.
+///
+/// # Arguments
+///
+/// - `paths` - Metadata JSON files, or directories containing them.
+///
+/// # Returns
+///
+/// The program pairs of every metadata file that was parsed, and a
+/// [`MetadataFileReport`] for every metadata file, on success, or a
+/// [`DownloaderError`] if directory reading fails.
+pub(crate) fn read_metadata(
+    paths: &[PathBuf],
+) -> Result<(Vec<ProgramPair>, Vec<MetadataFileReport>), DownloaderError> {
+    let mut pairs = Vec::new();
+    let mut reports = Vec::new();
+    for metadata_file in metadata_files(paths)? {
+        // If there is an error parsing the current file, record it and move
+        // on to the next file.
+        let report = match corpus::parse(&metadata_file) {
+            Ok(metadata) => {
+                let names = metadata
+                    .pairs
+                    .iter()
+                    .map(|pair| pair.program_name.clone())
+                    .collect();
+                pairs.extend(metadata.pairs);
+                MetadataFileReport {
+                    path: metadata_file,
+                    pairs: names,
+                    error: None,
+                }
+            }
+            Err(error) => MetadataFileReport {
+                path: metadata_file,
+                pairs: Vec::new(),
+                error: Some(ErrorReport::from(&error)),
+            },
+        };
+        reports.push(report);
+    }
+    Ok((pairs, reports))
+}
 
-            // If there is an error parsing the current file,
-            // display an error and move on to the next file.
-            Err(error) => eprintln!(
+/// Prints an error for every metadata file that failed to parse.
+///
+/// # Arguments
+///
+/// - `metadata_files` - The outcome of reading each metadata file.
+fn print_parse_errors(metadata_files: &[MetadataFileReport]) {
+    for metadata_file in metadata_files {
+        if let Some(error) = &metadata_file.error {
+            eprintln!(
                 "Failed to parse '{}': {}",
-                metadata_file.path().display(),
-                error
-            ),
+                metadata_file.path.display(),
+                error.message
+            );
         }
     }
+}
 
-    Ok(())
+/// Lists the metadata files given directly or found in given directories.
+///
+/// # Arguments
+///
+/// - `paths` - Metadata JSON files, or directories containing them.
+///
+/// # Returns
+///
+/// The metadata files, in the order of `paths` and sorted within each
+/// directory, or a [`DownloaderError`] if directory reading fails.
+pub fn metadata_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, DownloaderError> {
+    let mut metadata_files = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            metadata_files.push(path.clone());
+        } else {
+            metadata_files.extend(utils::find_metadata_files(path)?);
+        }
+    }
+    Ok(metadata_files)
 }
 
-/// Downloads all program pairs in a given Metadata object.
+/// Downloads the given program pairs.
+///
+/// Each repository is cloned once, however many pairs use it, and
+/// repositories are cloned in parallel.  Then the programs are copied in
+/// parallel, except that programs sharing a repository but needing different
+/// commits of it are copied one commit at a time.
 ///
-/// The program continues, rather than halts, if it fails to download
-/// a program pair.
+/// The program continues, rather than halts, if it fails to download a
+/// program pair, unless it is running in locked mode.
+///
+/// # Side Effects
 ///
-/// Increments the progress bar each time a metadata file is finished
-/// processing.
+/// - Creates `repository_clones/`, which is a local cache for git clones,
+///   and `program_pairs/`, marking both as created by this tool.
+/// - Creates `program_pairs/<program-name>/` for each pair, containing the
+///   `c-program/` and `rust-program/` directories and `pair.json`.
 ///
 /// # Arguments
 ///
-/// - `metadata` - The program pairs to download.
-/// - `progress_bar` - Is updated each time a metadata file is processed.
-fn download_from_metadata_file(metadata: &Metadata, progress_bar: &ProgressBar) {
-    for pair in metadata.pairs.iter() {
-        if let Err(error) = download_program_pair(pair) {
-            eprintln!("Failed to download '{}': {}", pair.program_name, error)
+/// - `pairs` - The program pairs to download.
+/// - `options` - Options that control the download.
+/// - `config` - The configuration, which decides where repositories are
+///   fetched from.
+/// - `expected_lockfile` - The lockfile every pair must match, in locked
+///   mode.  The locked commits are checked out instead of the revisions in
+///   the metadata, and the downloaded files must match the locked hashes.
+/// - `previous` - The manifests of the pairs downloaded before, when
+///   updating.  Default branches are then fetched even if cached, pairs
+///   whose metadata and commits match their manifest are not copied again,
+///   and pairs that changed are copied into a staging directory, which only
+///   replaces theirs once both programs are copied.
+///
+/// # Returns
+///
+/// A [`Lockfile`] recording every downloaded pair, and a [`PairReport`] for
+/// every pair, on success, or, in locked mode, a [`DownloaderError`] for the
+/// first pair that fails to download or does not match the lockfile.
+fn download_pairs(
+    pairs: &[ProgramPair],
+    options: &DownloadOptions,
+    config: &Config,
+    expected_lockfile: Option<&Lockfile>,
+    previous: Option<&BTreeMap<String, PairManifest>>,
+) -> Result<(Lockfile, Vec<PairReport>), DownloaderError> {
+    utils::mark_directory(&options.directories.output)?;
+    utils::mark_directory(&options.directories.cache)?;
+
+    // List the programs to copy.  The C program of `pairs[i]` is
+    // `copy_jobs[2 * i]`, and its Rust program is `copy_jobs[2 * i + 1]`.
+    let mut copy_jobs = Vec::new();
+    for pair in pairs {
+        let locked_pair = match expected_lockfile {
+            Some(expected_lockfile) => Some(
+                expected_lockfile
+                    .pairs
+                    .get(&pair.program_name)
+                    .ok_or_else(|| DownloaderError::LockMismatch {
+                        program_name: pair.program_name.clone(),
+                        differences: "the pair is not in the lockfile".to_string(),
+                    })?,
+            ),
+            None => None,
+        };
+
+        // When updating, a pair downloaded before is copied next to its
+        // directory, which it only replaces once both programs are copied.
+        let pair_directory = options.directories.output.join(&pair.program_name);
+        let base_program_path =
+            if previous.is_some_and(|previous| previous.contains_key(&pair.program_name)) {
+                utils::staging_path(&pair_directory, PAIR_STAGING_EXTENSION)
+            } else {
+                pair_directory
+            };
+        for (program, directory_name, locked_program) in [
+            (
+                &pair.c_program,
+                "c-program",
+                locked_pair.map(|locked_pair| &locked_pair.c_program),
+            ),
+            (
+                &pair.rust_program,
+                "rust-program",
+                locked_pair.map(|locked_pair| &locked_pair.rust_program),
+            ),
+        ] {
+            let mut program = program.clone();
+            if let Some(locked_program) = locked_program {
+                program.revision = Some(Revision::Commit(locked_program.commit.clone()));
+            }
+            copy_jobs.push(CopyJob {
+                repository_path: repository_path(&options.directories.cache, &program)?,
+                program,
+                program_directory: base_program_path.join(directory_name),
+            });
+        }
+    }
+
+    let sparse = options.sparse && sparse::git_available();
+    if options.sparse && !sparse {
+        eprintln!("Cloning repositories in full, since sparse clones need git to be installed");
+    }
+
+    // Group the programs by repository, so each repository is cloned once.
+    let mut repository_jobs: BTreeMap<&Path, RepositoryJob> = BTreeMap::new();
+    let mut repository_copy_jobs: BTreeMap<&Path, Vec<usize>> = BTreeMap::new();
+    for (index, copy_job) in copy_jobs.iter().enumerate() {
+        repository_jobs
+            .entry(&copy_job.repository_path)
+            .or_insert_with(|| RepositoryJob {
+                repository_url: copy_job.program.source_url().to_string(),
+                fetch_url: config.fetch_url(copy_job.program.source_url()),
+                revisions: BTreeSet::new(),
+                archive: copy_job.program.archive.clone(),
+                sparse: sparse && copy_job.program.archive.is_none(),
+            })
+            .revisions
+            .insert(copy_job.program.revision.clone());
+        repository_copy_jobs
+            .entry(&copy_job.repository_path)
+            .or_default()
+            .push(index);
+    }
+
+    let thread_pool = ThreadPoolBuilder::new()
+        .num_threads(options.jobs)
+        .build()
+        .map_err(|error| DownloaderError::ThreadPool(error.to_string()))?;
+    let multi_progress = MultiProgress::new();
+    let progress_bar = multi_progress.add(ProgressBar::new(repository_jobs.len() as u64));
+    progress_bar.set_style(progress_bar_style()?);
+
+    // Reports a program pair that failed, and in locked mode stops the
+    // download.
+    let report = |error: DownloaderError, program_name: &str| -> Result<(), DownloaderError> {
+        if expected_lockfile.is_some() {
+            return Err(error);
+        }
+        progress_bar.suspend(|| eprintln!("Failed to download '{program_name}': {error}"));
+        Ok(())
+    };
+
+    // Clone the repositories and resolve the commit of every revision needed.
+    progress_bar.set_message("Cloning repositories...");
+    let clone_results: Vec<_> = thread_pool.install(|| {
+        repository_jobs
+            .par_iter()
+            .map(|(repository_path, repository_job)| {
+                let start = Instant::now();
+                let received_bytes = AtomicU64::new(0);
+                let result = clone_repository(
+                    repository_path,
+                    repository_job,
+                    options.offline,
+                    previous.is_some(),
+                    &config.network,
+                    &received_bytes,
+                    &multi_progress,
+                );
+                progress_bar.inc(1);
+                (result, start.elapsed(), received_bytes.into_inner())
+            })
+            .collect()
+    });
+    // Failures are reported with each program pair they affect.
+    let mut commits: HashMap<(&Path, Option<Revision>), Result<Oid, String>> = HashMap::new();
+    let mut clone_stats: HashMap<&Path, (Duration, u64)> = HashMap::new();
+    for ((repository_path, repository_job), (result, duration, received_bytes)) in
+        repository_jobs.iter().zip(clone_results)
+    {
+        clone_stats.insert(repository_path, (duration, received_bytes));
+        match result {
+            Ok(revisions) => {
+                for (revision, result) in revisions {
+                    let result = result.map_err(|error| error.to_string());
+                    commits.insert((repository_path, revision), result);
+                }
+            }
+            Err(error) => {
+                for revision in &repository_job.revisions {
+                    commits.insert((repository_path, revision.clone()), Err(error.to_string()));
+                }
+            }
+        }
+    }
+
+    // When updating, keep the programs of pairs that did not change.  Those
+    // that did are copied into an empty staging directory, so no stale files
+    // remain.
+    let mut kept_programs = HashMap::new();
+    for (index, pair) in pairs.iter().enumerate() {
+        let Some(manifest) = previous.and_then(|previous| previous.get(&pair.program_name)) else {
+            continue;
+        };
+        let [c_commit, rust_commit] =
+            [&copy_jobs[2 * index], &copy_jobs[2 * index + 1]].map(|copy_job| {
+                match commits.get(&(
+                    copy_job.repository_path.as_path(),
+                    copy_job.program.revision.clone(),
+                )) {
+                    Some(Ok(commit)) => Some(commit.to_string()),
+                    _ => None,
+                }
+            });
+        // Pairs whose repositories are unavailable fail below, and keep
+        // their previous files.
+        let (Some(c_commit), Some(rust_commit)) = (c_commit, rust_commit) else {
+            continue;
+        };
+        if manifest.pair == *pair
+            && manifest.c_program.commit == c_commit
+            && manifest.rust_program.commit == rust_commit
+        {
+            kept_programs.insert(2 * index, manifest.c_program.clone());
+            kept_programs.insert(2 * index + 1, manifest.rust_program.clone());
+        } else {
+            let pair_directory = options.directories.output.join(&pair.program_name);
+            let staging_path = utils::staging_path(&pair_directory, PAIR_STAGING_EXTENSION);
+            if let Err(error) = remove_staging(&staging_path) {
+                report(error, &pair.program_name)?;
+            }
+        }
+    }
+    let kept_pairs: HashSet<usize> = kept_programs.keys().map(|index| index / 2).collect();
+
+    // Copy the programs.  Each repository is handled by a single task, so
+    // its working directory is only checked out at one commit at a time.
+    progress_bar.set_position(0);
+    progress_bar.set_length((copy_jobs.len() - kept_programs.len()) as u64);
+    progress_bar.set_message("Copying files...");
+    let mut copy_results: Vec<CopyResult> = thread_pool.install(|| {
+        repository_copy_jobs
+            .par_iter()
+            .flat_map_iter(|(repository_path, indices)| {
+                let indices: Vec<_> = indices
+                    .iter()
+                    .copied()
+                    .filter(|index| !kept_programs.contains_key(index))
+                    .collect();
+                copy_from_repository(
+                    repository_path,
+                    &copy_jobs,
+                    &indices,
+                    &commits,
+                    options,
+                    &config.network,
+                    &progress_bar,
+                )
+            })
+            .collect()
+    });
+
+    copy_results.extend(
+        kept_programs
+            .into_iter()
+            .map(|(index, program)| (index, Duration::ZERO, Ok(program))),
+    );
+
+    // Write the manifest of every pair whose programs were both copied.
+    copy_results.sort_by_key(|(index, _, _)| *index);
+    let mut manifests = copy_results.into_iter().map(|(index, duration, result)| {
+        let copy_job = &copy_jobs[index];
+        let (clone_duration, received_bytes) = clone_stats[copy_job.repository_path.as_path()];
+        let files = result.as_ref().map_or(0, |manifest| manifest.files.len());
+        let program_report = ProgramReport::new(clone_duration, duration, received_bytes, files);
+        (program_report, result)
+    });
+    let mut lockfile = Lockfile::default();
+    let mut pair_reports = Vec::new();
+    for (index, pair) in pairs.iter().enumerate() {
+        let (Some((c_report, c_program)), Some((rust_report, rust_program))) =
+            (manifests.next(), manifests.next())
+        else {
+            unreachable!("Every program is copied from its repository");
         };
+        let pair_directory = options.directories.output.join(&pair.program_name);
+        let copy_directory = copy_jobs[2 * index]
+            .program_directory
+            .parent()
+            .unwrap_or(&pair_directory);
+        let staged = copy_directory != pair_directory && !kept_pairs.contains(&index);
+        let manifest = c_program.and_then(|c_program| {
+            let manifest = PairManifest {
+                pair: pair.clone(),
+                c_program,
+                rust_program: rust_program?,
+            };
+            let locked_pair = LockedProgramPair::new(&manifest);
+            if let Some(expected_lockfile) = expected_lockfile {
+                let expected_pair = &expected_lockfile.pairs[&pair.program_name];
+                expected_pair
+                    .c_program
+                    .verify(&pair.program_name, &locked_pair.c_program)?;
+                expected_pair
+                    .rust_program
+                    .verify(&pair.program_name, &locked_pair.rust_program)?;
+            }
+            if staged {
+                manifest.write(&copy_directory.join(PAIR_MANIFEST_FILE))?;
+                replace_directory(copy_directory, &pair_directory)?;
+            } else {
+                manifest.write(&pair_directory.join(PAIR_MANIFEST_FILE))?;
+            }
+            Ok(locked_pair)
+        });
+        // A pair that failed keeps its previous files.  A staging directory
+        // that cannot be removed is cleared before the next update instead.
+        if staged && manifest.is_err() {
+            let _ = remove_staging(copy_directory);
+        }
+        let (status, error) = match manifest {
+            Ok(locked_pair) => {
+                lockfile
+                    .pairs
+                    .insert(pair.program_name.clone(), locked_pair);
+                (PairStatus::Downloaded, None)
+            }
+            Err(error) => {
+                let error_report = ErrorReport::from(&error);
+                report(error, &pair.program_name)?;
+                (PairStatus::Failed, Some(error_report))
+            }
+        };
+        pair_reports.push(PairReport {
+            program_name: pair.program_name.clone(),
+            status,
+            error,
+            c_program: c_report,
+            rust_program: rust_report,
+        });
     }
-    progress_bar.inc(1);
+
+    progress_bar.finish_with_message("Downloaded all program pairs!");
+    Ok((lockfile, pair_reports))
 }
 
-/// Downloads a C-Rust program pair.
+/// The index in the list of copy jobs, copy duration and result of copying
+/// one program.
+type CopyResult = (usize, Duration, Result<ProgramManifest, DownloaderError>);
+
+/// Copies programs out of one repository's clone.
+///
+/// The programs are grouped by the commit they need.  For each commit, the
+/// working directory is checked out at that commit and then the programs are
+/// copied in parallel.
+///
+/// A partial clone is checked out with `git`, which fetches the contents of
+/// the files it checks out.  In sparse mode, only the `source_paths` of the
+/// programs being copied are checked out.
 ///
-/// Checks if the C and Rust repositories exist, and clone them if they don't.
-/// Copy the C source files to program_pairs/<program_name>/c-program.
-/// Copy the Rust source files to program_pairs/<program_name>/rust-program.
+/// # Arguments
+///
+/// - `repository_path` - Where the repository is cloned.
+/// - `copy_jobs` - All programs being downloaded.
+/// - `indices` - The indices in `copy_jobs` of the programs to copy.
+/// - `commits` - The commit each revision of each repository resolved to,
+///   or why it failed to.
+/// - `options` - How the copied files are arranged, and whether to check
+///   out only their `source_paths`.
+/// - `network` - The network settings used to fetch the contents of a
+///   partial clone's files, and whether to check out with the user's and
+///   system's git configuration.
+/// - `progress_bar` - Is updated each time a program is copied.
+///
+/// # Returns
+///
+/// The index, copy duration and result of each copied program.
+fn copy_from_repository(
+    repository_path: &Path,
+    copy_jobs: &[CopyJob],
+    indices: &[usize],
+    commits: &HashMap<(&Path, Option<Revision>), Result<Oid, String>>,
+    options: &DownloadOptions,
+    network: &NetworkConfig,
+    progress_bar: &ProgressBar,
+) -> Vec<CopyResult> {
+    let layout = options.layout;
+    let mut results = Vec::new();
+    let mut commit_jobs: BTreeMap<Oid, Vec<usize>> = BTreeMap::new();
+    let mut sparse_patterns = BTreeSet::new();
+    for &index in indices {
+        let program = &copy_jobs[index].program;
+        // A malformed pattern only fails its own program, whose files are
+        // then left out of the checkout.
+        if options.sparse {
+            match sparse::patterns([program]) {
+                Ok(patterns) => sparse_patterns.extend(patterns),
+                Err(error) => {
+                    results.push((index, Duration::ZERO, Err(error)));
+                    continue;
+                }
+            }
+        }
+        match commits.get(&(repository_path, program.revision.clone())) {
+            Some(Ok(commit)) => commit_jobs.entry(*commit).or_default().push(index),
+            Some(Err(reason)) => results.push((
+                index,
+                Duration::ZERO,
+                Err(DownloaderError::RepositoryUnavailable {
+                    repository_url: program.repository_url.clone(),
+                    reason: reason.clone(),
+                }),
+            )),
+            None => unreachable!("Every revision of every repository is resolved"),
+        }
+    }
+
+    let sparse_patterns: Option<Vec<_>> = options
+        .sparse
+        .then(|| sparse_patterns.into_iter().collect());
+
+    for (commit, indices) in commit_jobs {
+        let checkout = git::open(repository_path, network.use_git_config)
+            .and_then(|repository| {
+                if sparse::is_partial(&repository) {
+                    sparse::checkout(&repository, commit, sparse_patterns.as_deref(), network)
+                } else {
+                    checkout_commit(&repository, commit)
+                }
+            })
+            .map_err(|error| (error.code(), error.class(), error.message().to_string()));
+        let commit_results: Vec<_> = indices
+            .par_iter()
+            .map(|&index| {
+                let copy_job = &copy_jobs[index];
+                let start = Instant::now();
+                let result = match &checkout {
+                    Ok(()) => copy_files(copy_job, repository_path, commit, layout),
+                    Err((code, class, message)) => Err(DownloaderError::CheckoutRevision {
+                        repository_url: copy_job.program.repository_url.clone(),
+                        revision: commit.to_string(),
+                        error: git2::Error::new(*code, *class, message),
+                    }),
+                };
+                progress_bar.inc(1);
+                (index, start.elapsed(), result)
+            })
+            .collect();
+        results.extend(commit_results);
+    }
+    results
+}
+
+/// Copies a program's `source_paths` out of its repository's clone.
 ///
 /// # Side Effects
 ///
-/// - Creates destination directories for program pairs at
-///   `program_pairs/<program-name>/`.
+/// - Creates the program's `c-program/` or `rust-program/` directory.
+/// - May overwrite files in that directory.
 ///
 /// # Arguments
 ///
-/// - `pair` - A program pair.
+/// - `copy_job` - The program to copy.
+/// - `repository_path` - Where the program's repository is cloned.  Its
+///   working directory must be checked out at `commit`.
+/// - `commit` - The commit the files are copied from.
+/// - `layout` - How the copied files are arranged.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
-fn download_program_pair(pair: &ProgramPair) -> Result<(), DownloaderError> {
-    let program_name = &pair.program_name;
-    let base_program_path = Path::new(PROGRAM_PAIRS_DIRECTORY).join(program_name);
-    let c_program_path = base_program_path.join("c-program");
-    let rust_program_path = base_program_path.join("rust-program");
-
-    // Create the destination directories for the C and Rust source files.
-    fs::create_dir_all(&c_program_path).map_err(|source| DownloaderError::IoCreate {
-        path: c_program_path.clone(),
-        error: source,
-    })?;
-    fs::create_dir_all(&rust_program_path).map_err(|source| DownloaderError::IoCreate {
-        path: rust_program_path.clone(),
-        error: source,
+/// A [`ProgramManifest`] recording the commit and the copied files if all
+/// files were successfully copied, or [`DownloaderError`] on failure.
+fn copy_files(
+    copy_job: &CopyJob,
+    repository_path: &Path,
+    commit: Oid,
+    layout: Layout,
+) -> Result<ProgramManifest, DownloaderError> {
+    let program_directory = &copy_job.program_directory;
+    fs::create_dir_all(program_directory).map_err(|error| DownloaderError::IoCreate {
+        path: program_directory.clone(),
+        error,
     })?;
 
-    download_files(
-        program_name,
-        Language::C,
-        &c_program_path,
-        &pair.c_program.repository_url,
-        &pair.c_program.source_paths,
-    )?;
-    download_files(
-        program_name,
-        Language::Rust,
-        &rust_program_path,
-        &pair.rust_program.repository_url,
-        &pair.rust_program.source_paths,
-    )?;
+    // Copy given files from the repository to the given directory, and
+    // record the source and hash of every copied file keyed by its path
+    // relative to `program_directory`.
+    let copies = plan_copies(&copy_job.program, repository_path, layout)?;
+    let mut files = BTreeMap::new();
+    for (relative_destination, relative_source) in &copies {
+        let source = repository_path.join(relative_source);
+        let destination = program_directory.join(relative_destination);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|error| DownloaderError::IoCreate {
+                path: parent.to_path_buf(),
+                error,
+            })?;
+        }
+        fs::copy(&source, &destination).map_err(|error| DownloaderError::IoCopy {
+            source: source.clone(),
+            destination: destination.clone(),
+            error,
+        })?;
+        files.insert(
+            utils::to_slash_path(relative_destination)?,
+            FileManifest {
+                source_path: utils::to_slash_path(relative_source)?,
+                sha256: lockfile::hash_file(&destination)?,
+            },
+        );
+    }
 
-    Ok(())
+    Ok(ProgramManifest {
+        commit: commit.to_string(),
+        files,
+    })
 }
 
-/// Downloads the specified source files from a Git repository.
+/// Decides where each file listed in a program's `source_paths` is copied
+/// to.
 ///
-/// This function clones the repository (if not already cached) into
-/// `repository_clones/<language>/<repository_name>`, then copies the listed
-/// `source_files` into the given `program_directory`.
+/// Directories are expanded into the files they contain with one of the
+/// program's extensions, and glob patterns into the files they match.  Files
+/// matching an exclusion are left out.  A file that is reached through more
+/// than one entry of `source_paths` is only copied once.  In the flat layout,
+/// files found through a glob pattern are named by their path within the
+/// pattern's base directory, as if that directory had been listed.
 ///
-/// A progress bar is displayed on standard output to track cloning progress.
+/// If the program asks for its crate manifest, the nearest `Cargo.toml`
+/// above each copied file is copied too.
 ///
-/// # Side Effects
+/// # Arguments
+///
+/// - `program` - The program whose files are copied.  Its `source_paths` are
+///   paths, relative to the repository root, of the files and directories to
+///   copy, glob patterns, and exclusions; see [`patterns`].
+/// - `repository_directory` - The working directory of the repository.
+/// - `layout` - How the copied files are arranged.
+///
+/// # Returns
 ///
-/// - Creates `repository_clones/`, which is a local cache for git clones.
-/// - May overwrite files at `program_directory`.
+/// A map from each destination, relative to the program directory, to its
+/// source, relative to the repository root.  Returns
+/// [`DownloaderError::FileNameCollision`] if two different sources would be
+/// copied to the same destination.
+fn plan_copies(
+    program: &Program,
+    repository_directory: &Path,
+    layout: Layout,
+) -> Result<BTreeMap<PathBuf, PathBuf>, DownloaderError> {
+    let patterns = SourcePatterns::new(&program.source_paths)?;
+    let mut copies: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for include in patterns.includes() {
+        // List every file to copy together with its flattened file name.
+        let files = match include {
+            Include::Path(source_path) => {
+                let source = repository_directory.join(source_path);
+                if source.is_dir() {
+                    let source_files = utils::find_source_files(&source, &program.extensions);
+                    flatten_paths(source_files, &source)?
+                } else {
+                    let file_name = Path::new(source_path).file_name().ok_or_else(|| {
+                        DownloaderError::Io(format!(
+                            "Failed to get file name for path '{source_path}'"
+                        ))
+                    })?;
+                    vec![(source.clone(), PathBuf::from(file_name))]
+                }
+            }
+            Include::Glob {
+                pattern,
+                base,
+                matcher,
+            } => {
+                let matches = patterns::find_glob_matches(repository_directory, base, matcher)?;
+                if matches.is_empty() {
+                    return Err(DownloaderError::NoGlobMatches {
+                        pattern: pattern.clone(),
+                    });
+                }
+                flatten_paths(matches, &repository_directory.join(base))?
+            }
+        };
+
+        for (file, flat_name) in files {
+            let repository_path = file
+                .strip_prefix(repository_directory)
+                .unwrap_or(&file)
+                .to_path_buf();
+            if patterns.is_excluded(&utils::to_slash_path(&repository_path)?) {
+                continue;
+            }
+            add_copy(&mut copies, layout, flat_name, repository_path)?;
+        }
+    }
+
+    if program.crate_manifest {
+        let manifests: BTreeSet<PathBuf> = copies
+            .values()
+            .filter_map(|repository_path| {
+                repository_path
+                    .ancestors()
+                    .skip(1)
+                    .map(|directory| directory.join(CRATE_MANIFEST_FILE))
+                    .find(|manifest| repository_directory.join(manifest).is_file())
+            })
+            .collect();
+        for manifest in manifests {
+            add_copy(
+                &mut copies,
+                layout,
+                PathBuf::from(CRATE_MANIFEST_FILE),
+                manifest,
+            )?;
+        }
+    }
+    Ok(copies)
+}
+
+/// Adds a file to the copies planned by [`plan_copies`].
 ///
 /// # Arguments
 ///
-/// - `program_name` - Name of the program being downloaded (used for progress messages).
-/// - `program_language` - Language of the program (affects repository clone path).
-/// - `program_directory` - Destination directory for the downloaded source files.
-/// - `repository_url` - Git URL of the repository to clone.
-/// - `source_files` - Paths (relative to repo root) of files or directories to copy.
+/// - `copies` - The copies planned so far.
+/// - `layout` - How the copied files are arranged.
+/// - `flat_name` - The destination of the file in the flat layout.
+/// - `repository_path` - The file, relative to the repository root.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if all files were successfully downloaded and copied, or
-/// [`DownloadError`] on failure.
-fn download_files(
-    program_name: &str,
-    program_language: Language,
-    program_directory: &Path,
+/// Returns `Ok(())` on success, or [`DownloaderError::FileNameCollision`] if
+/// a different file is already copied to the same destination.
+fn add_copy(
+    copies: &mut BTreeMap<PathBuf, PathBuf>,
+    layout: Layout,
+    flat_name: PathBuf,
+    repository_path: PathBuf,
+) -> Result<(), DownloaderError> {
+    let destination = match layout {
+        Layout::Flat => flat_name,
+        Layout::Mirror => repository_path.clone(),
+    };
+    match copies.get(&destination) {
+        Some(existing) if *existing != repository_path => Err(DownloaderError::FileNameCollision {
+            destination,
+            first: existing.clone(),
+            second: repository_path,
+        }),
+        Some(_) => Ok(()),
+        None => {
+            copies.insert(destination, repository_path);
+            Ok(())
+        }
+    }
+}
+
+/// Pairs each file found within a directory with its flattened file name,
+/// its path within the directory joined with `-`.
+///
+/// # Arguments
+///
+/// - `files` - Files within `directory`.
+/// - `directory` - The directory the files were found in.
+///
+/// # Returns
+///
+/// Each file with its flattened file name on success and
+/// [`DownloaderError`] on failure.
+fn flatten_paths(
+    files: Vec<PathBuf>,
+    directory: &Path,
+) -> Result<Vec<(PathBuf, PathBuf)>, DownloaderError> {
+    files
+        .into_iter()
+        .map(|file| {
+            let relative_path = file.strip_prefix(directory).unwrap_or(&file);
+            let flat_name = utils::to_slash_path(relative_path)?.replace('/', "-");
+            Ok((file, PathBuf::from(flat_name)))
+        })
+        .collect()
+}
+
+/// Returns where a program's repository is cloned.
+///
+/// We store repositories in `repository_clones/<host>/<owner>%2F<repository>`,
+/// using the cache key of the repository URL, so a repository is cloned once
+/// however its URL is spelled and whichever language uses it.  Repositories
+/// unpacked from a release archive are stored under the archive URL instead.
+///
+/// # Arguments
+///
+/// - `cache_directory` - The directory repositories are cloned into.
+/// - `program` - A C or Rust program.
+///
+/// # Returns
+///
+/// The path of the clone, or a [`DownloaderError`] on failure.
+pub(crate) fn repository_path(
+    cache_directory: &Path,
+    program: &Program,
+) -> Result<PathBuf, DownloaderError> {
+    Ok(cache_directory.join(utils::cache_key(program.source_url())?))
+}
+
+/// Checks that a cached clone was cloned from the expected repository.
+///
+/// # Arguments
+///
+/// - `repository` - The cached clone.
+/// - `repository_path` - Where the clone is cached.
+/// - `repository_url` - The URL the clone is expected to come from.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the clone's `origin` remote normalizes to the same
+/// URL as `repository_url`, or [`DownloaderError::CacheMismatch`] otherwise.
+pub(crate) fn verify_origin(
+    repository: &Repository,
+    repository_path: &Path,
     repository_url: &str,
-    source_files: &[String],
 ) -> Result<(), DownloaderError> {
-    let progress_bar = ProgressBar::new(80);
+    let origin_url = repository
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().ok().map(str::to_string));
+    let matches = match &origin_url {
+        Some(origin_url) => {
+            utils::normalize_repository_url(origin_url).ok()
+                == Some(utils::normalize_repository_url(repository_url)?)
+        }
+        None => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(DownloaderError::CacheMismatch {
+            path: repository_path.to_path_buf(),
+            repository_url: repository_url.to_string(),
+            origin_url: origin_url.unwrap_or_default(),
+        })
+    }
+}
 
-    let repository_directory = download_with_git(&program_language, repository_url, &progress_bar)?;
+/// Downloads a git repository using git clone, and resolves the commit of
+/// every revision that is needed.
+///
+/// Pinned revisions are fetched unless they are already present in the
+/// local clone, so the result always matches the metadata, including for
+/// cached clones.  In offline mode, nothing is cloned or fetched, and the
+/// revisions must already be present.
+///
+/// The repository is fetched from its rewritten fetch URL, but the clone's
+/// `origin` remote always keeps the URL given in the metadata, so the cache
+/// stays valid when mirrors change.
+///
+/// A repository with a release archive is unpacked from it instead.  It is
+/// never fetched again, since the archive is pinned by its checksum.
+///
+/// New clones are fetched next to `repository_path` and only moved there
+/// once their revisions are fetched, and cached clones are checked before
+/// they are reused, so an interrupted clone is never mistaken for a
+/// finished one.
+///
+/// A progress bar is displayed to track cloning progress.
+///
+/// # Arguments
+///
+/// - `repository_path` - Where the repository is cloned.
+/// - `repository_job` - The repository and the revisions of it to resolve.
+/// - `offline` - Whether to only use a cached clone.
+/// - `refresh` - Whether to fetch the default branch even if it is cached.
+/// - `network` - How failed clones and fetches are retried, and how long
+///   each attempt may take.
+/// - `received_bytes` - Incremented by the bytes received while cloning or
+///   fetching.
+/// - `multi_progress` - Displays the progress bar of this clone.
+///
+/// # Returns
+///
+/// The commit, or the error, for each revision on success, or a
+/// [`DownloaderError`] if the repository cannot be cloned.
+fn clone_repository(
+    repository_path: &Path,
+    repository_job: &RepositoryJob,
+    offline: bool,
+    refresh: bool,
+    network: &NetworkConfig,
+    received_bytes: &AtomicU64,
+    multi_progress: &MultiProgress,
+) -> Result<ResolvedRevisions, DownloaderError> {
+    let repository_url = repository_job.repository_url.as_str();
+    let repository_name = utils::get_repository_name(repository_url)?;
 
-    progress_bar.set_style(ProgressStyle::default_spinner());
-    progress_bar.set_message("Copying files...");
+    let progress_bar = multi_progress.add(ProgressBar::new(80));
+    progress_bar.set_style(progress_bar_style()?);
+    progress_bar.set_message(format!("Cloning repository {repository_name}..."));
+    let ca_bundle = git::CaBundle::read(network)?;
+    let transfer = Transfer {
+        repository_name: &repository_name,
+        fetch_url: &repository_job.fetch_url,
+        network,
+        ca_bundle: ca_bundle.as_ref(),
+        progress_bar: &progress_bar,
+        received_bytes,
+        sparse: repository_job.sparse,
+    };
 
-    // Copy given files from the repository to the given directory.
-    for file_path in source_files {
-        let file_name = Path::new(file_path).file_name().ok_or_else(|| {
-            DownloaderError::Io(format!("Failed to get file name for path '{file_path}'"))
-        })?;
+    // Reuse the clone in `repository_clones/` if it is intact, or clone it.
+    let use_git_config = network.use_git_config;
+    let repository =
+        match open_cached_clone(repository_path, repository_url, offline, use_git_config)? {
+            Some(repository) => repository,
 
-        let source = repository_directory.join(file_path);
-        let destination = program_directory.join(file_name);
+            None if offline => {
+                return Err(DownloaderError::NotCloned {
+                    repository_url: repository_url.to_string(),
+                    path: repository_path.to_path_buf(),
+                });
+            }
 
-        // Copy files from destination to source.
-        if source.is_dir() {
-            utils::copy_files_from_directory(&source, &program_directory)?;
-        } else {
-            fs::copy(&source, &destination).map_err(|error| DownloaderError::IoCopy {
-                source: source.to_path_buf(),
-                destination: destination.to_path_buf(),
-                error,
-            })?;
+            None if let Some(archive) = &repository_job.archive => {
+                progress_bar.set_message(format!("Downloading archive {repository_name}..."));
+                archive::unpack_archive(
+                    repository_path,
+                    archive,
+                    &repository_job.fetch_url,
+                    network,
+                    received_bytes,
+                )?
+            }
+
+            // Fetch the revisions of a new clone into a staging directory,
+            // and only move it into the cache once they are fetched, so an
+            // interrupted or failed clone never leaves a repository there.
+            None => {
+                let staging_path = utils::staging_path(repository_path, STAGING_EXTENSION);
+                let staging = init_repository(&staging_path, repository_url, use_git_config)?;
+                let commits =
+                    resolve_revisions(&staging, repository_job, false, refresh, &transfer);
+                drop(staging);
+                if commits.iter().any(|(_, commit)| commit.is_ok()) {
+                    move_into_cache(&staging_path, repository_path)?;
+                } else {
+                    remove_staging(&staging_path)?;
+                }
+                progress_bar.finish_and_clear();
+                return Ok(commits);
+            }
+        };
+
+    let offline = offline || repository_job.archive.is_some();
+    let commits = resolve_revisions(&repository, repository_job, offline, refresh, &transfer);
+    progress_bar.finish_and_clear();
+    Ok(commits)
+}
+
+/// Resolves every revision of a repository that is needed to a commit.
+///
+/// # Arguments
+///
+/// - `repository` - The local clone.
+/// - `repository_job` - The repository and the revisions of it to resolve.
+/// - `offline` - Whether to only use what the clone already contains.
+/// - `refresh` - Whether to fetch the default branch even if it is cached.
+/// - `transfer` - Fetches the revisions, from their fetch URL.
+///
+/// # Returns
+///
+/// The commit, or the error, for each revision.
+fn resolve_revisions(
+    repository: &Repository,
+    repository_job: &RepositoryJob,
+    offline: bool,
+    refresh: bool,
+    transfer: &Transfer,
+) -> ResolvedRevisions {
+    repository_job
+        .revisions
+        .iter()
+        .map(|revision| {
+            let commit = resolve_revision(
+                repository,
+                &repository_job.repository_url,
+                revision.as_ref(),
+                offline,
+                refresh,
+                transfer,
+            );
+            (revision.clone(), commit)
+        })
+        .collect()
+}
+
+/// Opens the cached clone of a repository, if it can be reused.
+///
+/// A clone that fails its integrity check, or a directory that is not a
+/// repository at all, is removed so the repository is cloned again.  In
+/// offline mode it cannot be cloned again, so it is an error instead.
+///
+/// # Arguments
+///
+/// - `repository_path` - Where the repository is cached.
+/// - `repository_url` - The URL the clone is expected to come from.
+/// - `offline` - Whether the repository cannot be cloned again.
+/// - `use_git_config` - Whether to open the clone with the user's and
+///   system's git configuration.
+///
+/// # Returns
+///
+/// The cached [`Repository`], `None` if there is none to reuse, or a
+/// [`DownloaderError`] if it comes from another repository, is corrupt in
+/// offline mode, or cannot be removed.
+fn open_cached_clone(
+    repository_path: &Path,
+    repository_url: &str,
+    offline: bool,
+    use_git_config: bool,
+) -> Result<Option<Repository>, DownloaderError> {
+    if !repository_path.exists() {
+        return Ok(None);
+    }
+    let error = match git::open(repository_path, use_git_config) {
+        Ok(repository) => {
+            verify_origin(&repository, repository_path, repository_url)?;
+            match verify_clone(&repository) {
+                Ok(()) => return Ok(Some(repository)),
+                Err(error) => error,
+            }
         }
+        Err(error) => error,
+    };
+    if offline {
+        return Err(DownloaderError::CorruptClone {
+            path: repository_path.to_path_buf(),
+            error,
+        });
     }
+    fs::remove_dir_all(repository_path).map_err(|error| DownloaderError::IoRemove {
+        path: repository_path.to_path_buf(),
+        error,
+    })?;
+    Ok(None)
+}
 
-    progress_bar.finish_with_message(format!(
-        "Downloaded '{}' ({})",
-        program_name,
-        program_language.to_str()
-    ));
+/// Checks that a cached clone has references, and that every one points to
+/// a commit whose tree is present, so an empty clone or one with missing
+/// objects is not reused.
+///
+/// # Arguments
+///
+/// - `repository` - The cached clone.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the clone is intact, or a [`git2::Error`] for the
+/// missing references or the first missing object otherwise.
+fn verify_clone(repository: &Repository) -> Result<(), git2::Error> {
+    let mut references = 0;
+    for reference in repository.references()? {
+        reference?.peel_to_commit()?.tree()?;
+        references += 1;
+    }
+    if references == 0 {
+        return Err(git2::Error::from_str("the clone has no references"));
+    }
     Ok(())
 }
 
-/// Downloads a git repository using git clone.
+/// Creates an empty repository with an `origin` remote, replacing anything
+/// an earlier attempt left at its path.
 ///
 /// # Arguments
 ///
-/// - `program_language` - Either C or Rust.
-/// - `repository_url` - The URL to download with git.
-/// - `progress_bar` - A `ProgressBar` used to show the progress of the
-///                    download status of the current program-pair.
+/// - `repository_path` - Where to create the repository.
+/// - `repository_url` - The URL of the `origin` remote.
+/// - `use_git_config` - Whether to open the repository with the user's and
+///   system's git configuration.
 ///
 /// # Returns
 ///
-/// A `PathBuf` to the downloaded repository on success, or a
-/// [`DownloaderError`] on failure.
-fn download_with_git(
-    program_language: &Language,
+/// The new [`Repository`] on success, or a [`DownloaderError`] on failure.
+fn init_repository(
+    repository_path: &Path,
     repository_url: &str,
-    progress_bar: &ProgressBar,
-) -> Result<PathBuf, DownloaderError> {
-    let repository_clones_path =
-        Path::new(REPOSITORY_CLONES_DIRECTORY).join(program_language.to_str());
-    let repository_name = utils::get_repository_name(repository_url)?;
+    use_git_config: bool,
+) -> Result<Repository, DownloaderError> {
+    let to_error = |error| DownloaderError::CloneRepository {
+        repository_url: repository_url.to_string(),
+        error,
+    };
+    remove_staging(repository_path)?;
+    let repository = git::init(repository_path, use_git_config).map_err(to_error)?;
+    repository
+        .remote("origin", repository_url)
+        .map_err(to_error)?;
+    Ok(repository)
+}
 
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("{bar:40.white/white} {pos}/{len} {msg}")
-            .map_err(|error| DownloaderError::ProgressBar(error.to_string()))?
-            .progress_chars("##-"),
-    );
-    progress_bar.set_message(format!("Cloning repository {repository_name}..."));
+/// Removes a staging directory, if it exists.
+///
+/// # Arguments
+///
+/// - `staging_path` - The staging directory.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+fn remove_staging(staging_path: &Path) -> Result<(), DownloaderError> {
+    if staging_path.exists() {
+        fs::remove_dir_all(staging_path).map_err(|error| DownloaderError::IoRemove {
+            path: staging_path.to_path_buf(),
+            error,
+        })?;
+    }
+    Ok(())
+}
 
-    // Set up remote callbacks for progress tracking.
-    let mut remote_callbacks = RemoteCallbacks::new();
-    remote_callbacks.transfer_progress(|progress: git2::Progress| {
-        update_progress_bar_callback(progress, &repository_name, &progress_bar)
-    });
+/// Replaces a directory with the one a pair was copied into.
+///
+/// # Arguments
+///
+/// - `staging_path` - The directory the pair was copied into.
+/// - `directory` - The pair's directory, which is replaced.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+fn replace_directory(staging_path: &Path, directory: &Path) -> Result<(), DownloaderError> {
+    if directory.exists() {
+        fs::remove_dir_all(directory).map_err(|error| DownloaderError::IoRemove {
+            path: directory.to_path_buf(),
+            error,
+        })?;
+    }
+    fs::rename(staging_path, directory).map_err(|error| DownloaderError::IoRename {
+        source: staging_path.to_path_buf(),
+        destination: directory.to_path_buf(),
+        error,
+    })
+}
 
-    // Check if repository exists in `repository_clones/`, if not clone it.
-    // We store repositories in repository_clones/<language>/<repository_name>.
-    let repository = match Repository::open(repository_clones_path.join(&repository_name)) {
-        Ok(repository) => repository,
-        Err(_) => {
-            // Set up fetch options with progress-tracking callbacks.
+/// Moves a complete clone from where it was created into the clone cache.
+///
+/// # Arguments
+///
+/// - `staging_path` - Where the clone was created.
+/// - `repository_path` - Where the clone is cached.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+fn move_into_cache(staging_path: &Path, repository_path: &Path) -> Result<(), DownloaderError> {
+    fs::rename(staging_path, repository_path).map_err(|error| DownloaderError::IoRename {
+        source: staging_path.to_path_buf(),
+        destination: repository_path.to_path_buf(),
+        error,
+    })
+}
+
+/// A clone or fetch of one repository, with the settings and progress bar
+/// shared by every attempt at it.
+struct Transfer<'a> {
+    /// The name of the repository, shown in progress messages.
+    repository_name: &'a str,
+    /// The URL the repository is fetched from.
+    fetch_url: &'a str,
+    /// How failed attempts are retried, and how long each may take.
+    network: &'a NetworkConfig,
+    /// The certificate authorities to trust instead of the system's.
+    ca_bundle: Option<&'a git::CaBundle>,
+    /// The progress bar of the repository.
+    progress_bar: &'a ProgressBar,
+    /// Incremented by the bytes received.
+    received_bytes: &'a AtomicU64,
+    /// Whether to fetch without file contents.
+    sparse: bool,
+}
+
+impl Transfer<'_> {
+    /// Clones or fetches the repository, retrying with exponential backoff
+    /// when an attempt fails with a network error or times out.
+    ///
+    /// # Arguments
+    ///
+    /// - `operation` - Makes one attempt, given fetch options whose
+    ///   callbacks report progress, supply credentials and abandon the
+    ///   attempt when it times out, and that connect through the proxy.
+    ///
+    /// # Returns
+    ///
+    /// The result of the first attempt that succeeds, or the error of the
+    /// last attempt.
+    fn run<T>(
+        &self,
+        mut operation: impl FnMut(FetchOptions<'_>) -> Result<T, git2::Error>,
+    ) -> Result<T, git2::Error> {
+        let attempt = || {
+            let deadline = self
+                .network
+                .clone_timeout()
+                .map(|timeout| Instant::now() + timeout);
+            let mut remote_callbacks = progress_callbacks(
+                self.repository_name,
+                self.progress_bar,
+                self.received_bytes,
+                deadline,
+            );
+            remote_callbacks.credentials(git::credentials(self.network));
+            if let Some(ca_bundle) = self.ca_bundle {
+                remote_callbacks.certificate_check(ca_bundle.certificate_check());
+            }
             let mut fetch_options = FetchOptions::new();
             fetch_options.remote_callbacks(remote_callbacks);
-
+            fetch_options.proxy_options(git::proxy_options(self.network));
             // Clone only the latest commit to save time and space.
-            fetch_options.depth(1);
-
-            // Clone the repository.
-            let mut builder = RepoBuilder::new();
-            builder.fetch_options(fetch_options);
-            builder
-                .clone(
-                    repository_url,
-                    &repository_clones_path.join(&repository_name),
+            if !utils::is_local_url(self.fetch_url) {
+                fetch_options.depth(1);
+            }
+
+            operation(fetch_options).map_err(|error| {
+                // The callbacks abort an attempt once it is past its deadline.
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    git2::Error::new(
+                        ErrorCode::GenericError,
+                        ErrorClass::Net,
+                        format!(
+                            "timed out after {} seconds",
+                            self.network.clone_timeout_seconds
+                        ),
+                    )
+                } else {
+                    error
+                }
+            })
+        };
+        // Local repositories do not suffer from network errors.
+        let local = utils::is_local_url(self.fetch_url);
+        self.network.retry(
+            attempt,
+            |error| !local && is_transient(error),
+            |error, delay, retry| {
+                self.progress_bar.set_message(format!(
+                    "Retrying {} in {:.1}s ({retry}/{}): {}",
+                    self.repository_name,
+                    delay.as_secs_f64(),
+                    self.network.retries,
+                    error.message()
+                ));
+            },
+        )
+    }
+}
+
+/// Checks whether a clone or fetch failed for a reason that may go away when
+/// it is retried, such as a refused or dropped connection, rather than one
+/// that will not, such as failed authentication.
+///
+/// # Arguments
+///
+/// - `error` - The error of the failed attempt.
+///
+/// # Returns
+///
+/// True if the attempt should be retried.
+fn is_transient(error: &git2::Error) -> bool {
+    matches!(
+        error.class(),
+        ErrorClass::Net | ErrorClass::Os | ErrorClass::Http | ErrorClass::Ssl
+    ) && !matches!(
+        error.code(),
+        ErrorCode::Auth | ErrorCode::Certificate | ErrorCode::NotFound
+    )
+}
+
+/// Resolves a revision to a commit in a local clone.
+///
+/// The revision is fetched from `fetch_url` if it is not already present.
+/// In sparse mode, it is fetched without file contents.
+/// Branches are always fetched, since their tip may have moved, and so is
+/// the default branch when refreshing.  In offline mode, nothing is fetched,
+/// and branches resolve to their tip when they were last fetched.
+///
+/// # Arguments
+///
+/// - `repository` - The local clone.
+/// - `repository_url` - The URL of the repository, used in error messages.
+/// - `revision` - The revision to resolve, or `None` for the default branch.
+/// - `offline` - Whether to only use what the clone already contains.
+/// - `refresh` - Whether to fetch the default branch even if it is cached.
+/// - `transfer` - Fetches the revision, from its fetch URL.
+///
+/// # Returns
+///
+/// The ID of the commit on success, or a [`DownloaderError`] on failure.
+fn resolve_revision(
+    repository: &Repository,
+    repository_url: &str,
+    revision: Option<&Revision>,
+    offline: bool,
+    refresh: bool,
+    transfer: &Transfer,
+) -> Result<Oid, DownloaderError> {
+    if offline {
+        return find_revision(repository, revision)
+            .map(|commit| commit.id())
+            .map_err(|error| DownloaderError::MissingRevision {
+                repository_url: repository_url.to_string(),
+                revision: describe_revision(revision),
+                error,
+            });
+    }
+    let moves = match revision {
+        Some(Revision::Branch(_)) => true,
+        None => refresh,
+        Some(_) => false,
+    };
+    if !moves && let Ok(commit) = find_revision(repository, revision) {
+        return Ok(commit.id());
+    }
+
+    // Fetch through an anonymous remote, so `origin` keeps its URL.  The
+    // refspecs name their destinations, so the fetched references are
+    // stored just as if `origin` had been fetched.
+    let refspec = revision.map_or(DEFAULT_BRANCH_REFSPEC.to_string(), Revision::refspec);
+    let fetch = || {
+        transfer.run(|mut fetch_options| {
+            repository.remote_anonymous(transfer.fetch_url)?.fetch(
+                &[&refspec],
+                Some(&mut fetch_options),
+                None,
+            )
+        })
+    };
+    let result = if transfer.sparse {
+        // Fall back to a full fetch if `git` itself fails, rather than the
+        // network.
+        let depth = (!utils::is_local_url(transfer.fetch_url)).then_some(1);
+        transfer
+            .run(|_| {
+                sparse::fetch(
+                    repository,
+                    transfer.fetch_url,
+                    &refspec,
+                    depth,
+                    transfer.network,
                 )
-                .map_err(|error| DownloaderError::CloneRepository {
-                    repository_url: repository_url.to_string(),
-                    error,
-                })?
-        }
+            })
+            .or_else(|error| {
+                if is_transient(&error) {
+                    Err(error)
+                } else {
+                    fetch()
+                }
+            })
+    } else {
+        fetch()
     };
+    result.map_err(|error| DownloaderError::FetchRevision {
+        repository_url: repository_url.to_string(),
+        revision: describe_revision(revision),
+        error,
+    })?;
 
-    let repository_directory = repository
-        .workdir()
-        .ok_or_else(|| {
-            DownloaderError::Io(format!(
-                "Failed to find working directory for repository '{repository_name}'"
-            ))
-        })?
-        .to_path_buf();
-    Ok(repository_directory)
+    find_revision(repository, revision)
+        .map(|commit| commit.id())
+        .map_err(|error| DownloaderError::CheckoutRevision {
+            repository_url: repository_url.to_string(),
+            revision: describe_revision(revision),
+            error,
+        })
+}
+
+/// Checks out a commit in a local clone, detaching `HEAD`.
+///
+/// # Arguments
+///
+/// - `repository` - The local clone.
+/// - `commit` - The commit to check out.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`git2::Error`] on failure.
+fn checkout_commit(repository: &Repository, commit: Oid) -> Result<(), git2::Error> {
+    let commit = repository.find_commit(commit)?;
+    repository.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+    repository.set_head_detached(commit.id())
+}
+
+/// Looks up the commit a revision refers to in a local clone.
+///
+/// # Arguments
+///
+/// - `repository` - The local clone.
+/// - `revision` - The revision to look up, or `None` for the default branch.
+///
+/// # Returns
+///
+/// The [`Commit`] on success, or a [`git2::Error`] if the revision has not
+/// been fetched.
+pub(crate) fn find_revision<'a>(
+    repository: &'a Repository,
+    revision: Option<&Revision>,
+) -> Result<Commit<'a>, git2::Error> {
+    match revision {
+        None => repository
+            .find_reference("refs/remotes/origin/HEAD")?
+            .peel_to_commit(),
+        Some(Revision::Commit(commit)) => repository.find_commit(Oid::from_str(commit)?),
+        Some(Revision::Tag(tag)) => repository
+            .find_reference(&format!("refs/tags/{tag}"))?
+            .peel_to_commit(),
+        Some(Revision::Branch(branch)) => repository
+            .find_reference(&format!("refs/remotes/origin/{branch}"))?
+            .peel_to_commit(),
+    }
+}
+
+/// Describes a revision for use in messages.
+///
+/// # Arguments
+///
+/// - `revision` - A revision, or `None` for the default branch.
+///
+/// # Returns
+///
+/// A description such as "tag v1.0" or "the default branch".
+pub(crate) fn describe_revision(revision: Option<&Revision>) -> String {
+    revision.map_or("the default branch".to_string(), Revision::to_string)
+}
+
+/// Creates the style shared by all progress bars.
+///
+/// # Returns
+///
+/// The [`ProgressStyle`] on success, or a [`DownloaderError`] on failure.
+fn progress_bar_style() -> Result<ProgressStyle, DownloaderError> {
+    Ok(ProgressStyle::default_bar()
+        .template("{bar:40.white/white} {pos}/{len} {msg}")
+        .map_err(|error| DownloaderError::ProgressBar(error.to_string()))?
+        .progress_chars("##-"))
+}
+
+/// Creates remote callbacks that report transfer progress, and abort the
+/// transfer once it is past its deadline.
+///
+/// # Arguments
+///
+/// - `repository_name` - The repository being downloaded.
+/// - `progress_bar` - The progress bar to update.
+/// - `received_bytes` - Incremented by the bytes received.
+/// - `deadline` - When to abort the transfer, if ever.
+///
+/// # Returns
+///
+/// The [`RemoteCallbacks`] to use when cloning or fetching.
+fn progress_callbacks<'a>(
+    repository_name: &'a str,
+    progress_bar: &'a ProgressBar,
+    received_bytes: &'a AtomicU64,
+    deadline: Option<Instant>,
+) -> RemoteCallbacks<'a> {
+    let in_time = move || deadline.is_none_or(|deadline| Instant::now() < deadline);
+
+    // The progress counts the bytes received by this transfer so far, so
+    // only add what it received since it was last reported.
+    let mut reported_bytes = 0;
+    let mut remote_callbacks = RemoteCallbacks::new();
+    remote_callbacks.sideband_progress(move |_| in_time());
+    remote_callbacks.transfer_progress(move |progress: git2::Progress| {
+        let bytes = progress.received_bytes() as u64;
+        received_bytes.fetch_add(bytes.saturating_sub(reported_bytes), Ordering::Relaxed);
+        reported_bytes = bytes;
+        update_progress_bar_callback(progress, repository_name, progress_bar) && in_time()
+    });
+    remote_callbacks
 }
 
 /// Callback used to update the progress bar as a repository is cloned.
@@ -390,3 +1811,761 @@ fn update_progress_bar_callback(
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::schema::{DEFAULT_EXTENSIONS, Language};
+
+    use crate::corpus::config::UrlRewrite;
+
+    use git2::Signature;
+    use indicatif::ProgressDrawTarget;
+    use tempfile::TempDir;
+
+    /// Creates a repository containing one commit per given file contents,
+    /// all writing to `main.c`.
+    ///
+    /// Returns the IDs of the commits in order.
+    fn create_repository(path: &Path, contents: &[&str]) -> Vec<Oid> {
+        let repository = Repository::init(path).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let mut commits = Vec::new();
+        for content in contents {
+            fs::write(path.join("main.c"), content).unwrap();
+            let mut index = repository.index().unwrap();
+            index.add_path(Path::new("main.c")).unwrap();
+            index.write().unwrap();
+            let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents: Vec<Commit> = commits
+                .last()
+                .map(|id| repository.find_commit(*id).unwrap())
+                .into_iter()
+                .collect();
+            let parents: Vec<&Commit> = parents.iter().collect();
+            let commit = repository
+                .commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    content,
+                    &tree,
+                    &parents,
+                )
+                .unwrap();
+            commits.push(commit);
+        }
+        commits
+    }
+
+    /// Creates the given empty files under `directory`.
+    fn create_files(directory: &Path, files: &[&str]) {
+        for file in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    /// Creates a Rust program with the given source paths and the default
+    /// extensions.
+    fn program(source_paths: &[String]) -> Program {
+        Program {
+            language: Language::Rust,
+            documentation_url: "https://example.com".to_string(),
+            repository_url: "https://example.com/repository".to_string(),
+            source_paths: source_paths.to_vec(),
+            revision: None,
+            archive: None,
+            extensions: DEFAULT_EXTENSIONS.map(str::to_string).to_vec(),
+            crate_manifest: false,
+        }
+    }
+
+    /// Tests that the flat layout reports files that share a name instead of
+    /// overwriting one with the other.
+    #[test]
+    fn test_plan_copies_flat_collision() {
+        let directory = TempDir::new().unwrap();
+        create_files(directory.path(), &["src/ls.c", "lib/ls.c"]);
+        let source_paths = ["src/ls.c".to_string(), "lib/ls.c".to_string()];
+
+        let result = plan_copies(&program(&source_paths), directory.path(), Layout::Flat);
+        assert!(matches!(
+            result,
+            Err(DownloaderError::FileNameCollision { .. })
+        ));
+    }
+
+    /// Tests that the flat layout joins paths within a listed directory with
+    /// `-`, and copies files listed more than once only once.
+    #[test]
+    fn test_plan_copies_flat() {
+        let directory = TempDir::new().unwrap();
+        create_files(
+            directory.path(),
+            &["src/uu/ls/src/ls.rs", "src/uu/ls/src/dired/mod.rs"],
+        );
+        let source_paths = [
+            "src/uu/ls/src".to_string(),
+            "src/uu/ls/src/ls.rs".to_string(),
+        ];
+
+        let copies = plan_copies(&program(&source_paths), directory.path(), Layout::Flat).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("dired-mod.rs"),
+                    PathBuf::from("src/uu/ls/src/dired/mod.rs")
+                ),
+                (PathBuf::from("ls.rs"), PathBuf::from("src/uu/ls/src/ls.rs")),
+            ],
+            copies.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    /// Tests that the mirror layout keeps paths relative to the repository
+    /// root.
+    #[test]
+    fn test_plan_copies_mirror() {
+        let directory = TempDir::new().unwrap();
+        create_files(directory.path(), &["src/ls.c", "lib/ls.c", "lib/ls.txt"]);
+        let source_paths = ["src/ls.c".to_string(), "lib".to_string()];
+
+        let copies =
+            plan_copies(&program(&source_paths), directory.path(), Layout::Mirror).unwrap();
+        assert_eq!(
+            vec![PathBuf::from("lib/ls.c"), PathBuf::from("src/ls.c")],
+            copies.into_keys().collect::<Vec<_>>()
+        );
+    }
+
+    /// Tests that glob patterns select matching files and that exclusions
+    /// remove files selected by any entry.
+    #[test]
+    fn test_plan_copies_glob() {
+        let directory = TempDir::new().unwrap();
+        create_files(
+            directory.path(),
+            &[
+                "src/uu/ls/src/ls.rs",
+                "src/uu/ls/src/dired.rs",
+                "src/uu/ls/tests/test_ls.rs",
+                "src/uu/ls/Cargo.toml",
+                "src/ls.c",
+            ],
+        );
+        let source_paths = [
+            "src/uu/ls/**/*.rs".to_string(),
+            "src/ls.c".to_string(),
+            "!**/tests/**".to_string(),
+            "!src/uu/ls/src/dired.rs".to_string(),
+        ];
+
+        let copies = plan_copies(&program(&source_paths), directory.path(), Layout::Flat).unwrap();
+        assert_eq!(
+            vec![
+                (PathBuf::from("ls.c"), PathBuf::from("src/ls.c")),
+                (
+                    PathBuf::from("src-ls.rs"),
+                    PathBuf::from("src/uu/ls/src/ls.rs")
+                ),
+            ],
+            copies.into_iter().collect::<Vec<_>>()
+        );
+
+        let source_paths = ["src/**/*.h".to_string()];
+        let result = plan_copies(&program(&source_paths), directory.path(), Layout::Flat);
+        assert!(matches!(result, Err(DownloaderError::NoGlobMatches { .. })));
+    }
+
+    /// Tests that listed directories are filtered by the program's
+    /// extensions, and that the crate manifest is copied when asked for.
+    #[test]
+    fn test_plan_copies_extensions() {
+        let directory = TempDir::new().unwrap();
+        create_files(
+            directory.path(),
+            &[
+                "Cargo.toml",
+                "src/uu/ls/Cargo.toml",
+                "src/uu/ls/src/ls.rs",
+                "src/uu/ls/src/ls.def",
+                "src/uu/ls/src/Makefile",
+                "src/uu/ls/src/README.md",
+            ],
+        );
+        let mut program = program(&["src/uu/ls/src".to_string()]);
+        program.extensions = vec!["rs".to_string(), "def".to_string(), "Makefile".to_string()];
+        program.crate_manifest = true;
+
+        let copies = plan_copies(&program, directory.path(), Layout::Mirror).unwrap();
+        assert_eq!(
+            vec![
+                PathBuf::from("src/uu/ls/Cargo.toml"),
+                PathBuf::from("src/uu/ls/src/Makefile"),
+                PathBuf::from("src/uu/ls/src/ls.def"),
+                PathBuf::from("src/uu/ls/src/ls.rs"),
+            ],
+            copies.into_keys().collect::<Vec<_>>()
+        );
+    }
+
+    /// Runs a function with a transfer from a URL that reports no progress.
+    fn with_transfer<T>(fetch_url: &str, function: impl FnOnce(&Transfer) -> T) -> T {
+        let progress_bar = ProgressBar::hidden();
+        let transfer = Transfer {
+            repository_name: "test",
+            fetch_url,
+            network: &NetworkConfig::default(),
+            ca_bundle: None,
+            progress_bar: &progress_bar,
+            received_bytes: &AtomicU64::new(0),
+            sparse: false,
+        };
+        function(&transfer)
+    }
+
+    /// Resolves a revision in a clone and checks it out.
+    fn resolve_and_checkout(
+        repository: &Repository,
+        repository_url: &str,
+        revision: Option<&Revision>,
+    ) -> Oid {
+        let commit = with_transfer(repository_url, |transfer| {
+            resolve_revision(repository, repository_url, revision, false, false, transfer)
+        })
+        .unwrap();
+        checkout_commit(repository, commit).unwrap();
+        commit
+    }
+
+    /// Tests that a pinned commit is checked out rather than the latest one,
+    /// both for a fresh clone and for a clone that is already cached.
+    #[test]
+    fn test_resolve_revision_commit() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first", "second"]);
+        let origin_url = format!("file://{}", origin.display());
+
+        let clone = directory.path().join("clone");
+        let repository = init_repository(&clone, &origin_url, false).unwrap();
+        let revision = Revision::Commit(commits[0].to_string());
+        let checked_out = resolve_and_checkout(&repository, &origin_url, Some(&revision));
+        assert_eq!(commits[0], checked_out);
+        assert_eq!("first", fs::read_to_string(clone.join("main.c")).unwrap());
+
+        let revision = Revision::Commit(commits[1].to_string());
+        let checked_out = resolve_and_checkout(&repository, &origin_url, Some(&revision));
+        assert_eq!(commits[1], checked_out);
+        assert_eq!("second", fs::read_to_string(clone.join("main.c")).unwrap());
+    }
+
+    /// Tests that a pinned tag is fetched and checked out.
+    #[test]
+    fn test_resolve_revision_tag() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first", "second"]);
+        let origin_repository = Repository::open(&origin).unwrap();
+        let first = origin_repository.find_object(commits[0], None).unwrap();
+        origin_repository
+            .tag_lightweight("v1.0", &first, false)
+            .unwrap();
+        let origin_url = format!("file://{}", origin.display());
+
+        let clone = directory.path().join("clone");
+        let repository = init_repository(&clone, &origin_url, false).unwrap();
+        let revision = Revision::Tag("v1.0".to_string());
+        let checked_out = resolve_and_checkout(&repository, &origin_url, Some(&revision));
+        assert_eq!(commits[0], checked_out);
+        assert_eq!("first", fs::read_to_string(clone.join("main.c")).unwrap());
+    }
+
+    /// Tests that the default branch is fetched into a clone that was
+    /// created for a pinned revision.
+    #[test]
+    fn test_resolve_revision_default_branch() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first", "second"]);
+        let origin_url = format!("file://{}", origin.display());
+
+        let clone = directory.path().join("clone");
+        let repository = init_repository(&clone, &origin_url, false).unwrap();
+        let revision = Revision::Commit(commits[0].to_string());
+        resolve_and_checkout(&repository, &origin_url, Some(&revision));
+        let checked_out = resolve_and_checkout(&repository, &origin_url, None);
+        assert_eq!(commits[1], checked_out);
+        assert_eq!("second", fs::read_to_string(clone.join("main.c")).unwrap());
+    }
+
+    /// Tests that the default branch of a cached clone is only fetched again
+    /// when refreshing.
+    #[test]
+    fn test_clone_repository_refresh() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first"]);
+        let origin_url = format!("file://{}", origin.display());
+        let clone = directory.path().join("clone");
+        let repository_job = RepositoryJob {
+            repository_url: origin_url.clone(),
+            fetch_url: origin_url,
+            revisions: BTreeSet::from([None]),
+            archive: None,
+            sparse: false,
+        };
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let resolve = |refresh| {
+            let resolved = clone_repository(
+                &clone,
+                &repository_job,
+                false,
+                refresh,
+                &NetworkConfig::default(),
+                &AtomicU64::new(0),
+                &multi_progress,
+            );
+            resolved.unwrap().remove(0).1.unwrap()
+        };
+        assert_eq!(commits[0], resolve(false));
+
+        let origin_repository = Repository::open(&origin).unwrap();
+        let parent = origin_repository.find_commit(commits[0]).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let second = origin_repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "second",
+                &parent.tree().unwrap(),
+                &[&parent],
+            )
+            .unwrap();
+
+        assert_eq!(commits[0], resolve(false));
+        assert_eq!(second, resolve(true));
+    }
+
+    /// Tests that a cached clone with missing objects, or a directory left
+    /// behind by an interrupted clone, is cloned again, except offline.
+    #[test]
+    fn test_clone_repository_corrupt() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first"]);
+        let origin_url = format!("file://{}", origin.display());
+        let clone = directory.path().join("clone");
+        let repository_job = RepositoryJob {
+            repository_url: origin_url.clone(),
+            fetch_url: origin_url,
+            revisions: BTreeSet::from([None]),
+            archive: None,
+            sparse: false,
+        };
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let resolve = |offline| {
+            clone_repository(
+                &clone,
+                &repository_job,
+                offline,
+                false,
+                &NetworkConfig::default(),
+                &AtomicU64::new(0),
+                &multi_progress,
+            )
+            .map(|mut resolved| resolved.remove(0).1.unwrap())
+        };
+        assert_eq!(commits[0], resolve(false).unwrap());
+        assert!(!utils::staging_path(&clone, STAGING_EXTENSION).exists());
+
+        let objects = clone.join(".git/objects");
+        fs::remove_dir_all(&objects).unwrap();
+        fs::create_dir(&objects).unwrap();
+        assert!(matches!(
+            resolve(true),
+            Err(DownloaderError::CorruptClone { .. })
+        ));
+        assert_eq!(commits[0], resolve(false).unwrap());
+
+        fs::remove_dir_all(&clone).unwrap();
+        fs::create_dir(&clone).unwrap();
+        fs::write(clone.join("main.c"), "partial").unwrap();
+        assert_eq!(commits[0], resolve(false).unwrap());
+    }
+
+    /// Tests that a clone whose fetch fails leaves nothing in the cache, and
+    /// that what an interrupted clone left behind is cloned again.
+    #[test]
+    fn test_clone_repository_interrupted() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let origin_url = format!("file://{}", origin.display());
+        let clone = directory.path().join("clone");
+        let staging = utils::staging_path(&clone, STAGING_EXTENSION);
+        let repository_job = RepositoryJob {
+            repository_url: origin_url.clone(),
+            fetch_url: origin_url,
+            revisions: BTreeSet::from([None]),
+            archive: None,
+            sparse: false,
+        };
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let resolve = || {
+            clone_repository(
+                &clone,
+                &repository_job,
+                false,
+                false,
+                &NetworkConfig::default(),
+                &AtomicU64::new(0),
+                &multi_progress,
+            )
+            .unwrap()
+            .remove(0)
+            .1
+        };
+
+        // The origin does not exist yet, so the fetch fails.
+        assert!(matches!(
+            resolve(),
+            Err(DownloaderError::FetchRevision { .. })
+        ));
+        assert!(!clone.exists());
+        assert!(!staging.exists());
+
+        // A staging directory and an empty repository, as left by a killed
+        // process, are replaced.
+        let commits = create_repository(&origin, &["first"]);
+        init_repository(&staging, "https://example.com/staging", false).unwrap();
+        init_repository(&clone, &repository_job.repository_url, false).unwrap();
+        assert_eq!(commits[0], resolve().unwrap());
+        assert!(!staging.exists());
+    }
+
+    /// Tests that network errors are retried until the retries run out, and
+    /// that other errors are not.
+    #[test]
+    fn test_transfer_retries() {
+        let progress_bar = ProgressBar::hidden();
+        let network = NetworkConfig {
+            retries: 2,
+            retry_delay_ms: 0,
+            clone_timeout_seconds: 0,
+            ..NetworkConfig::default()
+        };
+        let transfer = Transfer {
+            repository_name: "test",
+            fetch_url: "https://example.com/test",
+            network: &network,
+            ca_bundle: None,
+            progress_bar: &progress_bar,
+            received_bytes: &AtomicU64::new(0),
+            sparse: false,
+        };
+        let attempts = |code, failures| {
+            let mut attempts = 0;
+            let result = transfer.run(|_| {
+                attempts += 1;
+                if attempts > failures {
+                    Ok(())
+                } else {
+                    Err(git2::Error::new(code, ErrorClass::Net, "connection reset"))
+                }
+            });
+            (result.is_ok(), attempts)
+        };
+        assert_eq!((true, 2), attempts(ErrorCode::GenericError, 1));
+        assert_eq!((false, 3), attempts(ErrorCode::GenericError, 5));
+        assert_eq!((false, 1), attempts(ErrorCode::Auth, 5));
+    }
+
+    /// Tests that offline mode never clones or fetches, and only resolves
+    /// revisions already present in a cached clone.
+    #[test]
+    fn test_offline() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first", "second"]);
+        let origin_url = format!("file://{}", origin.display());
+        let clone = directory.path().join("clone");
+        let revision = Revision::Commit(commits[0].to_string());
+
+        let repository_job = RepositoryJob {
+            repository_url: origin_url.clone(),
+            fetch_url: origin_url.clone(),
+            revisions: BTreeSet::from([Some(revision.clone())]),
+            archive: None,
+            sparse: false,
+        };
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let result = clone_repository(
+            &clone,
+            &repository_job,
+            true,
+            false,
+            &NetworkConfig::default(),
+            &AtomicU64::new(0),
+            &multi_progress,
+        );
+        assert!(matches!(result, Err(DownloaderError::NotCloned { .. })));
+        assert!(!clone.exists());
+
+        let repository = init_repository(&clone, &origin_url, false).unwrap();
+        let offline = |revision| {
+            with_transfer(&origin_url, |transfer| {
+                resolve_revision(&repository, &origin_url, revision, true, false, transfer)
+            })
+        };
+        assert!(matches!(
+            offline(Some(&revision)),
+            Err(DownloaderError::MissingRevision { .. })
+        ));
+
+        resolve_and_checkout(&repository, &origin_url, Some(&revision));
+        assert_eq!(commits[0], offline(Some(&revision)).unwrap());
+    }
+
+    /// Tests that pairs that cannot be built offline are reported as failed,
+    /// instead of failing the whole download.
+    #[test]
+    fn test_download_offline_report() {
+        let root = TempDir::new().unwrap();
+        let metadata = root.path().join("cat.json");
+        fs::write(
+            &metadata,
+            r#"{
+                "project_information": {
+                    "program_name": "coreutils",
+                    "translation_tools": ["manual"],
+                    "feature_relationship": "rust_equivalent_to_c",
+                    "c_program": {
+                        "documentation_url": "https://example.com",
+                        "repository_url": "https://example.com/c"
+                    },
+                    "rust_program": {
+                        "documentation_url": "https://example.com",
+                        "repository_url": "https://example.com/rust"
+                    }
+                },
+                "pairs": [{
+                    "program_name": "cat",
+                    "program_description": "Concatenates files.",
+                    "c_program": {"source_paths": ["cat.c"]},
+                    "rust_program": {"source_paths": ["cat.rs"]}
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let options = DownloadOptions {
+            offline: true,
+            directories: Directories::new(root.path()),
+            ..DownloadOptions::default()
+        };
+        let report = download_program_pairs(&[metadata], &PairFilter::default(), &options).unwrap();
+        assert_eq!(report.pairs.len(), 1);
+        assert_eq!(report.pairs[0].status, PairStatus::Failed);
+        assert_eq!(
+            report.pairs[0].error.as_ref().unwrap().kind,
+            "RepositoryUnavailable"
+        );
+    }
+
+    /// Writes an individual metadata file with one pair per given name,
+    /// description and C source path, copied from the given repositories.
+    fn write_metadata(path: &Path, c_url: &str, rust_url: &str, pairs: &[(&str, &str, &str)]) {
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|(program_name, description, c_source_path)| {
+                serde_json::json!({
+                    "program_name": program_name,
+                    "program_description": description,
+                    "translation_tools": ["manual"],
+                    "feature_relationship": "rust_equivalent_to_c",
+                    "c_program": {
+                        "documentation_url": "https://example.com/c",
+                        "repository_url": c_url,
+                        "source_paths": [c_source_path]
+                    },
+                    "rust_program": {
+                        "documentation_url": "https://example.com/rust",
+                        "repository_url": rust_url,
+                        "source_paths": ["main.c"]
+                    }
+                })
+            })
+            .collect();
+        fs::write(path, serde_json::json!({ "pairs": pairs }).to_string()).unwrap();
+    }
+
+    /// Tests that updating adds, updates, keeps and removes pairs, that a
+    /// pair that fails to copy keeps its previous files, and that nothing is
+    /// removed while a metadata file fails to parse.
+    #[test]
+    fn test_update_program_pairs() {
+        let root = TempDir::new().unwrap();
+        let c_url = format!("file://{}", root.path().join("c").display());
+        let rust_url = format!("file://{}", root.path().join("rust").display());
+        create_repository(&root.path().join("c"), &["int main;"]);
+        create_repository(&root.path().join("rust"), &["fn main() {}"]);
+        let metadata = [root.path().join("a.json"), root.path().join("b.json")];
+        let options = DownloadOptions {
+            directories: Directories::new(root.path()),
+            ..DownloadOptions::default()
+        };
+        let output = &options.directories.output;
+        let update = |a: &[(&str, &str, &str)], b: &[(&str, &str, &str)]| {
+            write_metadata(&metadata[0], &c_url, &rust_url, a);
+            write_metadata(&metadata[1], &c_url, &rust_url, b);
+            update_program_pairs(&metadata, &options)
+        };
+
+        let summary = update(
+            &[("cat", "cat", "main.c"), ("ls", "ls", "main.c")],
+            &[("echo", "echo", "main.c")],
+        )
+        .unwrap();
+        assert_eq!(summary.added, ["cat", "ls", "echo"]);
+        assert!(output.join("ls/c-program/main.c").exists());
+
+        let summary = update(
+            &[("cat", "cat", "main.c"), ("ls", "ls", "missing.c")],
+            &[("echo", "Prints its arguments.", "main.c")],
+        )
+        .unwrap();
+        assert_eq!(summary.unchanged, ["cat"]);
+        assert_eq!(summary.updated, ["echo"]);
+        assert_eq!(summary.failed, ["ls"]);
+        assert!(output.join("ls/c-program/main.c").exists());
+        assert!(!output.join("ls.updating").exists());
+        let manifest = PairManifest::read(&output.join("echo").join(PAIR_MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.pair.program_description, "Prints its arguments.");
+
+        let summary = update(&[("cat", "cat", "main.c")], &[("echo", "echo", "main.c")]).unwrap();
+        assert_eq!(summary.removed, ["ls"]);
+        assert!(!output.join("ls").exists());
+
+        write_metadata(&metadata[0], &c_url, &rust_url, &[]);
+        fs::write(&metadata[1], "{").unwrap();
+        let result = update_program_pairs(&metadata, &options);
+        assert!(matches!(
+            result,
+            Err(DownloaderError::IncompleteMetadata { failed: 1 })
+        ));
+        assert!(output.join("cat").exists());
+        assert!(output.join("echo").exists());
+    }
+
+    /// Tests that a locked download succeeds while the lockfile matches, and
+    /// fails when a locked commit or file hash differs from what is
+    /// downloaded.
+    #[test]
+    fn test_download_locked() {
+        let root = TempDir::new().unwrap();
+        let c_url = format!("file://{}", root.path().join("c").display());
+        let rust_url = format!("file://{}", root.path().join("rust").display());
+        let c_commits = create_repository(&root.path().join("c"), &["int x;", "int main;"]);
+        create_repository(&root.path().join("rust"), &["fn main() {}"]);
+        let metadata = [root.path().join("cat.json")];
+        write_metadata(&metadata[0], &c_url, &rust_url, &[("cat", "cat", "main.c")]);
+        let mut options = DownloadOptions {
+            directories: Directories::new(root.path()),
+            ..DownloadOptions::default()
+        };
+        let lockfile_path = options.directories.lockfile();
+        download_program_pairs(&metadata, &PairFilter::default(), &options).unwrap();
+        let lockfile = Lockfile::read(&lockfile_path).unwrap();
+
+        options.locked = true;
+        download_program_pairs(&metadata, &PairFilter::default(), &options).unwrap();
+
+        let mut tampered = Lockfile::read(&lockfile_path).unwrap();
+        tampered.pairs.get_mut("cat").unwrap().c_program.commit = c_commits[0].to_string();
+        tampered.write(&lockfile_path).unwrap();
+        let result = download_program_pairs(&metadata, &PairFilter::default(), &options);
+        assert!(matches!(result, Err(DownloaderError::LockMismatch { .. })));
+
+        let mut tampered = lockfile;
+        let files = &mut tampered.pairs.get_mut("cat").unwrap().rust_program.files;
+        *files.get_mut("main.c").unwrap() = "0".repeat(64);
+        tampered.write(&lockfile_path).unwrap();
+        let result = download_program_pairs(&metadata, &PairFilter::default(), &options);
+        assert!(matches!(result, Err(DownloaderError::LockMismatch { .. })));
+    }
+
+    /// Tests that a repository is cloned and fetched from its mirror, a local
+    /// bare repository, while its clone keeps the canonical URL.
+    #[test]
+    fn test_clone_repository_mirror() {
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commits = create_repository(&origin, &["first", "second"]);
+        let mirror = directory.path().join("mirrors/uutils/coreutils.git");
+        git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(&format!("file://{}", origin.display()), &mirror)
+            .unwrap();
+
+        let config = Config {
+            url_rewrites: vec![UrlRewrite {
+                from: "https://github.com/".to_string(),
+                to: format!("{}/mirrors/", directory.path().display()),
+            }],
+            ..Config::default()
+        };
+        let repository_url = "https://github.com/uutils/coreutils.git";
+        let revision = Some(Revision::Commit(commits[0].to_string()));
+        let repository_job = RepositoryJob {
+            repository_url: repository_url.to_string(),
+            fetch_url: config.fetch_url(repository_url),
+            revisions: BTreeSet::from([None, revision.clone()]),
+            archive: None,
+            sparse: false,
+        };
+        assert_eq!(mirror.to_str().unwrap(), repository_job.fetch_url);
+
+        let clone = directory.path().join("clone");
+        let multi_progress = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let resolved: Vec<_> = clone_repository(
+            &clone,
+            &repository_job,
+            false,
+            false,
+            &NetworkConfig::default(),
+            &AtomicU64::new(0),
+            &multi_progress,
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(revision, commit)| (revision, commit.unwrap()))
+        .collect();
+        assert_eq!(vec![(None, commits[1]), (revision, commits[0])], resolved);
+
+        let repository = Repository::open(&clone).unwrap();
+        assert!(verify_origin(&repository, &clone, repository_url).is_ok());
+    }
+
+    /// Tests that a cached clone is only reused for the repository it was
+    /// cloned from.
+    #[test]
+    fn test_verify_origin() {
+        let directory = TempDir::new().unwrap();
+        let clone = directory.path().join("clone");
+        let repository =
+            init_repository(&clone, "https://github.com/uutils/coreutils.git", false).unwrap();
+
+        assert!(verify_origin(&repository, &clone, "https://github.com/uutils/coreutils").is_ok());
+        assert!(matches!(
+            verify_origin(
+                &repository,
+                &clone,
+                "https://github.com/coreutils/coreutils"
+            ),
+            Err(DownloaderError::CacheMismatch { .. })
+        ));
+    }
+}
@@ -42,6 +42,15 @@ pub enum ParserError {
         /// Type string because `ValidationError` requires lifetimes.
         error: String,
     },
+
+    /// A program sets more than one of `commit`, `tag` and `branch`.
+    #[error("'{pointer}' sets {fields}, but only one of 'commit', 'tag' and 'branch' may be set")]
+    ConflictingRevisions {
+        /// The JSON pointer to the program, such as `/pairs/0/c_program`.
+        pointer: String,
+        /// The fields that are set, such as `'commit' and 'tag'`.
+        fields: String,
+    },
 }
 
 /// Errors that occur in the Downloader program.
@@ -70,6 +79,16 @@ pub enum DownloaderError {
         error: io::Error,
     },
 
+    /// Failed to remove a file or directory.
+    #[error("Failed to remove '{path}': {error}")]
+    IoRemove {
+        /// The path that could not be removed.
+        path: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
     /// Failed to copy a file or directory from `source` to `destination`.
     #[error("Failed to copy '{source}' to '{destination}': {error}")]
     IoCopy {
@@ -82,6 +101,29 @@ pub enum DownloaderError {
         error: io::Error,
     },
 
+    /// Failed to move a file or directory from `source` to `destination`.
+    #[error("Failed to move '{source}' to '{destination}': {error}")]
+    IoRename {
+        /// The path being moved.
+        source: PathBuf,
+        /// Where it was moved to.
+        destination: PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// Two different source files would be copied to the same destination.
+    #[error("Both '{first}' and '{second}' would be copied to '{destination}'")]
+    FileNameCollision {
+        /// The destination, relative to the program directory.
+        destination: PathBuf,
+        /// The first source, relative to the repository root.
+        first: PathBuf,
+        /// The second source, relative to the repository root.
+        second: PathBuf,
+    },
+
     /// Generic I/O error.
     #[error("IO error: {0}")]
     Io(String),
@@ -96,6 +138,272 @@ pub enum DownloaderError {
         error: git2::Error,
     },
 
+    /// A cached clone was cloned from a different repository than expected.
+    #[error("Cached clone '{path}' has origin '{origin_url}' instead of '{repository_url}'")]
+    CacheMismatch {
+        /// Where the clone is cached.
+        path: PathBuf,
+        /// The URL the clone was expected to come from.
+        repository_url: String,
+        /// The URL of the clone's `origin` remote, or an empty string if it
+        /// has none.
+        origin_url: String,
+    },
+
+    /// A cached clone is incomplete or damaged, and cannot be cloned again
+    /// because the download is offline.
+    #[error("Cached clone '{path}' is corrupt: {error}")]
+    CorruptClone {
+        /// Where the clone is cached.
+        path: PathBuf,
+        /// The error found while checking the clone.
+        #[source]
+        error: git2::Error,
+    },
+
+    /// Failed to fetch a pinned revision from a git repository.
+    #[error("Failed to fetch {revision} from '{repository_url}': {error}")]
+    FetchRevision {
+        /// The URL of the repository the revision was fetched from.
+        repository_url: String,
+        /// A description of the revision that failed to fetch.
+        revision: String,
+        /// The underlying git error.
+        #[source]
+        error: git2::Error,
+    },
+
+    /// Failed to check out a pinned revision in a local clone.
+    #[error("Failed to check out {revision} of '{repository_url}': {error}")]
+    CheckoutRevision {
+        /// The URL of the repository being checked out.
+        repository_url: String,
+        /// A description of the revision that failed to check out.
+        revision: String,
+        /// The underlying git error.
+        #[source]
+        error: git2::Error,
+    },
+
+    /// Failed to deserialize a corpus lockfile.
+    #[error("Failed to deserialize lockfile '{path}': {error}")]
+    DeserializeLockfile {
+        /// The lockfile that could not be deserialized.
+        path: PathBuf,
+        /// The underlying deserialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to deserialize the configuration file.
+    #[error("Failed to deserialize configuration file '{path}': {error}")]
+    DeserializeConfig {
+        /// The configuration file that could not be deserialized.
+        path: PathBuf,
+        /// The underlying deserialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to load the configured bundle of certificate authorities.
+    #[error("Failed to load certificate authorities from '{path}': {reason}")]
+    CaBundle {
+        /// The bundle that could not be loaded.
+        path: PathBuf,
+        /// Why the bundle could not be loaded.
+        reason: String,
+    },
+
+    /// A directory to delete lacks the marker file of directories this tool
+    /// created.
+    #[error(
+        "Refusing to delete '{path}', which has no '{marker}' file and may not have been \
+         created by this tool"
+    )]
+    UnmarkedDirectory {
+        /// The directory that was not deleted.
+        path: PathBuf,
+        /// The name of the missing marker file.
+        marker: String,
+    },
+
+    /// Some metadata files failed to parse, so the pairs and clones still in
+    /// use are not known, and nothing may be removed.
+    #[error("Refusing to remove anything while {failed} metadata file(s) fail to parse")]
+    IncompleteMetadata {
+        /// The number of metadata files that failed to parse.
+        failed: usize,
+    },
+
+    /// Failed to serialize a download report.
+    #[error("Failed to serialize download report: {error}")]
+    SerializeReport {
+        /// The underlying serialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to serialize a corpus lockfile.
+    #[error("Failed to serialize lockfile: {error}")]
+    SerializeLockfile {
+        /// The underlying serialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to deserialize a program pair manifest.
+    #[error("Failed to deserialize manifest '{path}': {error}")]
+    DeserializeManifest {
+        /// The manifest that could not be deserialized.
+        path: PathBuf,
+        /// The underlying deserialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to serialize a program pair manifest.
+    #[error("Failed to serialize manifest: {error}")]
+    SerializeManifest {
+        /// The underlying serialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// Failed to serialize a record of a `jsonl` export.
+    #[error("Failed to serialize dataset record: {error}")]
+    SerializeRecord {
+        /// The underlying serialization error.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// A downloaded program pair does not match the corpus lockfile.
+    #[error("Program pair '{program_name}' does not match the lockfile: {differences}")]
+    LockMismatch {
+        /// The name of the mismatched program pair.
+        program_name: String,
+        /// A description of every difference found.
+        differences: String,
+    },
+
+    /// A program's repository, or the revision of it the program needs,
+    /// could not be downloaded.
+    #[error("Repository '{repository_url}' could not be downloaded: {reason}")]
+    RepositoryUnavailable {
+        /// The URL of the repository.
+        repository_url: String,
+        /// Why the repository or revision could not be downloaded.  Several
+        /// programs may share the cause, so it is kept as a message.
+        reason: String,
+    },
+
+    /// A `source_paths` entry is not a valid glob pattern.
+    #[error("Invalid source path pattern '{pattern}': {error}")]
+    InvalidPattern {
+        /// The `source_paths` entry.
+        pattern: String,
+        /// The underlying glob error.
+        #[source]
+        error: globset::Error,
+    },
+
+    /// A glob pattern in `source_paths` matched no file.
+    #[error("Source path pattern '{pattern}' does not match any file")]
+    NoGlobMatches {
+        /// The `source_paths` entry.
+        pattern: String,
+    },
+
+    /// A repository has not been cloned into the local cache.
+    #[error("Repository '{repository_url}' has not been cloned to '{path}'")]
+    NotCloned {
+        /// The URL of the repository.
+        repository_url: String,
+        /// Where the clone was expected.
+        path: PathBuf,
+    },
+
+    /// A revision is not present in the local clone of a repository.
+    #[error("The local clone of '{repository_url}' does not contain {revision}: {error}")]
+    MissingRevision {
+        /// The URL of the repository.
+        repository_url: String,
+        /// A description of the missing revision.
+        revision: String,
+        /// The underlying git error.
+        #[source]
+        error: git2::Error,
+    },
+
+    /// Failed to download a release archive.
+    #[error("Failed to download archive '{archive_url}': {reason}")]
+    DownloadArchive {
+        /// The URL the archive was downloaded from.
+        archive_url: String,
+        /// Why the archive could not be downloaded.
+        reason: String,
+    },
+
+    /// A downloaded release archive does not have the expected checksum.
+    #[error("Archive '{archive_url}' has SHA-256 {actual} but {expected} was expected")]
+    ArchiveChecksum {
+        /// The URL the archive was downloaded from.
+        archive_url: String,
+        /// The checksum given in the metadata.
+        expected: String,
+        /// The checksum of the downloaded archive.
+        actual: String,
+    },
+
+    /// Failed to unpack a release archive.
+    #[error("Failed to unpack archive '{archive_url}': {error}")]
+    UnpackArchive {
+        /// The URL the archive was downloaded from.
+        archive_url: String,
+        /// The underlying I/O error.
+        #[source]
+        error: io::Error,
+    },
+
+    /// An archive given to `import` was not created by `export`.
+    #[error("'{path}' is not a corpus export: {reason}")]
+    InvalidExport {
+        /// The archive being imported.
+        path: PathBuf,
+        /// What is wrong with the archive.
+        reason: String,
+    },
+
+    /// A program pair selected by name is not in the metadata.
+    #[error("No program pair named '{program_name}'")]
+    UnknownPair {
+        /// The name that was selected.
+        program_name: String,
+    },
+
+    /// Failed to parse a metadata file.
+    #[error("Failed to parse '{path}': {error}")]
+    ParseMetadata {
+        /// The metadata file.
+        path: PathBuf,
+        /// The underlying parser error.
+        #[source]
+        error: ParserError,
+    },
+
+    /// A program pair has not been downloaded, so it has no files.
+    #[error("Program pair '{program_name}' has not been downloaded to '{path}'")]
+    NotDownloaded {
+        /// The name of the program pair.
+        program_name: String,
+        /// Where the pair's manifest was expected.
+        path: PathBuf,
+    },
+
+    /// Failed to create a thread pool.
+    #[error("Failed to create thread pool: {0}")]
+    ThreadPool(String),
+
     /// Failed to create a progress bar.
     #[error("Failed to create progress bar: {0}")]
     ProgressBar(String),
@@ -0,0 +1,903 @@
+//! # Corpus Export and Import
+//!
+//! The main entry points are [`export`], which packs the downloaded program
+//! pairs into a single file to hand to others, and [`import`], which unpacks
+//! such a file into the output directory without touching the network.
+//!
+//! A `tar.gz` export holds [`EXPORT_MANIFEST_FILE`], listing every program
+//! pair it contains, followed by `program_pairs/` with each pair's
+//! `pair.json` and copied files.  Only pairs that were fully downloaded, and
+//! only the files their manifests list, are exported.  Entries are sorted and
+//! have a fixed mtime, mode and owner, so exporting the same corpus twice
+//! gives the same bytes.
+//!
+//! A `jsonl` export is a dataset with one JSON record per line, for training
+//! and evaluating models.  By default each record is a program pair with the
+//! contents of all its C and Rust files; with `split_files`, each record is a
+//! single file together with the metadata of its pair.  Every file keeps its
+//! path in its original repository.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path},
+};
+
+use clap::ValueEnum;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::Serialize;
+
+use crate::{
+    corpus::{
+        errors::DownloaderError,
+        manifest::{PairManifest, ProgramManifest},
+        schema::{Features, Language, Metadata, Program},
+        utils,
+    },
+    paths::{EXPORT_MANIFEST_FILE, PAIR_MANIFEST_FILE, PROGRAM_PAIRS_DIRECTORY},
+};
+
+/// The extension of the directory an export is unpacked into before its
+/// pairs are moved into place.
+const IMPORT_STAGING_EXTENSION: &str = "importing";
+
+/// The file format of an exported corpus.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A reproducible gzip-compressed tarball, which `import` can unpack.
+    #[default]
+    #[value(name = "tar.gz")]
+    TarGz,
+    /// One JSON record per line, for machine learning pipelines.
+    Jsonl,
+}
+
+impl ExportFormat {
+    /// Returns the file an export is written to when none is given.
+    pub fn default_output(self) -> &'static str {
+        match self {
+            ExportFormat::TarGz => "corpus.tar.gz",
+            ExportFormat::Jsonl => "corpus.jsonl",
+        }
+    }
+}
+
+/// Options that control how the corpus is exported.
+#[derive(Debug, Default, Clone)]
+pub struct ExportOptions {
+    /// The file format to export to.
+    pub format: ExportFormat,
+    /// For `jsonl`, write one record per source file instead of one per
+    /// program pair.
+    pub split_files: bool,
+    /// For `jsonl`, skip records whose serialized line is longer than this
+    /// many bytes.
+    pub skip_records_over_bytes: Option<usize>,
+}
+
+/// What was exported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// The number of program pairs exported.
+    pub pairs: usize,
+    /// The `jsonl` records skipped for exceeding
+    /// [`ExportOptions::skip_records_over_bytes`].
+    pub skipped_records: Vec<SkippedRecord>,
+    /// The program pairs missing from the `jsonl` dataset because all their
+    /// records were skipped.  Pairs with only some records skipped are
+    /// exported without those files.
+    pub missing_pairs: Vec<String>,
+}
+
+/// A `jsonl` record that was skipped for being too long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRecord {
+    /// The name of the record's program pair.
+    pub program_name: String,
+    /// The repository path of the record's source file, if records hold
+    /// single files.
+    pub path: Option<String>,
+    /// The length of the record's serialized line, in bytes.
+    pub bytes: usize,
+}
+
+/// The metadata of a program pair, shared by every kind of `jsonl` record.
+#[derive(Serialize)]
+struct PairFields<'a> {
+    program_name: &'a str,
+    program_description: &'a str,
+    translation_tools: &'a [String],
+    feature_relationship: &'a Features,
+}
+
+/// A `jsonl` record holding a whole program pair.
+#[derive(Serialize)]
+struct PairRecord<'a> {
+    #[serde(flatten)]
+    pair: &'a PairFields<'a>,
+    c_program: ProgramRecord<'a>,
+    rust_program: ProgramRecord<'a>,
+}
+
+/// The source files of one program in a [`PairRecord`].
+#[derive(Serialize)]
+struct ProgramRecord<'a> {
+    repository_url: &'a str,
+    commit: &'a str,
+    files: Vec<SourceFile<'a>>,
+}
+
+/// A `jsonl` record holding a single source file.
+#[derive(Serialize)]
+struct FileRecord<'a> {
+    #[serde(flatten)]
+    pair: &'a PairFields<'a>,
+    language: &'a Language,
+    repository_url: &'a str,
+    commit: &'a str,
+    #[serde(flatten)]
+    file: SourceFile<'a>,
+}
+
+/// A source file and its path in its original repository.
+#[derive(Serialize)]
+struct SourceFile<'a> {
+    path: &'a str,
+    contents: String,
+}
+
+/// Exports the downloaded program pairs.
+///
+/// # Arguments
+///
+/// - `pairs_directory` - The directory the pairs were downloaded into,
+///   typically `program_pairs/`.
+/// - `options` - Options that control the export.
+/// - `output` - Where to write the export.
+///
+/// # Returns
+///
+/// An [`ExportSummary`] on success, or a [`DownloaderError`] on failure.
+pub fn export(
+    pairs_directory: &Path,
+    options: &ExportOptions,
+    output: &Path,
+) -> Result<ExportSummary, DownloaderError> {
+    match options.format {
+        ExportFormat::TarGz => Ok(ExportSummary {
+            pairs: export_tarball(pairs_directory, output)?,
+            skipped_records: Vec::new(),
+            missing_pairs: Vec::new(),
+        }),
+        ExportFormat::Jsonl => export_jsonl(pairs_directory, output, options),
+    }
+}
+
+/// Imports a `tar.gz` export.
+///
+/// # Arguments
+///
+/// - `archive` - The archive created by [`export`].
+/// - `pairs_directory` - The directory to unpack the pairs into, typically
+///   `program_pairs/`.
+///
+/// # Returns
+///
+/// The number of imported program pairs on success, or a
+/// [`DownloaderError`] on failure.
+pub fn import(archive: &Path, pairs_directory: &Path) -> Result<usize, DownloaderError> {
+    import_tarball(archive, pairs_directory)
+}
+
+/// Packs program pairs into a reproducible `tar.gz` archive.
+///
+/// # Arguments
+///
+/// - `pairs_directory` - The directory containing one directory per pair.
+/// - `output` - Where to write the archive.
+///
+/// # Returns
+///
+/// The number of exported program pairs on success, or a
+/// [`DownloaderError`] on failure.
+fn export_tarball(pairs_directory: &Path, output: &Path) -> Result<usize, DownloaderError> {
+    let manifests = PairManifest::read_all(pairs_directory)?;
+    let metadata = Metadata {
+        pairs: manifests
+            .iter()
+            .map(|manifest| manifest.pair.clone())
+            .collect(),
+    };
+    let metadata = serde_json::to_string_pretty(&metadata)
+        .map_err(|error| DownloaderError::SerializeManifest { error })?;
+
+    let to_error = |error| DownloaderError::IoCreate {
+        path: output.to_path_buf(),
+        error,
+    };
+    let file = File::create(output).map_err(to_error)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    append_file(
+        &mut builder,
+        EXPORT_MANIFEST_FILE,
+        (metadata + "\n").as_bytes(),
+    )
+    .map_err(to_error)?;
+
+    for manifest in &manifests {
+        let pair_directory = pairs_directory.join(&manifest.pair.program_name);
+        let mut files = vec![PAIR_MANIFEST_FILE.to_string()];
+        for (program_directory, program) in [
+            ("c-program", &manifest.c_program),
+            ("rust-program", &manifest.rust_program),
+        ] {
+            files.extend(
+                program
+                    .files
+                    .keys()
+                    .map(|file| format!("{program_directory}/{file}")),
+            );
+        }
+
+        for file in files {
+            let path = pair_directory.join(&file);
+            let contents = fs::read(&path).map_err(|error| DownloaderError::IoRead {
+                path: path.clone(),
+                error,
+            })?;
+            let archive_path = format!(
+                "{PROGRAM_PAIRS_DIRECTORY}/{}/{file}",
+                manifest.pair.program_name
+            );
+            append_file(&mut builder, &archive_path, &contents).map_err(to_error)?;
+        }
+    }
+
+    builder
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .map_err(to_error)?;
+    Ok(manifests.len())
+}
+
+/// Writes program pairs as a `jsonl` dataset.
+///
+/// # Arguments
+///
+/// - `pairs_directory` - The directory containing one directory per pair.
+/// - `output` - Where to write the dataset.
+/// - `options` - Options that control how the records are split, and which
+///   are skipped for being too long.
+///
+/// # Returns
+///
+/// An [`ExportSummary`] on success, or a [`DownloaderError`] on failure.
+fn export_jsonl(
+    pairs_directory: &Path,
+    output: &Path,
+    options: &ExportOptions,
+) -> Result<ExportSummary, DownloaderError> {
+    let manifests = PairManifest::read_all(pairs_directory)?;
+    let to_error = |error| DownloaderError::IoCreate {
+        path: output.to_path_buf(),
+        error,
+    };
+    let to_record = |record: Result<String, serde_json::Error>| {
+        record.map_err(|error| DownloaderError::SerializeRecord { error })
+    };
+    let mut writer = BufWriter::new(File::create(output).map_err(to_error)?);
+
+    let mut summary = ExportSummary {
+        pairs: 0,
+        skipped_records: Vec::new(),
+        missing_pairs: Vec::new(),
+    };
+    for manifest in &manifests {
+        let pair = &manifest.pair;
+        let fields = PairFields {
+            program_name: &pair.program_name,
+            program_description: &pair.program_description,
+            translation_tools: &pair.translation_tools,
+            feature_relationship: &pair.feature_relationship,
+        };
+        let pair_directory = pairs_directory.join(&pair.program_name);
+        let programs = [
+            (&pair.c_program, &manifest.c_program, "c-program"),
+            (&pair.rust_program, &manifest.rust_program, "rust-program"),
+        ];
+
+        // Each record with the repository path of its source file, if it
+        // holds a single one.
+        let mut records = Vec::new();
+        if options.split_files {
+            for (program, program_manifest, directory_name) in programs {
+                for file in read_sources(&pair_directory.join(directory_name), program_manifest)? {
+                    let path = file.path;
+                    let record = FileRecord {
+                        pair: &fields,
+                        language: &program.language,
+                        repository_url: program.source_url(),
+                        commit: &program_manifest.commit,
+                        file,
+                    };
+                    records.push((Some(path), to_record(serde_json::to_string(&record))?));
+                }
+            }
+        } else {
+            let [c_program, rust_program] =
+                programs.map(|(program, program_manifest, directory_name)| {
+                    read_sources(&pair_directory.join(directory_name), program_manifest)
+                        .map(|files| program_record(program, program_manifest, files))
+                });
+            let record = PairRecord {
+                pair: &fields,
+                c_program: c_program?,
+                rust_program: rust_program?,
+            };
+            records.push((None, to_record(serde_json::to_string(&record))?));
+        }
+
+        let mut exported = false;
+        for (path, record) in records {
+            if options
+                .skip_records_over_bytes
+                .is_some_and(|max| record.len() > max)
+            {
+                summary.skipped_records.push(SkippedRecord {
+                    program_name: pair.program_name.clone(),
+                    path: path.map(str::to_string),
+                    bytes: record.len(),
+                });
+                continue;
+            }
+            writeln!(writer, "{record}").map_err(to_error)?;
+            exported = true;
+        }
+        if exported {
+            summary.pairs += 1;
+        } else {
+            summary.missing_pairs.push(pair.program_name.clone());
+        }
+    }
+    writer.flush().map_err(to_error)?;
+    Ok(summary)
+}
+
+/// Reads the source files of a downloaded program.
+///
+/// Files that are not valid UTF-8 have their invalid bytes replaced.
+///
+/// # Arguments
+///
+/// - `program_directory` - The program's `c-program/` or `rust-program/`
+///   directory.
+/// - `manifest` - The program's manifest, listing its files.
+///
+/// # Returns
+///
+/// Each file with its path in its original repository, in the order of the
+/// manifest, on success, or a [`DownloaderError`] on failure.
+fn read_sources<'a>(
+    program_directory: &Path,
+    manifest: &'a ProgramManifest,
+) -> Result<Vec<SourceFile<'a>>, DownloaderError> {
+    manifest
+        .files
+        .iter()
+        .map(|(file, file_manifest)| {
+            let path = program_directory.join(file);
+            let contents =
+                fs::read(&path).map_err(|error| DownloaderError::IoRead { path, error })?;
+            Ok(SourceFile {
+                path: &file_manifest.source_path,
+                contents: String::from_utf8_lossy(&contents).into_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Creates the record of one program in a [`PairRecord`].
+///
+/// # Arguments
+///
+/// - `program` - The program's metadata.
+/// - `manifest` - The program's manifest.
+/// - `files` - The program's source files.
+///
+/// # Returns
+///
+/// The program's [`ProgramRecord`].
+fn program_record<'a>(
+    program: &'a Program,
+    manifest: &'a ProgramManifest,
+    files: Vec<SourceFile<'a>>,
+) -> ProgramRecord<'a> {
+    ProgramRecord {
+        repository_url: program.source_url(),
+        commit: &manifest.commit,
+        files,
+    }
+}
+
+/// Appends a regular file to a tarball, with a fixed mtime, mode and owner.
+///
+/// # Arguments
+///
+/// - `builder` - The tarball being written.
+/// - `path` - The path of the file within the tarball.
+/// - `contents` - The contents of the file.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or an [`io::Error`] on failure.
+fn append_file<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    contents: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    builder.append_data(&mut header, path, contents)
+}
+
+/// Unpacks a `tar.gz` export into a directory.
+///
+/// The whole archive is unpacked next to the directory first, so an archive
+/// that turns out to be invalid leaves the directory untouched.  Only then
+/// is the directory of every pair listed in the export's manifest replaced,
+/// so no stale files are left behind.  Other pairs are kept.  Entries that
+/// are not regular files, such as links, or that belong to no listed pair
+/// are rejected.
+///
+/// # Arguments
+///
+/// - `archive` - The archive created by [`export`].
+/// - `pairs_directory` - The directory to unpack the program pairs into.
+///
+/// # Returns
+///
+/// The number of imported program pairs on success, or a
+/// [`DownloaderError`] on failure.
+fn import_tarball(archive: &Path, pairs_directory: &Path) -> Result<usize, DownloaderError> {
+    let staging_path = utils::staging_path(pairs_directory, IMPORT_STAGING_EXTENSION);
+    remove_directory(&staging_path)?;
+    let result = unpack_tarball(archive, &staging_path);
+    let program_names = match result {
+        Ok(program_names) => program_names,
+        Err(error) => {
+            remove_directory(&staging_path)?;
+            return Err(error);
+        }
+    };
+
+    utils::mark_directory(pairs_directory)?;
+    for program_name in &program_names {
+        let pair_directory = pairs_directory.join(program_name);
+        remove_directory(&pair_directory)?;
+        let unpacked_directory = staging_path.join(program_name);
+        fs::rename(&unpacked_directory, &pair_directory).map_err(|error| {
+            DownloaderError::IoRename {
+                source: unpacked_directory,
+                destination: pair_directory,
+                error,
+            }
+        })?;
+    }
+    remove_directory(&staging_path)?;
+    Ok(program_names.len())
+}
+
+/// Checks a `tar.gz` export and unpacks its program pairs into a directory.
+///
+/// # Arguments
+///
+/// - `archive` - The archive created by [`export`].
+/// - `directory` - The directory to unpack the program pairs into, which
+///   must not exist yet.
+///
+/// # Returns
+///
+/// The names of the program pairs listed in the export's manifest on
+/// success, or a [`DownloaderError`] if the archive is not a valid export.
+fn unpack_tarball(archive: &Path, directory: &Path) -> Result<Vec<String>, DownloaderError> {
+    let invalid = |reason: String| DownloaderError::InvalidExport {
+        path: archive.to_path_buf(),
+        reason,
+    };
+    let to_read_error = |error| DownloaderError::IoRead {
+        path: archive.to_path_buf(),
+        error,
+    };
+
+    let file = File::open(archive).map_err(to_read_error)?;
+    let mut tarball = tar::Archive::new(GzDecoder::new(file));
+    let mut entries = tarball.entries().map_err(to_read_error)?;
+
+    // The manifest comes first, so the archive is checked before anything
+    // is unpacked.
+    let mut manifest = entries
+        .next()
+        .ok_or_else(|| invalid("the archive is empty".to_string()))?
+        .map_err(to_read_error)?;
+    if manifest.path().map_err(to_read_error)?.as_ref() != Path::new(EXPORT_MANIFEST_FILE) {
+        return Err(invalid(format!("'{EXPORT_MANIFEST_FILE}' is missing")));
+    }
+    let mut contents = String::new();
+    manifest
+        .read_to_string(&mut contents)
+        .map_err(to_read_error)?;
+    let metadata: Metadata =
+        serde_json::from_str(&contents).map_err(|error| DownloaderError::DeserializeManifest {
+            path: archive.join(EXPORT_MANIFEST_FILE),
+            error,
+        })?;
+    let mut program_names = Vec::new();
+    for pair in &metadata.pairs {
+        let mut components = Path::new(&pair.program_name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(invalid(format!(
+                "invalid program name '{}'",
+                pair.program_name
+            )));
+        }
+        program_names.push(pair.program_name.clone());
+    }
+
+    for entry in entries {
+        let mut entry = entry.map_err(to_read_error)?;
+        let path = entry.path().map_err(to_read_error)?.into_owned();
+        let relative_path = path
+            .strip_prefix(PROGRAM_PAIRS_DIRECTORY)
+            .ok()
+            .filter(|relative_path| is_relative_path(relative_path))
+            .filter(|relative_path| {
+                relative_path.components().nth(1).is_some()
+                    && program_names
+                        .iter()
+                        .any(|program_name| relative_path.starts_with(program_name))
+            })
+            .ok_or_else(|| invalid(format!("unexpected entry '{}'", path.display())))?;
+        // Exports only hold regular files, and a link could point the
+        // entries after it outside the directory.
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            return Err(invalid(format!(
+                "'{}' is not a regular file",
+                path.display()
+            )));
+        }
+
+        let destination = directory.join(relative_path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|error| DownloaderError::IoCreate {
+                path: parent.to_path_buf(),
+                error,
+            })?;
+        }
+        entry
+            .unpack(&destination)
+            .map_err(|error| DownloaderError::IoCreate {
+                path: destination.clone(),
+                error,
+            })?;
+    }
+
+    for program_name in &program_names {
+        if !directory
+            .join(program_name)
+            .join(PAIR_MANIFEST_FILE)
+            .is_file()
+        {
+            return Err(invalid(format!(
+                "'{PROGRAM_PAIRS_DIRECTORY}/{program_name}/{PAIR_MANIFEST_FILE}' is missing"
+            )));
+        }
+    }
+    Ok(program_names)
+}
+
+/// Removes a directory, if it exists.
+///
+/// # Arguments
+///
+/// - `directory` - The directory to remove.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+fn remove_directory(directory: &Path) -> Result<(), DownloaderError> {
+    if directory.exists() {
+        fs::remove_dir_all(directory).map_err(|error| DownloaderError::IoRemove {
+            path: directory.to_path_buf(),
+            error,
+        })?;
+    }
+    Ok(())
+}
+
+/// Checks that a path is relative and only descends, so it cannot escape
+/// the directory it is joined to.
+///
+/// # Arguments
+///
+/// - `path` - The path to check.
+///
+/// # Returns
+///
+/// `true` if `path` is not empty and all its components are normal.
+fn is_relative_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use tempfile::TempDir;
+
+    use crate::corpus::{
+        manifest::{FileManifest, ProgramManifest},
+        schema::{DEFAULT_EXTENSIONS, Features, Language, Program, ProgramPair},
+    };
+
+    /// Creates a downloaded program pair, with its manifest, and a stale
+    /// file that its manifest does not list.
+    fn create_pair(pairs_directory: &Path, program_name: &str) {
+        let program = |language, file: &str| {
+            let program = Program {
+                language,
+                documentation_url: "https://example.com".to_string(),
+                repository_url: "https://example.com/repository".to_string(),
+                source_paths: vec![format!("src/{file}")],
+                revision: None,
+                archive: None,
+                extensions: DEFAULT_EXTENSIONS.map(str::to_string).to_vec(),
+                crate_manifest: false,
+            };
+            let manifest = ProgramManifest {
+                commit: "0".repeat(40),
+                files: BTreeMap::from([(
+                    file.to_string(),
+                    FileManifest {
+                        source_path: format!("src/{file}"),
+                        sha256: String::new(),
+                    },
+                )]),
+            };
+            (program, manifest)
+        };
+        let (c_program, c_manifest) = program(Language::C, "main.c");
+        let (rust_program, rust_manifest) = program(Language::Rust, "main.rs");
+        let manifest = PairManifest {
+            pair: ProgramPair {
+                program_name: program_name.to_string(),
+                program_description: "A test program.".to_string(),
+                project: None,
+                translation_tools: vec!["manual".to_string()],
+                feature_relationship: Features::RustEquivalentToC,
+                c_program,
+                rust_program,
+            },
+            c_program: c_manifest,
+            rust_program: rust_manifest,
+        };
+
+        let pair_directory = pairs_directory.join(program_name);
+        for file in [
+            "c-program/main.c",
+            "rust-program/main.rs",
+            "c-program/stale.c",
+        ] {
+            let path = pair_directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        manifest
+            .write(&pair_directory.join(PAIR_MANIFEST_FILE))
+            .unwrap();
+    }
+
+    /// Tests that an export is reproducible, only holds the files listed in
+    /// the manifests, and imports back into the same files.
+    #[test]
+    fn test_export_import() {
+        let directory = TempDir::new().unwrap();
+        let pairs_directory = directory.path().join("program_pairs");
+        create_pair(&pairs_directory, "cat");
+        create_pair(&pairs_directory, "ls");
+        // A pair that failed to download has no manifest.
+        fs::create_dir_all(pairs_directory.join("failed/c-program")).unwrap();
+
+        let first = directory.path().join("first.tar.gz");
+        let second = directory.path().join("second.tar.gz");
+        assert_eq!(export_tarball(&pairs_directory, &first).unwrap(), 2);
+        assert_eq!(export_tarball(&pairs_directory, &second).unwrap(), 2);
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+        let imported_directory = directory.path().join("imported");
+        create_pair(&imported_directory, "cat");
+        fs::write(imported_directory.join("cat/old.txt"), "").unwrap();
+        assert_eq!(import_tarball(&first, &imported_directory).unwrap(), 2);
+
+        for file in [
+            "cat/c-program/main.c",
+            "ls/rust-program/main.rs",
+            "ls/pair.json",
+        ] {
+            assert_eq!(
+                fs::read(pairs_directory.join(file)).unwrap(),
+                fs::read(imported_directory.join(file)).unwrap()
+            );
+        }
+        assert!(!imported_directory.join("cat/old.txt").exists());
+        assert!(!imported_directory.join("ls/c-program/stale.c").exists());
+        assert!(!imported_directory.join("failed").exists());
+    }
+
+    /// Tests that `jsonl` records hold whole pairs or single files, with
+    /// their repository paths, and that records whose lines are too long
+    /// are skipped, along with pairs left without records.
+    #[test]
+    fn test_export_jsonl() {
+        let directory = TempDir::new().unwrap();
+        let pairs_directory = directory.path().join("program_pairs");
+        create_pair(&pairs_directory, "cat");
+        create_pair(&pairs_directory, "ls");
+        let output = directory.path().join("corpus.jsonl");
+        let read_records = || -> Vec<serde_json::Value> {
+            fs::read_to_string(&output)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+
+        let mut options = ExportOptions {
+            format: ExportFormat::Jsonl,
+            ..ExportOptions::default()
+        };
+        let summary = export_jsonl(&pairs_directory, &output, &options).unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                pairs: 2,
+                skipped_records: Vec::new(),
+                missing_pairs: Vec::new()
+            }
+        );
+        let records = read_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["program_name"], "cat");
+        assert_eq!(records[0]["feature_relationship"], "rust_equivalent_to_c");
+        assert_eq!(records[0]["c_program"]["files"][0]["path"], "src/main.c");
+        assert_eq!(
+            records[0]["rust_program"]["files"][0]["contents"],
+            "rust-program/main.rs"
+        );
+
+        // The records of the Rust files are longer than those of the C files.
+        options.split_files = true;
+        export_jsonl(&pairs_directory, &output, &options).unwrap();
+        let lengths: Vec<usize> = fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(str::len)
+            .collect();
+        assert_eq!(lengths.len(), 4);
+        let max_c_length = lengths[0].max(lengths[2]);
+        assert!(lengths[1].min(lengths[3]) > max_c_length);
+
+        options.skip_records_over_bytes = Some(max_c_length);
+        let summary = export_jsonl(&pairs_directory, &output, &options).unwrap();
+        let skipped_record = |program_name: &str, index| SkippedRecord {
+            program_name: program_name.to_string(),
+            path: Some("src/main.rs".to_string()),
+            bytes: lengths[index],
+        };
+        assert_eq!(
+            summary,
+            ExportSummary {
+                pairs: 2,
+                skipped_records: vec![skipped_record("cat", 1), skipped_record("ls", 3)],
+                missing_pairs: Vec::new()
+            }
+        );
+        let records = read_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["program_name"], "ls");
+        assert_eq!(records[1]["language"], "c");
+        assert_eq!(records[1]["path"], "src/main.c");
+        assert_eq!(records[1]["contents"], "c-program/main.c");
+
+        // A skipped pair record leaves the whole pair out of the dataset.
+        options.split_files = false;
+        let summary = export_jsonl(&pairs_directory, &output, &options).unwrap();
+        assert_eq!(summary.pairs, 0);
+        assert_eq!(summary.skipped_records.len(), 2);
+        assert_eq!(summary.missing_pairs, ["cat", "ls"]);
+        assert!(read_records().is_empty());
+    }
+
+    /// Tests that an archive not created by `export` is rejected before
+    /// anything is unpacked.
+    #[test]
+    fn test_import_invalid() {
+        let directory = TempDir::new().unwrap();
+        let archive = directory.path().join("other.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        append_file(&mut builder, "program_pairs/cat/pair.json", b"{}").unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let pairs_directory = directory.path().join("program_pairs");
+        let result = import_tarball(&archive, &pairs_directory);
+        assert!(matches!(result, Err(DownloaderError::InvalidExport { .. })));
+        assert!(!pairs_directory.exists());
+    }
+
+    /// Tests that an archive holding a link, which would let the entries
+    /// after it escape the directory, or an entry outside the pairs it
+    /// lists, is rejected before any pair is replaced.
+    #[test]
+    fn test_import_malicious() {
+        let directory = TempDir::new().unwrap();
+        let pairs_directory = directory.path().join("program_pairs");
+        create_pair(&pairs_directory, "cat");
+        let manifest = PairManifest::read(&pairs_directory.join("cat/pair.json")).unwrap();
+        let metadata = serde_json::to_vec(&Metadata {
+            pairs: vec![manifest.pair],
+        })
+        .unwrap();
+        let outside = directory.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let archive = directory.path().join("malicious.tar.gz");
+
+        for (link, path) in [
+            (true, "program_pairs/cat/src/evil.c"),
+            (false, "program_pairs/ls/evil.c"),
+            (false, "program_pairs/evil.c"),
+        ] {
+            let mut builder = tar::Builder::new(GzEncoder::new(
+                File::create(&archive).unwrap(),
+                Compression::default(),
+            ));
+            append_file(&mut builder, EXPORT_MANIFEST_FILE, &metadata).unwrap();
+            append_file(&mut builder, "program_pairs/cat/pair.json", b"{}").unwrap();
+            if link {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                header.set_mode(0o777);
+                builder
+                    .append_link(&mut header, "program_pairs/cat/src", &outside)
+                    .unwrap();
+            }
+            append_file(&mut builder, path, b"evil").unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+
+            let result = import_tarball(&archive, &pairs_directory);
+            assert!(
+                matches!(result, Err(DownloaderError::InvalidExport { .. })),
+                "{path}"
+            );
+            assert!(pairs_directory.join("cat/c-program/stale.c").exists());
+            assert!(!pairs_directory.join("ls").exists());
+            assert!(!outside.join("evil.c").exists());
+            assert!(!utils::staging_path(&pairs_directory, IMPORT_STAGING_EXTENSION).exists());
+        }
+    }
+}
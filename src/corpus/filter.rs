@@ -0,0 +1,147 @@
+//! # Program Pair Filters
+//!
+//! A [`PairFilter`] selects program pairs by their name, project, feature
+//! relationship or translation tools.  A pair must match every kind of
+//! criterion given, and any one of the values given for each.
+
+use crate::corpus::{
+    errors::DownloaderError,
+    schema::{Features, ProgramPair},
+};
+
+/// Criteria that select program pairs.  Empty criteria select every pair.
+#[derive(Debug, Default, Clone)]
+pub struct PairFilter {
+    /// Select pairs with one of these program names.
+    pub names: Vec<String>,
+    /// Select pairs from one of these projects, compared case-insensitively.
+    pub projects: Vec<String>,
+    /// Select pairs with one of these feature relationships.
+    pub features: Vec<Features>,
+    /// Select pairs translated with one of these tools, compared
+    /// case-insensitively.
+    pub tools: Vec<String>,
+}
+
+impl PairFilter {
+    /// Checks whether the filter has no criteria, and so selects every pair.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.projects.is_empty()
+            && self.features.is_empty()
+            && self.tools.is_empty()
+    }
+
+    /// Selects the matching program pairs.
+    ///
+    /// # Arguments
+    ///
+    /// - `pairs` - The program pairs to select from.
+    ///
+    /// # Returns
+    ///
+    /// The matching pairs, in their original order, on success, or
+    /// [`DownloaderError::UnknownPair`] if a name given in
+    /// [`PairFilter::names`] is not the name of any pair.
+    pub fn select(&self, pairs: Vec<ProgramPair>) -> Result<Vec<ProgramPair>, DownloaderError> {
+        if let Some(name) = self
+            .names
+            .iter()
+            .find(|name| !pairs.iter().any(|pair| pair.program_name == **name))
+        {
+            return Err(DownloaderError::UnknownPair {
+                program_name: name.clone(),
+            });
+        }
+        Ok(pairs
+            .into_iter()
+            .filter(|pair| self.matches(pair))
+            .collect())
+    }
+
+    /// Checks whether a program pair is selected.
+    ///
+    /// # Arguments
+    ///
+    /// - `pair` - The program pair to check.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `pair` matches every criterion.
+    pub fn matches(&self, pair: &ProgramPair) -> bool {
+        let contains = |values: &[String], value: &str| {
+            values
+                .iter()
+                .any(|candidate| candidate.eq_ignore_ascii_case(value))
+        };
+
+        (self.names.is_empty() || self.names.contains(&pair.program_name))
+            && (self.projects.is_empty()
+                || pair
+                    .project
+                    .as_ref()
+                    .is_some_and(|project| contains(&self.projects, project)))
+            && (self.features.is_empty() || self.features.contains(&pair.feature_relationship))
+            && (self.tools.is_empty()
+                || pair
+                    .translation_tools
+                    .iter()
+                    .any(|tool| contains(&self.tools, tool)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use crate::{corpus::parser, paths::PROJECT_METADATA_DIRECTORY};
+
+    /// Tests that each criterion is applied, and that they are combined.
+    #[test]
+    fn test_matches() {
+        let metadata =
+            parser::parse(&Path::new(PROJECT_METADATA_DIRECTORY).join("coreutils.json")).unwrap();
+        let pair = &metadata.pairs[0];
+        assert_eq!(pair.project.as_deref(), Some("coreutils"));
+
+        let filter = |projects: &[&str], features: &[Features], tools: &[&str]| PairFilter {
+            names: Vec::new(),
+            projects: projects.iter().map(|project| project.to_string()).collect(),
+            features: features.to_vec(),
+            tools: tools.iter().map(|tool| tool.to_string()).collect(),
+        };
+        assert!(filter(&[], &[], &[]).matches(pair));
+        assert!(filter(&["CoreUtils", "procps"], &[], &[]).matches(pair));
+        assert!(!filter(&["procps"], &[], &[]).matches(pair));
+        assert!(filter(&[], &[Features::RustEquivalentToC], &["manual"]).matches(pair));
+        assert!(!filter(&["coreutils"], &[Features::Overlapping], &[]).matches(pair));
+        assert!(!filter(&[], &[], &["c2rust"]).matches(pair));
+    }
+
+    /// Tests that pairs are selected by name, and that unknown names are
+    /// reported.
+    #[test]
+    fn test_select() {
+        let metadata =
+            parser::parse(&Path::new(PROJECT_METADATA_DIRECTORY).join("coreutils.json")).unwrap();
+        let mut filter = PairFilter {
+            names: vec!["ls".to_string(), "cat".to_string()],
+            ..PairFilter::default()
+        };
+        let names: Vec<_> = filter
+            .select(metadata.pairs.clone())
+            .unwrap()
+            .into_iter()
+            .map(|pair| pair.program_name)
+            .collect();
+        assert_eq!(names, ["cat", "ls"]);
+
+        filter.names.push("procps".to_string());
+        assert!(matches!(
+            filter.select(metadata.pairs),
+            Err(DownloaderError::UnknownPair { program_name }) if program_name == "procps"
+        ));
+    }
+}
@@ -0,0 +1,399 @@
+//! # Git Access
+//!
+//! Clones are read and written without the user's or system's git
+//! configuration, so settings such as `core.autocrlf` or
+//! `url.<base>.insteadOf` on the machine running the downloader cannot
+//! change the corpus.  Rather than hiding that configuration from the whole
+//! process, each repository is opened with only its own configuration,
+//! unless `use_git_config` is set in the network settings.
+//!
+//! The network settings also give the proxy, certificate authorities and
+//! credentials used to reach remote repositories.  They apply to each
+//! transfer through its callbacks, never to libgit2's global state.
+
+use std::path::Path;
+
+use git2::{
+    CertificateCheckStatus, Cred, CredentialType, ErrorClass, ErrorCode, ProxyOptions, Repository,
+    cert::Cert,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject};
+use webpki::{EndEntityCert, KeyUsage};
+
+use crate::corpus::{config::NetworkConfig, errors::DownloaderError};
+
+/// The certificate authorities of a configured bundle, which the
+/// certificates of servers are checked against instead of the system's.
+pub struct CaBundle {
+    /// The certificates of the bundle, which are both trusted and used as
+    /// intermediates, since git only passes on the server's own certificate.
+    certificates: Vec<CertificateDer<'static>>,
+}
+
+impl CaBundle {
+    /// Reads the bundle of certificate authorities of the network settings.
+    ///
+    /// # Arguments
+    ///
+    /// - `network` - The network settings.
+    ///
+    /// # Returns
+    ///
+    /// The [`CaBundle`], or `None` if no bundle is configured, on success,
+    /// or [`DownloaderError::CaBundle`] if the bundle cannot be read or
+    /// holds no certificates.
+    pub fn read(network: &NetworkConfig) -> Result<Option<Self>, DownloaderError> {
+        let Some(ca_bundle) = &network.ca_bundle else {
+            return Ok(None);
+        };
+        let to_error = |reason: String| DownloaderError::CaBundle {
+            path: ca_bundle.clone(),
+            reason,
+        };
+        let certificates = CertificateDer::pem_file_iter(ca_bundle)
+            .map_err(|error| to_error(error.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| to_error(error.to_string()))?;
+        if certificates.is_empty() {
+            return Err(to_error("no certificates".to_string()));
+        }
+        Ok(Some(CaBundle { certificates }))
+    }
+
+    /// Returns the callback that accepts the certificates of servers issued
+    /// by an authority in the bundle, and rejects all others.
+    ///
+    /// # Returns
+    ///
+    /// A callback for [`git2::RemoteCallbacks::certificate_check`].
+    pub fn certificate_check(
+        &self,
+    ) -> impl FnMut(&Cert<'_>, &str) -> Result<CertificateCheckStatus, git2::Error> + '_ {
+        move |certificate, hostname| {
+            // SSH host keys are left to git.
+            let Some(certificate) = certificate.as_x509() else {
+                return Ok(CertificateCheckStatus::CertificatePassthrough);
+            };
+            self.verify(certificate.data(), hostname)
+                .map(|()| CertificateCheckStatus::CertificateOk)
+                .map_err(|error| {
+                    git2::Error::new(
+                        ErrorCode::Certificate,
+                        ErrorClass::Ssl,
+                        format!(
+                            "the certificate of '{hostname}' is not trusted by the CA bundle: \
+                             {error}"
+                        ),
+                    )
+                })
+        }
+    }
+
+    /// Checks that a server's certificate is valid for its host name and
+    /// issued by an authority in the bundle.
+    ///
+    /// # Arguments
+    ///
+    /// - `certificate` - The DER-encoded certificate of the server.
+    /// - `hostname` - The host name of the server.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the certificate is trusted, or the
+    /// [`webpki::Error`] of why not otherwise.
+    fn verify(&self, certificate: &[u8], hostname: &str) -> Result<(), webpki::Error> {
+        let trust_anchors: Vec<_> = self
+            .certificates
+            .iter()
+            .filter_map(|certificate| webpki::anchor_from_trusted_cert(certificate).ok())
+            .collect();
+        let certificate = CertificateDer::from(certificate);
+        let end_entity = EndEntityCert::try_from(&certificate)?;
+        end_entity.verify_for_usage(
+            webpki::ALL_VERIFICATION_ALGS,
+            &trust_anchors,
+            &self.certificates,
+            UnixTime::now(),
+            KeyUsage::server_auth(),
+            None,
+            None,
+        )?;
+        let server_name =
+            ServerName::try_from(hostname).map_err(|_| webpki::Error::MalformedDnsIdentifier)?;
+        end_entity.verify_is_valid_for_subject_name(&server_name)
+    }
+}
+
+/// Opens a repository.
+///
+/// # Arguments
+///
+/// - `path` - The repository.
+/// - `use_git_config` - Whether to read the user's and system's git
+///   configuration as well as the repository's.
+///
+/// # Returns
+///
+/// The [`Repository`] on success, or a [`git2::Error`] on failure.
+pub fn open(path: &Path, use_git_config: bool) -> Result<Repository, git2::Error> {
+    let repository = Repository::open(path)?;
+    isolate_config(&repository, use_git_config)?;
+    Ok(repository)
+}
+
+/// Creates an empty repository.
+///
+/// # Arguments
+///
+/// - `path` - Where to create the repository.
+/// - `use_git_config` - Whether to read the user's and system's git
+///   configuration as well as the repository's.
+///
+/// # Returns
+///
+/// The new [`Repository`] on success, or a [`git2::Error`] on failure.
+pub fn init(path: &Path, use_git_config: bool) -> Result<Repository, git2::Error> {
+    let repository = Repository::init(path)?;
+    isolate_config(&repository, use_git_config)?;
+    Ok(repository)
+}
+
+/// Makes a repository read only its own configuration file.
+///
+/// # Arguments
+///
+/// - `repository` - The repository.
+/// - `use_git_config` - Whether to leave the user's and system's git
+///   configuration in place instead.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`git2::Error`] on failure.
+fn isolate_config(repository: &Repository, use_git_config: bool) -> Result<(), git2::Error> {
+    if use_git_config {
+        return Ok(());
+    }
+    let mut config = git2::Config::new()?;
+    config.add_file(
+        &repository.path().join("config"),
+        git2::ConfigLevel::Local,
+        false,
+    )?;
+    repository.set_config(&config)
+}
+
+/// Returns the proxy to fetch repositories through.
+///
+/// # Arguments
+///
+/// - `network` - The network settings.
+///
+/// # Returns
+///
+/// The configured proxy, or the [`ProxyOptions`] that detect it from the
+/// environment and the git configuration otherwise.
+pub fn proxy_options(network: &NetworkConfig) -> ProxyOptions<'_> {
+    let mut proxy_options = ProxyOptions::new();
+    match &network.proxy {
+        Some(proxy) => proxy_options.url(proxy),
+        None => proxy_options.auto(),
+    };
+    proxy_options
+}
+
+/// Returns the callback that supplies credentials to repositories that
+/// need them.
+///
+/// SSH repositories use the keys of the SSH agent.  HTTPS repositories use
+/// the configured credential for their URL, then, if `use_git_config` is
+/// set, git's credential helpers.  Since git asks again whenever the
+/// credentials it was given are rejected, each source is only tried once.
+///
+/// # Arguments
+///
+/// - `network` - The network settings.
+///
+/// # Returns
+///
+/// A callback for [`git2::RemoteCallbacks::credentials`].
+pub fn credentials(
+    network: &NetworkConfig,
+) -> impl FnMut(&str, Option<&str>, CredentialType) -> Result<Cred, git2::Error> + '_ {
+    let mut tried_agent = false;
+    let mut tried_configured = false;
+    let mut tried_helper = false;
+    move |url, username, allowed_types| {
+        let username = username.unwrap_or("git");
+        if allowed_types.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        if allowed_types.contains(CredentialType::SSH_KEY) && !tried_agent {
+            tried_agent = true;
+            return Cred::ssh_key_from_agent(username);
+        }
+        if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if !tried_configured && let Some(credential) = network.credential(url) {
+                tried_configured = true;
+                let password = credential.password().ok_or_else(|| {
+                    auth_error(format!(
+                        "environment variable '{}' is not set",
+                        credential.password_env
+                    ))
+                })?;
+                return Cred::userpass_plaintext(&credential.username, &password);
+            }
+            if network.use_git_config && !tried_helper {
+                tried_helper = true;
+                return Cred::credential_helper(
+                    &git2::Config::open_default()?,
+                    url,
+                    Some(username),
+                );
+            }
+        }
+        Err(auth_error(format!("no credentials accepted for '{url}'")))
+    }
+}
+
+/// Creates an authentication error, which is never retried.
+///
+/// # Arguments
+///
+/// - `message` - Why authentication failed.
+///
+/// # Returns
+///
+/// The [`git2::Error`].
+fn auth_error(message: String) -> git2::Error {
+    git2::Error::new(ErrorCode::Auth, ErrorClass::Net, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::corpus::config::Credential;
+
+    /// A certificate authority that issued [`SERVER_CERTIFICATE`].
+    const CA_CERTIFICATE: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBjDCCATGgAwIBAgIUfzLCiwby2z/GnDTx0Of3CuKbEzcwCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTcwMzA4NDdaGA8yMTI2MDkyMzAz
+MDg0N1owEjEQMA4GA1UEAwwHVGVzdCBDQTBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABFqOomica1PRtmKvDhYlTVt+Rn9TwvIeXxtHnSf1VNSY7E+0pKO1962aAui5
+NE+9HQcI6nj6yYx3Z+8aUor+XrKjYzBhMB0GA1UdDgQWBBRUorMBh/h8k7vLj2iN
+XZHttTYQvDAfBgNVHSMEGDAWgBRUorMBh/h8k7vLj2iNXZHttTYQvDAPBgNVHRMB
+Af8EBTADAQH/MA4GA1UdDwEB/wQEAwICBDAKBggqhkjOPQQDAgNJADBGAiEA06Ow
+lcuMPOVK3v6+Vw61chtE6ONLr45w9JzOjJdOjD4CIQCFP/idVuvV0AETH6FenXzU
+I2mMpua5p8NwSKGDgAXhpQ==
+-----END CERTIFICATE-----
+";
+
+    /// The certificate of `git.example.com`, valid until 2126.
+    const SERVER_CERTIFICATE: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBrjCCAVSgAwIBAgIUdeXWdFi5MkBuKmzN4YcHeTypcLwwCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTcwMzA4NDdaGA8yMTI2MDkyMzAz
+MDg0N1owGjEYMBYGA1UEAwwPZ2l0LmV4YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAEB6nNLGaeBw7av5jJlLQnA6j4w4mIl31E2aQCdVzG97CSPS9Y
+EKVGxwTlP7p55VlTfc0Vetn0qkrrf6VygUWNhaN+MHwwGgYDVR0RBBMwEYIPZ2l0
+LmV4YW1wbGUuY29tMAkGA1UdEwQCMAAwEwYDVR0lBAwwCgYIKwYBBQUHAwEwHQYD
+VR0OBBYEFOjfJt+7nYUH7nv0bAGKqCIQrDKVMB8GA1UdIwQYMBaAFFSiswGH+HyT
+u8uPaI1dke21NhC8MAoGCCqGSM49BAMCA0gAMEUCIGw1rlOB6slYjwkPa7apaIIY
+iiorMf1iqZLcqctsGZa+AiEAkT4+n7ws2lqeH0JAFUUpPteWhGqkW5edLuPd2rWE
+IKY=
+-----END CERTIFICATE-----
+";
+
+    /// Tests that an isolated repository reads its own configuration, but
+    /// not the user's or system's.
+    #[test]
+    fn test_isolate_config() {
+        let directory = TempDir::new().unwrap();
+        let repository = init(directory.path(), false).unwrap();
+        repository
+            .remote("origin", "https://github.com/uutils/coreutils")
+            .unwrap();
+
+        let repository = open(directory.path(), false).unwrap();
+        let config = repository.config().unwrap();
+        assert_eq!(
+            "https://github.com/uutils/coreutils",
+            config.get_string("remote.origin.url").unwrap()
+        );
+        for level in [
+            git2::ConfigLevel::System,
+            git2::ConfigLevel::XDG,
+            git2::ConfigLevel::Global,
+        ] {
+            assert!(config.open_level(level).is_err());
+        }
+    }
+
+    /// Tests that the configured credential is tried once, and that
+    /// credentials are then refused.
+    #[test]
+    fn test_credentials() {
+        // Cargo sets this variable when running tests.
+        let network = NetworkConfig {
+            credentials: vec![Credential {
+                url_prefix: "https://git.example.com/".to_string(),
+                username: "corpus".to_string(),
+                password_env: "CARGO_PKG_NAME".to_string(),
+            }],
+            ..NetworkConfig::default()
+        };
+        let mut credentials = credentials(&network);
+        let mut ask = || {
+            credentials(
+                "https://git.example.com/ls",
+                None,
+                CredentialType::USER_PASS_PLAINTEXT,
+            )
+        };
+        assert!(ask().is_ok());
+        assert_eq!(ErrorCode::Auth, ask().err().unwrap().code());
+    }
+
+    /// Tests that a CA bundle must hold certificates, and only trusts the
+    /// certificates it issued for the host they were issued for.
+    #[test]
+    fn test_ca_bundle() {
+        let directory = TempDir::new().unwrap();
+        let ca_bundle = directory.path().join("ca.pem");
+        let mut network = NetworkConfig::default();
+        assert!(CaBundle::read(&network).unwrap().is_none());
+
+        network.ca_bundle = Some(ca_bundle.clone());
+        let result = CaBundle::read(&network);
+        assert!(matches!(result, Err(DownloaderError::CaBundle { .. })));
+        fs::write(&ca_bundle, "no certificates").unwrap();
+        let result = CaBundle::read(&network);
+        assert!(matches!(result, Err(DownloaderError::CaBundle { .. })));
+
+        fs::write(&ca_bundle, CA_CERTIFICATE).unwrap();
+        let bundle = CaBundle::read(&network).unwrap().unwrap();
+        let server_certificate =
+            CertificateDer::from_pem_slice(SERVER_CERTIFICATE.as_bytes()).unwrap();
+        assert!(
+            bundle
+                .verify(&server_certificate, "git.example.com")
+                .is_ok()
+        );
+        assert!(bundle.verify(&server_certificate, "example.com").is_err());
+
+        fs::write(&ca_bundle, SERVER_CERTIFICATE).unwrap();
+        let bundle = CaBundle::read(&network).unwrap().unwrap();
+        let ca_certificate = CertificateDer::from_pem_slice(CA_CERTIFICATE.as_bytes()).unwrap();
+        assert!(bundle.verify(&ca_certificate, "git.example.com").is_err());
+        assert!(
+            bundle
+                .verify(b"not a certificate", "git.example.com")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! # Corpus Loading
+//!
+//! A [`Corpus`] holds the program pairs parsed from a set of metadata files,
+//! and is the entry point for tools built on this crate rather than on the
+//! command line.  It looks pairs up by name, filters them, and lists the
+//! files each pair was downloaded to.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use c_rust_program_pairs::{Corpus, PairFilter};
+//!
+//! let corpus = Corpus::load_default()?;
+//! let filter = PairFilter {
+//!     projects: vec!["coreutils".to_string()],
+//!     ..PairFilter::default()
+//! };
+//! for pair in corpus.filter(&filter) {
+//!     let files = corpus.files(pair)?;
+//!     println!("{}: {:?} {:?}", pair.program_name, files.c_program.files, files.rust_program.files);
+//! }
+//! # Ok::<(), c_rust_program_pairs::DownloaderError>(())
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    slice,
+};
+
+use crate::{
+    corpus::{
+        self, downloader,
+        errors::DownloaderError,
+        filter::PairFilter,
+        manifest::{PairManifest, ProgramManifest},
+        schema::ProgramPair,
+    },
+    paths::{PAIR_MANIFEST_FILE, PROGRAM_PAIRS_DIRECTORY},
+};
+
+/// The program pairs of a set of metadata files.
+#[derive(Debug, Clone)]
+pub struct Corpus {
+    /// The pairs, in the order of their metadata files.
+    pairs: Vec<ProgramPair>,
+    /// The directory the pairs are downloaded into.
+    pairs_directory: PathBuf,
+}
+
+/// The downloaded files of one program pair.
+#[derive(Debug, Clone)]
+pub struct PairFiles {
+    /// The pair's directory, holding `c-program/`, `rust-program/` and its
+    /// `pair.json` manifest.
+    pub directory: PathBuf,
+    /// The files of the C program.
+    pub c_program: ProgramFiles,
+    /// The files of the Rust program.
+    pub rust_program: ProgramFiles,
+}
+
+/// The downloaded files of one C or Rust program.
+#[derive(Debug, Clone)]
+pub struct ProgramFiles {
+    /// The SHA of the commit the files were copied from.
+    pub commit: String,
+    /// The paths of the copied files, sorted.
+    pub files: Vec<PathBuf>,
+}
+
+impl Corpus {
+    /// Loads the program pairs of the given metadata.
+    ///
+    /// Unlike the downloader, which skips metadata files it cannot parse,
+    /// loading fails on the first such file.
+    ///
+    /// # Arguments
+    ///
+    /// - `metadata` - Metadata JSON files, or directories containing them.
+    ///
+    /// # Returns
+    ///
+    /// The [`Corpus`] on success, or a [`DownloaderError`] if a directory
+    /// cannot be read or a metadata file cannot be parsed.
+    pub fn load(metadata: &[PathBuf]) -> Result<Self, DownloaderError> {
+        let mut pairs = Vec::new();
+        for metadata_file in downloader::metadata_files(metadata)? {
+            let metadata =
+                corpus::parse(&metadata_file).map_err(|error| DownloaderError::ParseMetadata {
+                    path: metadata_file.clone(),
+                    error,
+                })?;
+            pairs.extend(metadata.pairs);
+        }
+        Ok(Self::from_pairs(pairs))
+    }
+
+    /// Loads the program pairs of `metadata/project/` and
+    /// `metadata/individual/`.
+    ///
+    /// # Returns
+    ///
+    /// The [`Corpus`] on success, or a [`DownloaderError`] on failure.
+    pub fn load_default() -> Result<Self, DownloaderError> {
+        Self::load(&downloader::metadata_directories(false))
+    }
+
+    /// Creates a corpus of the given program pairs.
+    ///
+    /// # Arguments
+    ///
+    /// - `pairs` - The program pairs.
+    ///
+    /// # Returns
+    ///
+    /// A [`Corpus`] whose pairs are downloaded into `program_pairs/`.
+    pub fn from_pairs(pairs: Vec<ProgramPair>) -> Self {
+        Self {
+            pairs,
+            pairs_directory: PathBuf::from(PROGRAM_PAIRS_DIRECTORY),
+        }
+    }
+
+    /// Sets the directory the pairs were downloaded into.
+    ///
+    /// # Arguments
+    ///
+    /// - `pairs_directory` - The directory holding one directory per pair,
+    ///   `program_pairs/` by default.
+    ///
+    /// # Returns
+    ///
+    /// The [`Corpus`], looking up downloaded files in `pairs_directory`.
+    pub fn with_pairs_directory(mut self, pairs_directory: impl Into<PathBuf>) -> Self {
+        self.pairs_directory = pairs_directory.into();
+        self
+    }
+
+    /// Returns every program pair, in the order of their metadata files.
+    pub fn pairs(&self) -> &[ProgramPair] {
+        &self.pairs
+    }
+
+    /// Returns an iterator over every program pair.
+    pub fn iter(&self) -> slice::Iter<'_, ProgramPair> {
+        self.pairs.iter()
+    }
+
+    /// Returns the number of program pairs.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Checks whether the corpus has no program pairs.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Looks up a program pair by name.
+    ///
+    /// # Arguments
+    ///
+    /// - `program_name` - The pair's `program_name`.
+    ///
+    /// # Returns
+    ///
+    /// The first pair with that name, or `None` if there is none.
+    pub fn get(&self, program_name: &str) -> Option<&ProgramPair> {
+        self.pairs
+            .iter()
+            .find(|pair| pair.program_name == program_name)
+    }
+
+    /// Returns the program pairs a filter selects.
+    ///
+    /// # Arguments
+    ///
+    /// - `filter` - The filter to apply.
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the matching pairs, in their original order.
+    pub fn filter<'a>(
+        &'a self,
+        filter: &'a PairFilter,
+    ) -> impl Iterator<Item = &'a ProgramPair> + 'a {
+        self.pairs.iter().filter(|pair| filter.matches(pair))
+    }
+
+    /// Lists the downloaded files of a program pair.
+    ///
+    /// The files are read from the `pair.json` manifest written when the pair
+    /// was downloaded, so only files copied from the repositories are listed.
+    ///
+    /// # Arguments
+    ///
+    /// - `pair` - The program pair.
+    ///
+    /// # Returns
+    ///
+    /// The pair's [`PairFiles`] on success,
+    /// [`DownloaderError::NotDownloaded`] if the pair has no manifest, or
+    /// another [`DownloaderError`] if its manifest cannot be read.
+    pub fn files(&self, pair: &ProgramPair) -> Result<PairFiles, DownloaderError> {
+        let directory = self.pairs_directory.join(&pair.program_name);
+        let manifest_path = directory.join(PAIR_MANIFEST_FILE);
+        if !manifest_path.is_file() {
+            return Err(DownloaderError::NotDownloaded {
+                program_name: pair.program_name.clone(),
+                path: manifest_path,
+            });
+        }
+
+        let manifest = PairManifest::read(&manifest_path)?;
+        Ok(PairFiles {
+            c_program: program_files(&directory.join("c-program"), &manifest.c_program),
+            rust_program: program_files(&directory.join("rust-program"), &manifest.rust_program),
+            directory,
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a Corpus {
+    type Item = &'a ProgramPair;
+    type IntoIter = slice::Iter<'a, ProgramPair>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Lists the downloaded files of one program from its manifest.
+///
+/// # Arguments
+///
+/// - `program_directory` - The `c-program/` or `rust-program/` directory.
+/// - `manifest` - The program's part of the pair manifest.
+///
+/// # Returns
+///
+/// The program's [`ProgramFiles`].
+fn program_files(program_directory: &Path, manifest: &ProgramManifest) -> ProgramFiles {
+    ProgramFiles {
+        commit: manifest.commit.clone(),
+        files: manifest
+            .files
+            .keys()
+            .map(|path| program_directory.join(path))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{collections::BTreeMap, fs};
+
+    use tempfile::TempDir;
+
+    use crate::{corpus::manifest::FileManifest, paths::PROJECT_METADATA_DIRECTORY};
+
+    /// Tests that pairs are looked up by name and filtered, and that their
+    /// files are listed from their manifests.
+    #[test]
+    fn test_corpus() {
+        let directory = TempDir::new().unwrap();
+        let corpus = Corpus::load(&[Path::new(PROJECT_METADATA_DIRECTORY).join("coreutils.json")])
+            .unwrap()
+            .with_pairs_directory(directory.path());
+        assert!(corpus.get("procps").is_none());
+        let pair = corpus.get("cat").unwrap();
+
+        let filter = PairFilter {
+            names: vec!["cat".to_string(), "ls".to_string()],
+            ..PairFilter::default()
+        };
+        let names: Vec<_> = corpus
+            .filter(&filter)
+            .map(|pair| pair.program_name.as_str())
+            .collect();
+        assert_eq!(names, ["cat", "ls"]);
+
+        assert!(matches!(
+            corpus.files(pair),
+            Err(DownloaderError::NotDownloaded { .. })
+        ));
+
+        let program_manifest = |file: &str| ProgramManifest {
+            commit: "0".repeat(40),
+            files: BTreeMap::from([(
+                file.to_string(),
+                FileManifest {
+                    source_path: format!("src/{file}"),
+                    sha256: "0".repeat(64),
+                },
+            )]),
+        };
+        let pair_directory = directory.path().join("cat");
+        fs::create_dir_all(&pair_directory).unwrap();
+        PairManifest {
+            pair: pair.clone(),
+            c_program: program_manifest("cat.c"),
+            rust_program: program_manifest("cat.rs"),
+        }
+        .write(&pair_directory.join(PAIR_MANIFEST_FILE))
+        .unwrap();
+
+        let files = corpus.files(pair).unwrap();
+        assert_eq!(
+            files.c_program.files,
+            [pair_directory.join("c-program/cat.c")]
+        );
+        assert_eq!(
+            files.rust_program.files,
+            [pair_directory.join("rust-program/cat.rs")]
+        );
+    }
+}
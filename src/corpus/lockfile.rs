@@ -0,0 +1,274 @@
+//! # Corpus Lockfile
+//!
+//! This module records the exact state of a downloaded corpus in
+//! `corpus.lock.json`: for every program pair, the repository and commit
+//! each program was copied from, and a SHA-256 hash of every copied file.
+//!
+//! A locked download reads the lockfile back, checks out the recorded
+//! commits, and verifies that the copied files hash to the recorded values.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::corpus::{
+    errors::DownloaderError,
+    manifest::{PairManifest, ProgramManifest},
+};
+
+/// The contents of a corpus lockfile, keyed by program name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    /// The locked program pairs, keyed by program name.
+    pub pairs: BTreeMap<String, LockedProgramPair>,
+}
+
+/// The resolved state of one downloaded C-Rust program pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedProgramPair {
+    /// The locked C program.
+    pub c_program: LockedProgram,
+    /// The locked Rust program.
+    pub rust_program: LockedProgram,
+}
+
+/// The resolved state of one downloaded C or Rust program.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedProgram {
+    /// The URL of the repository, or release archive, the files were
+    /// copied from.
+    pub repository_url: String,
+    /// The SHA of the commit the files were copied from.
+    pub commit: String,
+    /// SHA-256 hashes of the copied files, keyed by their path relative to
+    /// the `c-program/` or `rust-program/` directory.
+    pub files: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Reads a lockfile from disk.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The lockfile to read.
+    ///
+    /// # Returns
+    ///
+    /// The [`Lockfile`] on success, or a [`DownloaderError`] if the file
+    /// cannot be read or parsed.
+    pub fn read(path: &Path) -> Result<Self, DownloaderError> {
+        let contents = fs::read_to_string(path).map_err(|error| DownloaderError::IoRead {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        serde_json::from_str(&contents).map_err(|error| DownloaderError::DeserializeLockfile {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Writes the lockfile to disk as pretty-printed JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Where to write the lockfile.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+    pub fn write(&self, path: &Path) -> Result<(), DownloaderError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|error| DownloaderError::SerializeLockfile { error })?;
+        fs::write(path, contents + "\n").map_err(|error| DownloaderError::IoCreate {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Checks that every pair in this lockfile was downloaded.
+    ///
+    /// # Arguments
+    ///
+    /// - `downloaded` - The pairs that were actually downloaded.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if no locked pair is missing, or
+    /// [`DownloaderError::LockMismatch`] for the first missing pair.
+    pub fn verify_all_downloaded(&self, downloaded: &Lockfile) -> Result<(), DownloaderError> {
+        match self
+            .pairs
+            .keys()
+            .find(|program_name| !downloaded.pairs.contains_key(*program_name))
+        {
+            Some(program_name) => Err(DownloaderError::LockMismatch {
+                program_name: program_name.clone(),
+                differences: "the pair is locked but was not downloaded".to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl LockedProgramPair {
+    /// Creates the locked state of a downloaded program pair.
+    ///
+    /// # Arguments
+    ///
+    /// - `manifest` - The manifest of the downloaded pair.
+    ///
+    /// # Returns
+    ///
+    /// The pair's [`LockedProgramPair`].
+    pub fn new(manifest: &PairManifest) -> Self {
+        LockedProgramPair {
+            c_program: LockedProgram::new(
+                manifest.pair.c_program.source_url(),
+                &manifest.c_program,
+            ),
+            rust_program: LockedProgram::new(
+                manifest.pair.rust_program.source_url(),
+                &manifest.rust_program,
+            ),
+        }
+    }
+}
+
+impl LockedProgram {
+    /// Creates the locked state of a downloaded program.
+    ///
+    /// # Arguments
+    ///
+    /// - `repository_url` - The repository, or release archive, the program
+    ///   was downloaded from.
+    /// - `manifest` - The manifest of the downloaded program.
+    ///
+    /// # Returns
+    ///
+    /// The program's [`LockedProgram`].
+    pub fn new(repository_url: &str, manifest: &ProgramManifest) -> Self {
+        LockedProgram {
+            repository_url: repository_url.to_string(),
+            commit: manifest.commit.clone(),
+            files: manifest
+                .files
+                .iter()
+                .map(|(file, file_manifest)| (file.clone(), file_manifest.sha256.clone()))
+                .collect(),
+        }
+    }
+
+    /// Compares a downloaded program against its locked state.
+    ///
+    /// # Arguments
+    ///
+    /// - `program_name` - The name of the program pair, used in errors.
+    /// - `downloaded` - The state of the program that was just downloaded.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the repository, commit and all file hashes match,
+    /// or [`DownloaderError::LockMismatch`] listing every difference.
+    pub fn verify(
+        &self,
+        program_name: &str,
+        downloaded: &LockedProgram,
+    ) -> Result<(), DownloaderError> {
+        let mut differences = Vec::new();
+        if self.repository_url != downloaded.repository_url {
+            differences.push(format!(
+                "repository is '{}' but '{}' is locked",
+                downloaded.repository_url, self.repository_url
+            ));
+        }
+        if self.commit != downloaded.commit {
+            differences.push(format!(
+                "commit is {} but {} is locked",
+                downloaded.commit, self.commit
+            ));
+        }
+        for (file, hash) in &self.files {
+            match downloaded.files.get(file) {
+                Some(downloaded_hash) if downloaded_hash == hash => {}
+                Some(_) => differences.push(format!("'{file}' has a different hash")),
+                None => differences.push(format!("'{file}' is missing")),
+            }
+        }
+        for file in downloaded.files.keys() {
+            if !self.files.contains_key(file) {
+                differences.push(format!("'{file}' is not locked"));
+            }
+        }
+
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(DownloaderError::LockMismatch {
+                program_name: program_name.to_string(),
+                differences: differences.join("; "),
+            })
+        }
+    }
+}
+
+/// Computes the SHA-256 hash of a file.
+///
+/// # Arguments
+///
+/// - `path` - The file to hash.
+///
+/// # Returns
+///
+/// The hash as a lowercase hexadecimal string, or a [`DownloaderError`] if
+/// the file cannot be read.
+pub fn hash_file(path: &Path) -> Result<String, DownloaderError> {
+    let contents = fs::read(path).map_err(|error| DownloaderError::IoRead {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    Ok(format!("{:x}", Sha256::digest(&contents)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked_program(commit: &str, files: &[(&str, &str)]) -> LockedProgram {
+        LockedProgram {
+            repository_url: "https://github.com/uutils/coreutils".to_string(),
+            commit: commit.to_string(),
+            files: files
+                .iter()
+                .map(|(file, hash)| (file.to_string(), hash.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Tests that a download matching its lock is accepted.
+    #[test]
+    fn test_verify_matching() {
+        let locked = locked_program("abc", &[("ls.rs", "01")]);
+        assert!(locked.verify("ls", &locked.clone()).is_ok());
+    }
+
+    /// Tests that every difference between a download and its lock is
+    /// reported.
+    #[test]
+    fn test_verify_mismatch() {
+        let locked = locked_program("abc", &[("ls.rs", "01"), ("dired.rs", "02")]);
+        let downloaded = locked_program("def", &[("ls.rs", "03"), ("colors.rs", "04")]);
+        let Err(DownloaderError::LockMismatch { differences, .. }) =
+            locked.verify("ls", &downloaded)
+        else {
+            panic!("Expected a lock mismatch");
+        };
+        assert_eq!(
+            "commit is def but abc is locked; \
+             'dired.rs' is missing; \
+             'ls.rs' has a different hash; \
+             'colors.rs' is not locked",
+            differences
+        );
+    }
+}
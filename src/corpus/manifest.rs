@@ -0,0 +1,118 @@
+//! # Program Pair Manifest
+//!
+//! This module defines the `pair.json` manifest written next to each
+//! downloaded program pair.  The manifest records the pair's metadata, the
+//! commit each program was copied from, and where every copied file came
+//! from in its repository, so the downloaded directory keeps its provenance.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    corpus::{errors::DownloaderError, schema::ProgramPair},
+    paths::PAIR_MANIFEST_FILE,
+};
+
+/// The provenance of one downloaded C-Rust program pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairManifest {
+    /// The pair's metadata, as parsed from its metadata file.
+    pub pair: ProgramPair,
+    /// The provenance of the files copied into `c-program/`.
+    pub c_program: ProgramManifest,
+    /// The provenance of the files copied into `rust-program/`.
+    pub rust_program: ProgramManifest,
+}
+
+/// The provenance of one downloaded C or Rust program.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramManifest {
+    /// The SHA of the commit the files were copied from.
+    pub commit: String,
+    /// The copied files, keyed by their path relative to the `c-program/` or
+    /// `rust-program/` directory.
+    pub files: BTreeMap<String, FileManifest>,
+}
+
+/// The provenance of one copied file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileManifest {
+    /// The file's original path relative to the repository root.
+    pub source_path: String,
+    /// The SHA-256 hash of the file's contents.
+    pub sha256: String,
+}
+
+impl PairManifest {
+    /// Reads a manifest from disk.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The manifest to read.
+    ///
+    /// # Returns
+    ///
+    /// The [`PairManifest`] on success, or a [`DownloaderError`] if it cannot
+    /// be read or parsed.
+    pub fn read(path: &Path) -> Result<Self, DownloaderError> {
+        let contents = fs::read_to_string(path).map_err(|error| DownloaderError::IoRead {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        serde_json::from_str(&contents).map_err(|error| DownloaderError::DeserializeManifest {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Reads the manifest of every fully downloaded program pair.
+    ///
+    /// # Arguments
+    ///
+    /// - `pairs_directory` - The directory containing one directory per pair.
+    ///
+    /// # Returns
+    ///
+    /// The manifests, sorted by program name, on success, or a
+    /// [`DownloaderError`] on failure.
+    pub fn read_all(pairs_directory: &Path) -> Result<Vec<Self>, DownloaderError> {
+        let to_error = |error| DownloaderError::IoRead {
+            path: pairs_directory.to_path_buf(),
+            error,
+        };
+        let mut manifest_paths = Vec::new();
+        for entry in fs::read_dir(pairs_directory).map_err(to_error)? {
+            let manifest_path = entry.map_err(to_error)?.path().join(PAIR_MANIFEST_FILE);
+            // Pairs that failed to download have no manifest.
+            if manifest_path.is_file() {
+                manifest_paths.push(manifest_path);
+            }
+        }
+
+        let mut manifests = manifest_paths
+            .iter()
+            .map(|manifest_path| Self::read(manifest_path))
+            .collect::<Result<Vec<_>, _>>()?;
+        manifests.sort_by(|a, b| a.pair.program_name.cmp(&b.pair.program_name));
+        Ok(manifests)
+    }
+
+    /// Writes the manifest to disk as pretty-printed JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Where to write the manifest.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+    pub fn write(&self, path: &Path) -> Result<(), DownloaderError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|error| DownloaderError::SerializeManifest { error })?;
+        fs::write(path, contents + "\n").map_err(|error| DownloaderError::IoCreate {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
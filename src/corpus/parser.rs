@@ -3,27 +3,36 @@
 //! The main entry point is [`parse`], which takes a path to a JSON metadata
 //! file and returns a [`Metadata`] instance.
 
-use std::{
-    fs,
-    path::{Path, PathBuf},
-};
+use std::{fs, path::Path, sync::LazyLock};
 
-use jsonschema;
+use jsonschema::{self, Validator};
 use serde::Serialize;
 use serde_json::Value;
 
-use crate::{
-    corpus::{
-        errors::ParserError,
-        metadata_structs::{
-            CRustProgramPairSchema, FeatureRelationship, IndividualProgramPair,
-            ProjectPairsMetadataProjectInformation, ProjectProgramPair,
-        },
-        schema::{Features, Language, Metadata, Program, ProgramPair},
+use crate::corpus::{
+    errors::ParserError,
+    metadata_structs::{
+        ArchiveSha256, ArchiveUrl, Branch, CRustProgramPairSchema, Commit, Extensions,
+        FeatureRelationship, IndividualProgramPair, ProjectPairsMetadataProjectInformation,
+        ProjectProgramPair, Tag,
+    },
+    schema::{
+        Archive, DEFAULT_EXTENSIONS, Features, Language, Metadata, Program, ProgramPair, Revision,
     },
-    paths::METADATA_SCHEMA_FILE,
 };
 
+/// The project's JSON schema, embedded at build time so that metadata can be
+/// parsed from any working directory.  The schema cannot be replaced at run
+/// time, because the structs in `metadata_structs.rs` are generated from it.
+const METADATA_SCHEMA: &str = include_str!("../../metadata/metadata.schema.json");
+
+/// The validator for [`METADATA_SCHEMA`], created on first use and shared by
+/// every parse.
+static METADATA_VALIDATOR: LazyLock<Result<Validator, String>> = LazyLock::new(|| {
+    let schema = read_schema().map_err(|error| error.to_string())?;
+    jsonschema::validator_for(&schema).map_err(|error| error.to_string())
+});
+
 /// Parses a JSON metadata file describing C-Rust program pairs into a
 /// [`Metadata`] struct.
 ///
@@ -51,15 +60,39 @@ pub fn parse(path: &Path) -> Result<Metadata, ParserError> {
     // Create data structure conditioned on the metadata type.
     match metadata {
         CRustProgramPairSchema::IndividualPairsMetadata { pairs } => {
+            for (index, pair) in pairs.iter().enumerate() {
+                for (side, program) in [
+                    ("c_program", &pair.c_program),
+                    ("rust_program", &pair.rust_program),
+                ] {
+                    check_revision(
+                        &format!("/pairs/{index}/{side}"),
+                        program.commit.as_ref(),
+                        program.tag.as_ref(),
+                        program.branch.as_ref(),
+                    )?;
+                }
+            }
             let metadata = parse_individual(&pairs);
-            return Ok(metadata);
+            Ok(metadata)
         }
         CRustProgramPairSchema::ProjectPairsMetadata {
             pairs,
             project_information,
         } => {
+            for (side, program) in [
+                ("c_program", &project_information.c_program),
+                ("rust_program", &project_information.rust_program),
+            ] {
+                check_revision(
+                    &format!("/project_information/{side}"),
+                    program.commit.as_ref(),
+                    program.tag.as_ref(),
+                    program.branch.as_ref(),
+                )?;
+            }
             let metadata = parse_project(&pairs, &project_information);
-            return Ok(metadata);
+            Ok(metadata)
         }
     }
 }
@@ -74,17 +107,10 @@ pub fn parse(path: &Path) -> Result<Metadata, ParserError> {
 ///
 /// Returns `Ok(())` on success and [`ParserError`] on failure.
 fn validate_metadata<T: Serialize>(metadata: &T) -> Result<(), ParserError> {
-    // Create a validator based on the JSON schema.
-    let schema_str =
-        fs::read_to_string(METADATA_SCHEMA_FILE).map_err(|error| ParserError::IoRead {
-            path: PathBuf::from(METADATA_SCHEMA_FILE),
-            error,
-        })?;
-    let schema: Value =
-        serde_json::from_str(&schema_str).map_err(|error| ParserError::Deserialize { error })?;
-    let validator =
-        jsonschema::validator_for(&schema).map_err(|error| ParserError::Validation {
-            error: error.to_string(),
+    let validator = METADATA_VALIDATOR
+        .as_ref()
+        .map_err(|error| ParserError::Validation {
+            error: error.clone(),
         })?;
 
     // Convert metadata to a JSON `Value` type.
@@ -100,6 +126,15 @@ fn validate_metadata<T: Serialize>(metadata: &T) -> Result<(), ParserError> {
     Ok(())
 }
 
+/// Reads the project's embedded JSON schema.
+///
+/// # Returns
+///
+/// The schema as a JSON [`Value`] on success and [`ParserError`] on failure.
+pub(crate) fn read_schema() -> Result<Value, ParserError> {
+    serde_json::from_str(METADATA_SCHEMA).map_err(|error| ParserError::Deserialize { error })
+}
+
 /// Parses an individual-type metadata and returns a [`Metadata`] data structure.
 ///
 /// # Arguments
@@ -111,10 +146,11 @@ fn validate_metadata<T: Serialize>(metadata: &T) -> Result<(), ParserError> {
 /// A [`Metadata`] data structure.
 fn parse_individual(pairs: &[IndividualProgramPair]) -> Metadata {
     let pairs: Vec<ProgramPair> = pairs
-        .into_iter()
+        .iter()
         .map(|pair| ProgramPair {
             program_name: pair.program_name.to_string(),
             program_description: pair.program_description.to_string(),
+            project: None,
             translation_tools: pair.translation_tools.0.clone(),
             feature_relationship: map_feature_relationship(pair.feature_relationship),
             c_program: Program {
@@ -122,12 +158,42 @@ fn parse_individual(pairs: &[IndividualProgramPair]) -> Metadata {
                 documentation_url: pair.c_program.documentation_url.to_string(),
                 repository_url: pair.c_program.repository_url.to_string(),
                 source_paths: pair.c_program.source_paths.0.clone(),
+                revision: map_revision(
+                    pair.c_program.commit.as_ref(),
+                    pair.c_program.tag.as_ref(),
+                    pair.c_program.branch.as_ref(),
+                ),
+                archive: map_archive(
+                    pair.c_program.archive_url.as_ref(),
+                    pair.c_program.archive_sha256.as_ref(),
+                ),
+                extensions: map_extensions(pair.c_program.extensions.as_ref()),
+                crate_manifest: pair
+                    .c_program
+                    .crate_manifest
+                    .as_ref()
+                    .is_some_and(|manifest| **manifest),
             },
             rust_program: Program {
                 language: Language::Rust,
                 documentation_url: pair.rust_program.documentation_url.to_string(),
                 repository_url: pair.rust_program.repository_url.to_string(),
                 source_paths: pair.rust_program.source_paths.0.clone(),
+                revision: map_revision(
+                    pair.rust_program.commit.as_ref(),
+                    pair.rust_program.tag.as_ref(),
+                    pair.rust_program.branch.as_ref(),
+                ),
+                archive: map_archive(
+                    pair.rust_program.archive_url.as_ref(),
+                    pair.rust_program.archive_sha256.as_ref(),
+                ),
+                extensions: map_extensions(pair.rust_program.extensions.as_ref()),
+                crate_manifest: pair
+                    .rust_program
+                    .crate_manifest
+                    .as_ref()
+                    .is_some_and(|manifest| **manifest),
             },
         })
         .collect();
@@ -149,10 +215,11 @@ fn parse_project(
     project_information: &ProjectPairsMetadataProjectInformation,
 ) -> Metadata {
     let pairs: Vec<ProgramPair> = pairs
-        .into_iter()
+        .iter()
         .map(|pair| ProgramPair {
             program_name: pair.program_name.to_string(),
             program_description: pair.program_description.to_string(),
+            project: Some(project_information.program_name.to_string()),
             translation_tools: project_information.translation_tools.0.clone(),
             feature_relationship: map_feature_relationship(
                 project_information.feature_relationship,
@@ -162,6 +229,21 @@ fn parse_project(
                 documentation_url: project_information.c_program.documentation_url.to_string(),
                 repository_url: project_information.c_program.repository_url.to_string(),
                 source_paths: pair.c_program.source_paths.0.clone(),
+                revision: map_revision(
+                    project_information.c_program.commit.as_ref(),
+                    project_information.c_program.tag.as_ref(),
+                    project_information.c_program.branch.as_ref(),
+                ),
+                archive: map_archive(
+                    project_information.c_program.archive_url.as_ref(),
+                    project_information.c_program.archive_sha256.as_ref(),
+                ),
+                extensions: map_extensions(project_information.c_program.extensions.as_ref()),
+                crate_manifest: project_information
+                    .c_program
+                    .crate_manifest
+                    .as_ref()
+                    .is_some_and(|manifest| **manifest),
             },
             rust_program: Program {
                 language: Language::Rust,
@@ -171,6 +253,21 @@ fn parse_project(
                     .to_string(),
                 repository_url: project_information.rust_program.repository_url.to_string(),
                 source_paths: pair.rust_program.source_paths.0.clone(),
+                revision: map_revision(
+                    project_information.rust_program.commit.as_ref(),
+                    project_information.rust_program.tag.as_ref(),
+                    project_information.rust_program.branch.as_ref(),
+                ),
+                archive: map_archive(
+                    project_information.rust_program.archive_url.as_ref(),
+                    project_information.rust_program.archive_sha256.as_ref(),
+                ),
+                extensions: map_extensions(project_information.rust_program.extensions.as_ref()),
+                crate_manifest: project_information
+                    .rust_program
+                    .crate_manifest
+                    .as_ref()
+                    .is_some_and(|manifest| **manifest),
             },
         })
         .collect();
@@ -197,6 +294,111 @@ fn map_feature_relationship(relationship: FeatureRelationship) -> Features {
     }
 }
 
+/// Checks that a program sets at most one of its `commit`, `tag` and
+/// `branch` fields, which the schema does not check.
+///
+/// # Arguments
+///
+/// - `pointer` - The JSON pointer to the program, for the error message.
+/// - `commit` - The `commit` field, if present.
+/// - `tag` - The `tag` field, if present.
+/// - `branch` - The `branch` field, if present.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if at most one field is set, or
+/// [`ParserError::ConflictingRevisions`] otherwise.
+fn check_revision(
+    pointer: &str,
+    commit: Option<&Commit>,
+    tag: Option<&Tag>,
+    branch: Option<&Branch>,
+) -> Result<(), ParserError> {
+    let fields: Vec<_> = [
+        ("'commit'", commit.is_some()),
+        ("'tag'", tag.is_some()),
+        ("'branch'", branch.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(field, set)| set.then_some(field))
+    .collect();
+    if fields.len() > 1 {
+        return Err(ParserError::ConflictingRevisions {
+            pointer: pointer.to_string(),
+            fields: fields.join(" and "),
+        });
+    }
+    Ok(())
+}
+
+/// Convert from the optional `commit`, `tag` and `branch` fields in
+/// metadata files to the [`Revision`] used in our final schema.
+///
+/// At most one of them is set, which [`check_revision`] has checked.
+///
+/// # Arguments
+///
+/// - `commit` - The `commit` field, if present.
+/// - `tag` - The `tag` field, if present.
+/// - `branch` - The `branch` field, if present.
+///
+/// # Returns
+///
+/// The [`Revision`] to download, or `None` if no revision was specified.
+fn map_revision(
+    commit: Option<&Commit>,
+    tag: Option<&Tag>,
+    branch: Option<&Branch>,
+) -> Option<Revision> {
+    if let Some(commit) = commit {
+        Some(Revision::Commit(commit.to_string()))
+    } else if let Some(tag) = tag {
+        Some(Revision::Tag(tag.to_string()))
+    } else {
+        branch.map(|branch| Revision::Branch(branch.to_string()))
+    }
+}
+
+/// Convert from the optional `archive_url` and `archive_sha256` fields in
+/// metadata files to the [`Archive`] used in our final schema.
+///
+/// The schema requires both fields or neither.
+///
+/// # Arguments
+///
+/// - `url` - The `archive_url` field, if present.
+/// - `sha256` - The `archive_sha256` field, if present.
+///
+/// # Returns
+///
+/// The [`Archive`] to download, or `None` if the repository is cloned.
+fn map_archive(url: Option<&ArchiveUrl>, sha256: Option<&ArchiveSha256>) -> Option<Archive> {
+    Some(Archive {
+        url: url?.to_string(),
+        sha256: sha256?.to_string(),
+    })
+}
+
+/// Convert from the optional `extensions` field in metadata files to the
+/// extensions used in our final schema.
+///
+/// # Arguments
+///
+/// - `extensions` - The `extensions` field, if present.
+///
+/// # Returns
+///
+/// The given extensions, or [`DEFAULT_EXTENSIONS`] if none were specified.
+fn map_extensions(extensions: Option<&Extensions>) -> Vec<String> {
+    match extensions {
+        Some(extensions) => extensions
+            .iter()
+            .map(|extension| extension.to_string())
+            .collect(),
+        None => DEFAULT_EXTENSIONS.map(str::to_string).to_vec(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,4 +429,42 @@ mod tests {
             result.err()
         );
     }
+
+    /// Tests that a program setting more than one revision is rejected
+    /// rather than silently pinned to one of them.
+    #[test]
+    fn test_parse_conflicting_revisions() {
+        let directory = tempfile::tempdir().unwrap();
+        let metadata_file = directory.path().join("test.json");
+        let program = |repository_url: &str| {
+            serde_json::json!({
+                "documentation_url": "https://example.com",
+                "repository_url": repository_url,
+                "source_paths": ["src/main.c"],
+                "tag": "v1.0",
+                "branch": "main"
+            })
+        };
+        let metadata = serde_json::json!({
+            "pairs": [{
+                "program_name": "test",
+                "program_description": "A test program.",
+                "translation_tools": ["manual"],
+                "feature_relationship": "rust_equivalent_to_c",
+                "c_program": program("https://example.com/c"),
+                "rust_program": program("https://example.com/rust")
+            }]
+        });
+        fs::write(&metadata_file, metadata.to_string()).unwrap();
+
+        let result = parse(&metadata_file);
+        assert!(
+            matches!(
+                &result,
+                Err(ParserError::ConflictingRevisions { pointer, fields })
+                    if pointer == "/pairs/0/c_program" && fields == "'tag' and 'branch'"
+            ),
+            "{result:?}"
+        );
+    }
 }
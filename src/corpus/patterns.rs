@@ -0,0 +1,246 @@
+//! # Source Path Patterns
+//!
+//! An entry in `source_paths` is one of:
+//!
+//! - A literal path to a file or directory, such as `src/ls.c` or
+//!   `src/uu/ls/src`.  Directories are searched for source files.
+//! - A glob pattern, such as `src/uu/ls/**/*.rs`, which selects every
+//!   matching file in the repository.  `*` does not match `/`, but `**`
+//!   does.
+//! - An exclusion, such as `!**/tests/**`, which removes every matching file,
+//!   or every file within a matching directory, from those selected by the
+//!   other entries, wherever it appears in the list.
+
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+use crate::corpus::{errors::DownloaderError, utils};
+
+/// The characters that make a source path a glob pattern.
+const GLOB_CHARACTERS: [char; 4] = ['*', '?', '[', '{'];
+
+/// A `source_paths` list, split into what it includes and excludes.
+#[derive(Debug)]
+pub struct SourcePatterns {
+    /// The entries that select files, in order.
+    includes: Vec<Include>,
+    /// The exclusions, which apply to every included file.
+    excludes: GlobSet,
+}
+
+/// An entry of `source_paths` that selects files.
+#[derive(Debug)]
+pub enum Include {
+    /// A literal path to a file or directory.
+    Path(String),
+    /// A glob pattern.
+    Glob {
+        /// The pattern, as written in the metadata.
+        pattern: String,
+        /// The directory containing every match: the components of the
+        /// pattern before the first one containing a glob character.
+        base: String,
+        /// Matches repository-relative paths against the pattern.
+        matcher: GlobMatcher,
+    },
+}
+
+impl SourcePatterns {
+    /// Parses a `source_paths` list.
+    ///
+    /// # Arguments
+    ///
+    /// - `source_paths` - The `source_paths` of a program.
+    ///
+    /// # Returns
+    ///
+    /// The parsed patterns on success, or
+    /// [`DownloaderError::InvalidPattern`] if a glob pattern is malformed.
+    pub fn new(source_paths: &[String]) -> Result<Self, DownloaderError> {
+        let mut includes = Vec::new();
+        let mut excludes = GlobSetBuilder::new();
+        for source_path in source_paths {
+            if let Some(exclude) = source_path.strip_prefix('!') {
+                // Exclude both the matching paths and everything within them.
+                let exclude = exclude.trim_end_matches('/');
+                excludes.add(build_glob(source_path, exclude)?);
+                excludes.add(build_glob(source_path, &format!("{exclude}/**"))?);
+            } else if is_glob(source_path) {
+                includes.push(Include::Glob {
+                    pattern: source_path.clone(),
+                    base: glob_base(source_path),
+                    matcher: build_glob(source_path, source_path)?.compile_matcher(),
+                });
+            } else {
+                includes.push(Include::Path(source_path.clone()));
+            }
+        }
+
+        let excludes = excludes
+            .build()
+            .map_err(|error| DownloaderError::InvalidPattern {
+                pattern: source_paths.join(", "),
+                error,
+            })?;
+        Ok(Self { includes, excludes })
+    }
+
+    /// Returns the entries that select files, in order.
+    pub fn includes(&self) -> &[Include] {
+        &self.includes
+    }
+
+    /// Checks whether an exclusion applies to a file.
+    ///
+    /// # Arguments
+    ///
+    /// - `repository_path` - The path of the file relative to the repository
+    ///   root, separated by `/`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the file should not be copied.
+    pub fn is_excluded(&self, repository_path: &str) -> bool {
+        self.excludes.is_match(repository_path)
+    }
+}
+
+/// Finds the files in a repository that match a glob pattern.
+///
+/// # Arguments
+///
+/// - `repository_directory` - The root of the repository.
+/// - `base` - The directory, relative to the root, that contains every
+///   match.
+/// - `matcher` - Matches repository-relative paths against the pattern.
+///
+/// # Returns
+///
+/// The matching files, sorted, on success and [`DownloaderError`] on
+/// failure.
+pub fn find_glob_matches(
+    repository_directory: &Path,
+    base: &str,
+    matcher: &GlobMatcher,
+) -> Result<Vec<PathBuf>, DownloaderError> {
+    let mut matches = Vec::new();
+    let walker = WalkDir::new(repository_directory.join(base))
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+    for entry in walker.filter_map(|entry| entry.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative_path = entry
+            .path()
+            .strip_prefix(repository_directory)
+            .unwrap_or(entry.path());
+        if matcher.is_match(utils::to_slash_path(relative_path)?) {
+            matches.push(entry.path().to_path_buf());
+        }
+    }
+    Ok(matches)
+}
+
+/// Checks whether a source path is a glob pattern.
+///
+/// # Arguments
+///
+/// - `source_path` - An entry of `source_paths`, which must not be an
+///   exclusion.
+///
+/// # Returns
+///
+/// `true` if `source_path` contains a glob character.
+pub fn is_glob(source_path: &str) -> bool {
+    source_path.contains(GLOB_CHARACTERS)
+}
+
+/// Returns the directory containing every match of a glob pattern.
+///
+/// # Example
+///
+/// The base of "src/uu/ls/**/*.rs" is "src/uu/ls", and the base of
+/// "**/*.c" is "".
+///
+/// # Arguments
+///
+/// - `pattern` - A glob pattern.
+///
+/// # Returns
+///
+/// The components of `pattern` before the first one containing a glob
+/// character, joined by `/`.
+pub fn glob_base(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .take_while(|component| !is_glob(component))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Compiles a glob pattern in which `*` does not match `/`.
+///
+/// # Arguments
+///
+/// - `source_path` - The `source_paths` entry the pattern comes from.
+/// - `pattern` - The glob pattern.
+///
+/// # Returns
+///
+/// The compiled [`Glob`] on success, or
+/// [`DownloaderError::InvalidPattern`] on failure.
+fn build_glob(source_path: &str, pattern: &str) -> Result<Glob, DownloaderError> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|error| DownloaderError::InvalidPattern {
+            pattern: source_path.to_string(),
+            error,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that literal paths, globs and exclusions are told apart, and
+    /// that exclusions also apply within matching directories.
+    #[test]
+    fn test_source_patterns() {
+        let source_paths = [
+            "src/ls.c".to_string(),
+            "src/uu/ls/**/*.rs".to_string(),
+            "!**/tests/**".to_string(),
+            "!src/vendor".to_string(),
+        ];
+        let patterns = SourcePatterns::new(&source_paths).unwrap();
+
+        assert!(matches!(&patterns.includes()[0], Include::Path(path) if path == "src/ls.c"));
+        let Include::Glob { base, matcher, .. } = &patterns.includes()[1] else {
+            panic!("Expected a glob");
+        };
+        assert_eq!(base, "src/uu/ls");
+        assert!(matcher.is_match("src/uu/ls/src/ls.rs"));
+        assert!(matcher.is_match("src/uu/ls/ls.rs"));
+        assert!(!matcher.is_match("src/uu/ls/ls.c"));
+
+        assert!(patterns.is_excluded("tests/ls.rs"));
+        assert!(patterns.is_excluded("src/uu/ls/tests/mod.rs"));
+        assert!(patterns.is_excluded("src/vendor/lib/lib.c"));
+        assert!(!patterns.is_excluded("src/uu/ls/src/ls.rs"));
+    }
+
+    /// Tests that a malformed glob pattern is reported.
+    #[test]
+    fn test_source_patterns_invalid() {
+        let result = SourcePatterns::new(&["src/[a-.c".to_string()]);
+        assert!(matches!(
+            result,
+            Err(DownloaderError::InvalidPattern { pattern, .. }) if pattern == "src/[a-.c"
+        ));
+    }
+}
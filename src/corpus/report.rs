@@ -0,0 +1,198 @@
+//! # Download Reports
+//!
+//! A [`DownloadReport`] records what happened to every metadata file and
+//! program pair during a download: whether it succeeded, the error if it
+//! failed, how long cloning and copying took, how many bytes were received,
+//! and how many files were copied for each side.  It can be written as
+//! JSON for later inspection.
+
+use std::{fs, path::Path, path::PathBuf, time::Duration};
+
+use serde::Serialize;
+
+use crate::corpus::errors::{DownloaderError, ParserError};
+
+/// What happened during one download.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DownloadReport {
+    /// Every metadata file read, in the order they were read.
+    pub metadata_files: Vec<MetadataFileReport>,
+    /// Every program pair selected for download, in metadata order.
+    pub pairs: Vec<PairReport>,
+}
+
+/// The outcome of reading one metadata file.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetadataFileReport {
+    /// The metadata file.
+    pub path: PathBuf,
+    /// The names of the program pairs it holds; empty if it failed to parse.
+    pub pairs: Vec<String>,
+    /// Why the file failed to parse, if it did.
+    pub error: Option<ErrorReport>,
+}
+
+/// The outcome of downloading one program pair.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairReport {
+    /// The name of the program pair.
+    pub program_name: String,
+    /// Whether the pair was downloaded.
+    pub status: PairStatus,
+    /// Why the pair failed to download, if it did.
+    pub error: Option<ErrorReport>,
+    /// The C program's side of the download.
+    pub c_program: ProgramReport,
+    /// The Rust program's side of the download.
+    pub rust_program: ProgramReport,
+}
+
+/// Whether a program pair was downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairStatus {
+    /// Both programs were copied and the pair's manifest was written.
+    Downloaded,
+    /// The pair failed to download.
+    Failed,
+}
+
+/// The download of one C or Rust program.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProgramReport {
+    /// How long cloning or fetching the program's repository took, in
+    /// seconds.  Programs sharing a repository report the same clone.
+    pub clone_seconds: f64,
+    /// How long copying the program's files took, in seconds.
+    pub copy_seconds: f64,
+    /// The number of bytes received while cloning or fetching the program's
+    /// repository, or downloading its release archive.  Programs sharing a
+    /// repository report the same clone.
+    pub received_bytes: u64,
+    /// The number of files copied.
+    pub files: usize,
+}
+
+/// An error, with the name of its variant so reports can be grouped by it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    /// The error variant, such as `CloneRepository`.
+    pub kind: String,
+    /// The error message.
+    pub message: String,
+}
+
+impl DownloadReport {
+    /// Returns the number of program pairs that failed to download.
+    pub fn failed_pairs(&self) -> usize {
+        self.pairs
+            .iter()
+            .filter(|pair| pair.status == PairStatus::Failed)
+            .count()
+    }
+
+    /// Returns the number of metadata files that failed to parse.
+    pub fn failed_metadata_files(&self) -> usize {
+        self.metadata_files
+            .iter()
+            .filter(|metadata_file| metadata_file.error.is_some())
+            .count()
+    }
+
+    /// Checks whether every metadata file was parsed and every program pair
+    /// downloaded.
+    pub fn is_success(&self) -> bool {
+        self.failed_pairs() == 0 && self.failed_metadata_files() == 0
+    }
+
+    /// Writes the report to disk as pretty-printed JSON.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - Where to write the report.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+    pub fn write(&self, path: &Path) -> Result<(), DownloaderError> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|error| DownloaderError::SerializeReport { error })?;
+        fs::write(path, contents + "\n").map_err(|error| DownloaderError::IoCreate {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+impl ProgramReport {
+    /// Creates the report of a program's download.
+    ///
+    /// # Arguments
+    ///
+    /// - `clone_duration` - How long cloning the repository took.
+    /// - `copy_duration` - How long copying the files took.
+    /// - `received_bytes` - The bytes received while cloning.
+    /// - `files` - The number of files copied.
+    pub fn new(
+        clone_duration: Duration,
+        copy_duration: Duration,
+        received_bytes: u64,
+        files: usize,
+    ) -> Self {
+        Self {
+            clone_seconds: clone_duration.as_secs_f64(),
+            copy_seconds: copy_duration.as_secs_f64(),
+            received_bytes,
+            files,
+        }
+    }
+}
+
+impl From<&DownloaderError> for ErrorReport {
+    fn from(error: &DownloaderError) -> Self {
+        Self {
+            kind: variant_name(error),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<&ParserError> for ErrorReport {
+    fn from(error: &ParserError) -> Self {
+        Self {
+            kind: variant_name(error),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Returns the name of an error's enum variant.
+///
+/// The derived [`Debug`] output of an enum starts with the variant name, so
+/// this avoids listing every variant by hand.
+fn variant_name(error: &impl std::fmt::Debug) -> String {
+    format!("{error:?}")
+        .split(|character: char| !character.is_alphanumeric() && character != '_')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that errors are reported with their variant name.
+    #[test]
+    fn test_error_report() {
+        let error = DownloaderError::UnknownPair {
+            program_name: "ls".to_string(),
+        };
+        let report = ErrorReport::from(&error);
+        assert_eq!(report.kind, "UnknownPair");
+        assert_eq!(report.message, "No program pair named 'ls'");
+
+        let report = ErrorReport::from(&DownloaderError::ThreadPool("full".to_string()));
+        assert_eq!(report.kind, "ThreadPool");
+    }
+}
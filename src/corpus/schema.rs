@@ -5,6 +5,9 @@
 //! about program pairs after JSON parsing is complete. By contrast, structs
 //! defined in file `metadata-structs.rs` are used during JSON parsing.
 
+use std::fmt;
+
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 /// The metadata from a single .json metadata file, containing
@@ -15,53 +18,159 @@ pub struct Metadata {
 }
 
 /// One C-Rust program pair.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProgramPair {
+    /// The pair's name, unique within the corpus, such as `ls`.
     pub program_name: String,
+    /// A short description of what the program does.
     pub program_description: String,
+    /// The `program_name` of the project the pair belongs to, for pairs
+    /// from project metadata.
+    pub project: Option<String>,
+    /// The tools used to translate the C program to Rust, such as `c2rust`
+    /// or `manual`.
     pub translation_tools: Vec<String>,
+    /// How the Rust program's features relate to the C program's.
     pub feature_relationship: Features,
+    /// The original C program.
     pub c_program: Program,
+    /// The Rust translation of the C program.
     pub rust_program: Program,
 }
 
 /// One C or Rust program.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Program {
+    /// Whether this is the C or the Rust program.
     pub language: Language,
+    /// Where the program is documented.
     pub documentation_url: String,
+    /// The URL or local path of the program's git repository.
     pub repository_url: String,
+    /// The files, directories and glob patterns to copy, relative to the
+    /// repository root.  Patterns starting with `!` exclude files.
     pub source_paths: Vec<String>,
+    /// The revision to copy, or `None` for the default branch.
+    pub revision: Option<Revision>,
+    /// A release archive to download instead of cloning the repository.
+    pub archive: Option<Archive>,
+    /// The extensions of the files copied from listed directories.
+    pub extensions: Vec<String>,
+    /// Whether to copy the `Cargo.toml` of the crate the source files are in.
+    pub crate_manifest: bool,
+}
+
+/// The extensions of the files copied from listed directories when a
+/// program does not specify its own.
+pub const DEFAULT_EXTENSIONS: [&str; 3] = ["c", "h", "rs"];
+
+/// The revision of a repository to download.
+///
+/// When no revision is given, the repository's default branch is used.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Revision {
+    /// A full commit SHA.
+    Commit(String),
+    /// A tag name, such as `v9.5`.
+    Tag(String),
+    /// A branch name, such as `main`.
+    Branch(String),
+}
+
+/// A release archive downloaded instead of cloning a program's repository.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Archive {
+    /// The URL or local path of the `.tar.gz` archive.
+    pub url: String,
+    /// The expected SHA-256 checksum of the archive, in lowercase hex.
+    pub sha256: String,
 }
 
 /// Specifies the feature set of the Rust project in relation to its C counterpart.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
 #[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
 pub enum Features {
+    /// The Rust program implements some of the C program's features.
     RustSubsetOfC,
+    /// The Rust program implements the same features as the C program.
     RustEquivalentToC,
+    /// The Rust program implements all of the C program's features, and
+    /// more.
     RustSupersetOfC,
+    /// Each program has features the other lacks.
     Overlapping,
 }
 
 /// The language in which the program is written.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Language {
+    /// C.
     C,
+    /// Rust.
     Rust,
 }
 
-impl Language {
-    /// Converts the enum type to a string.
+impl Program {
+    /// Returns the URL the program's source code is downloaded from.
+    ///
+    /// # Returns
+    ///
+    /// The archive URL if the program has an archive, and the repository URL
+    /// otherwise.
+    pub fn source_url(&self) -> &str {
+        self.archive
+            .as_ref()
+            .map_or(&self.repository_url, |archive| &archive.url)
+    }
+}
+
+impl Revision {
+    /// Returns the refspec used to fetch this revision from a remote.
     ///
     /// # Returns
     ///
-    /// The string "c" or "rust".
-    pub fn to_str(&self) -> &'static str {
+    /// The commit SHA itself for a commit, or a refspec that stores the
+    /// fetched tag or branch under its usual local reference.
+    pub fn refspec(&self) -> String {
+        match self {
+            Revision::Commit(commit) => commit.clone(),
+            Revision::Tag(tag) => format!("+refs/tags/{tag}:refs/tags/{tag}"),
+            Revision::Branch(branch) => {
+                format!("+refs/heads/{branch}:refs/remotes/origin/{branch}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Revision::Commit(commit) => write!(f, "commit {commit}"),
+            Revision::Tag(tag) => write!(f, "tag {tag}"),
+            Revision::Branch(branch) => write!(f, "branch {branch}"),
+        }
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Features::RustSubsetOfC => write!(f, "rust_subset_of_c"),
+            Features::RustEquivalentToC => write!(f, "rust_equivalent_to_c"),
+            Features::RustSupersetOfC => write!(f, "rust_superset_of_c"),
+            Features::Overlapping => write!(f, "overlapping"),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Language::C => "c",
-            Language::Rust => "rust",
+            Language::C => write!(f, "C"),
+            Language::Rust => write!(f, "Rust"),
         }
     }
 }
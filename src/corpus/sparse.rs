@@ -0,0 +1,495 @@
+//! # Sparse Clones
+//!
+//! In sparse mode, repositories are fetched without their file contents, as
+//! partial clones, and only the files under the `source_paths` of the
+//! programs being copied are checked out.  Their contents are fetched on
+//! demand during the checkout.  This saves most of the download for large
+//! repositories of which a program only needs a few directories.
+//!
+//! libgit2 supports neither partial clones nor fetching missing objects, so
+//! sparse clones are fetched and checked out with the `git` command.  The
+//! objects are fetched through a separate promisor remote, which points to
+//! the repository's fetch URL, so the clone's `origin` keeps the URL given in
+//! the metadata.  A server that does not support partial clones sends every
+//! object instead, and the clone is then treated as a full clone.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::LazyLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use git2::{ErrorClass, ErrorCode, Oid, Repository};
+
+use crate::{
+    corpus::{
+        config::NetworkConfig,
+        errors::DownloaderError,
+        patterns::{Include, SourcePatterns},
+        schema::Program,
+    },
+    paths::CRATE_MANIFEST_FILE,
+};
+
+/// The remote that the objects of a partial clone are fetched from.
+const PROMISOR_REMOTE: &str = "corpus-promisor";
+
+/// The filter of a partial clone, which leaves out every file's contents.
+const FILTER: &str = "blob:none";
+
+/// The exit code of `git` when it is run with an unknown option.
+const USAGE_EXIT_CODE: i32 = 129;
+
+/// What `git fetch` prints when the server ignores the filter.
+const FILTER_IGNORED: &str = "filtering not recognized by server";
+
+/// The sparse-checkout pattern that matches every file.
+const FULL_CHECKOUT: &str = "/*";
+
+/// How often a running `git` command is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether the `git` command can be run.
+static GIT_AVAILABLE: LazyLock<bool> = LazyLock::new(|| {
+    Command::new("git")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+});
+
+/// Checks whether the `git` command, which sparse clones need, can be run.
+pub fn git_available() -> bool {
+    *GIT_AVAILABLE
+}
+
+/// Checks whether a clone is a partial clone, whose missing objects are
+/// fetched from its promisor remote.
+///
+/// # Arguments
+///
+/// - `repository` - The clone.
+pub fn is_partial(repository: &Repository) -> bool {
+    repository
+        .config()
+        .and_then(|config| config.get_bool(&format!("remote.{PROMISOR_REMOTE}.promisor")))
+        .unwrap_or(false)
+}
+
+/// Fetches a revision into a clone without the contents of its files,
+/// turning the clone into a partial clone.
+///
+/// If the server does not support partial clones, it sends every object,
+/// and the clone is left a full clone.
+///
+/// # Arguments
+///
+/// - `repository` - The clone.
+/// - `fetch_url` - The URL the repository is fetched from.
+/// - `refspec` - The refspec of the revision to fetch.
+/// - `depth` - The number of commits to fetch, or `None` for all of them.
+/// - `network` - The proxy, certificate authorities and credentials to use,
+///   and how long the fetch may take.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`git2::Error`] on failure.
+pub fn fetch(
+    repository: &Repository,
+    fetch_url: &str,
+    refspec: &str,
+    depth: Option<u32>,
+    network: &NetworkConfig,
+) -> Result<(), git2::Error> {
+    // The fetch URL may change between downloads, for example when mirrors
+    // are configured.
+    match repository.find_remote(PROMISOR_REMOTE) {
+        Ok(_) => repository.remote_set_url(PROMISOR_REMOTE, fetch_url)?,
+        Err(_) => drop(repository.remote(PROMISOR_REMOTE, fetch_url)?),
+    }
+
+    let mut command = git_command(repository, fetch_url, network);
+    command.args([
+        "fetch",
+        "--no-tags",
+        "--no-write-fetch-head",
+        &format!("--filter={FILTER}"),
+    ]);
+    if let Some(depth) = depth {
+        command.arg(format!("--depth={depth}"));
+    }
+    command.args([PROMISOR_REMOTE, refspec]);
+    let result = run(command, network.clone_timeout());
+    match &result {
+        Ok(stderr) if !stderr.contains(FILTER_IGNORED) => Ok(()),
+        _ => {
+            // Leave a full clone, or one that failed to fetch, as a plain
+            // clone rather than a partial one.
+            repository.remote_delete(PROMISOR_REMOTE)?;
+            result.map(drop)
+        }
+    }
+}
+
+/// Checks out a commit in a partial clone, fetching the contents of the
+/// files it checks out.
+///
+/// # Arguments
+///
+/// - `repository` - The partial clone.
+/// - `commit` - The commit to check out.
+/// - `patterns` - The sparse-checkout patterns of the files to check out,
+///   or `None` to check out every file.
+/// - `network` - The proxy, certificate authorities and credentials used to
+///   fetch the contents, and how long the checkout may take.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`git2::Error`] on failure.
+pub fn checkout(
+    repository: &Repository,
+    commit: Oid,
+    patterns: Option<&[String]>,
+    network: &NetworkConfig,
+) -> Result<(), git2::Error> {
+    // Sparse checkout stays enabled, with a pattern matching every file for
+    // a full checkout, since disabling it would leave files that an earlier
+    // sparse checkout skipped missing.
+    let patterns = patterns.map_or(FULL_CHECKOUT.to_string(), |patterns| patterns.join("\n"));
+    let sparse_checkout = repository.path().join("info").join("sparse-checkout");
+    sparse_checkout
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&sparse_checkout, patterns + "\n"))
+        .map_err(|error| git2::Error::from_str(&error.to_string()))?;
+    repository.config()?.set_bool("core.sparseCheckout", true)?;
+
+    let fetch_url = repository
+        .find_remote(PROMISOR_REMOTE)?
+        .url()
+        .unwrap_or_default()
+        .to_string();
+    let mut command = git_command(repository, &fetch_url, network);
+    command.args([
+        "checkout",
+        "--quiet",
+        "--force",
+        "--detach",
+        &commit.to_string(),
+    ]);
+    run(command, network.clone_timeout()).map(drop)
+}
+
+/// Returns the sparse-checkout patterns of the files some programs copy
+/// from a repository.
+///
+/// Each path and the base directory of each glob pattern in their
+/// `source_paths` is checked out, so a glob pattern without a base
+/// directory, such as `**/*.rs`, checks out every file.  For programs that
+/// ask for their crate manifest, so is every `Cargo.toml` above them.
+///
+/// # Arguments
+///
+/// - `programs` - The programs copied from the repository.
+///
+/// # Returns
+///
+/// The patterns, sorted, on success, or a [`DownloaderError`] if a glob
+/// pattern is malformed.
+pub fn patterns<'a>(
+    programs: impl IntoIterator<Item = &'a Program>,
+) -> Result<Vec<String>, DownloaderError> {
+    let mut patterns = BTreeSet::new();
+    for program in programs {
+        for include in SourcePatterns::new(&program.source_paths)?.includes() {
+            let path = match include {
+                Include::Path(path) => path,
+                Include::Glob { base, .. } => base,
+            };
+            let path = PathBuf::from(path.trim_matches('/'));
+            if path.as_os_str().is_empty() {
+                patterns.insert(FULL_CHECKOUT.to_string());
+                continue;
+            }
+            patterns.insert(format!("/{}", path.display()));
+            if !program.crate_manifest {
+                continue;
+            }
+            for ancestor in path.ancestors().skip(1) {
+                let manifest = ancestor.join(CRATE_MANIFEST_FILE);
+                patterns.insert(format!("/{}", manifest.display()));
+            }
+        }
+    }
+    Ok(patterns.into_iter().collect())
+}
+
+/// Creates a `git` command that runs in a clone.
+///
+/// The command reads the user's and system's git configuration only if
+/// `use_git_config` is set, and never prompts.  It connects through the
+/// configured proxy, trusts the configured certificate authorities, and
+/// authenticates with the configured credential of the fetch URL.
+///
+/// # Arguments
+///
+/// - `repository` - The clone.
+/// - `fetch_url` - The URL objects are fetched from.
+/// - `network` - The network settings.
+///
+/// # Returns
+///
+/// The [`Command`], without its subcommand.
+fn git_command(repository: &Repository, fetch_url: &str, network: &NetworkConfig) -> Command {
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(repository.workdir().unwrap_or(repository.path()))
+        .env("GIT_TERMINAL_PROMPT", "0");
+    if !network.use_git_config {
+        command
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1");
+    }
+    if let Some(proxy) = &network.proxy {
+        command.arg("-c").arg(format!("http.proxy={proxy}"));
+    }
+    if let Some(ca_bundle) = &network.ca_bundle {
+        command
+            .arg("-c")
+            .arg(format!("http.sslCAInfo={}", ca_bundle.display()));
+    }
+    if let Some(credential) = network.credential(fetch_url) {
+        // The helper reads the credential from the environment, so it never
+        // appears in the command line.
+        command
+            .arg("-c")
+            .arg(
+                "credential.helper=!f() { echo \"username=$CORPUS_GIT_USERNAME\"; \
+                 echo \"password=$CORPUS_GIT_PASSWORD\"; }; f",
+            )
+            .env("CORPUS_GIT_USERNAME", &credential.username)
+            .env(
+                "CORPUS_GIT_PASSWORD",
+                credential.password().unwrap_or_default(),
+            );
+    }
+    command
+}
+
+/// Runs a `git` command, killing it if it takes too long.
+///
+/// # Arguments
+///
+/// - `command` - The command.
+/// - `timeout` - How long the command may take, or `None` for no limit.
+///
+/// # Returns
+///
+/// What the command printed to standard error if it succeeded, or a
+/// [`git2::Error`] holding it if it failed or timed out.  The error is a
+/// network error unless `git` could not be run or does not support the
+/// command.
+fn run(mut command: Command, timeout: Option<Duration>) -> Result<String, git2::Error> {
+    let to_error =
+        |message: String| git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, message);
+    let to_git_error =
+        |message: String| git2::Error::new(ErrorCode::GenericError, ErrorClass::Invalid, message);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| to_git_error(format!("failed to run git: {error}")))?;
+
+    // Read standard error as the command runs, so it never blocks on a full
+    // pipe.
+    let mut stderr = child.stderr.take().expect("Standard error is piped");
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stderr.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|error| to_error(error.to_string()))?
+        {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(to_error("git timed out".to_string()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    let stderr = reader
+        .join()
+        .expect("Reading standard error does not panic")
+        .unwrap_or_default();
+    match status.code() {
+        Some(0) => Ok(stderr),
+        // `git` exits with this code when it does not know an option, such
+        // as `--filter` in old versions.
+        Some(USAGE_EXIT_CODE) => Err(to_git_error(stderr.trim().to_string())),
+        _ => Err(to_error(stderr.trim().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use git2::Signature;
+    use tempfile::TempDir;
+
+    use crate::corpus::{
+        git,
+        schema::{DEFAULT_EXTENSIONS, Language},
+    };
+
+    /// Creates a repository with one file in each of `a/` and `b/`, serving
+    /// partial clones if `allow_filter` is set.
+    ///
+    /// Returns the ID of the commit.
+    fn create_origin(path: &Path, allow_filter: bool) -> Oid {
+        let repository = Repository::init(path).unwrap();
+        for file in ["a/a.c", "b/b.c"] {
+            std::fs::create_dir_all(path.join(file).parent().unwrap()).unwrap();
+            std::fs::write(path.join(file), file).unwrap();
+        }
+        let mut index = repository.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let commit = repository
+            .commit(Some("HEAD"), &signature, &signature, "first", &tree, &[])
+            .unwrap();
+        repository
+            .config()
+            .unwrap()
+            .set_bool("uploadpack.allowFilter", allow_filter)
+            .unwrap();
+        commit
+    }
+
+    /// Fetches the default branch of `origin` into a new clone with the
+    /// filter, and checks out `a/` only.
+    fn sparse_clone(directory: &Path, origin: &Path, commit: Oid) -> Repository {
+        let clone = directory.join("clone");
+        let repository = git::init(&clone, false).unwrap();
+        let network = NetworkConfig::default();
+        let fetch_url = format!("file://{}", origin.display());
+        fetch(
+            &repository,
+            &fetch_url,
+            "+HEAD:refs/remotes/origin/HEAD",
+            Some(1),
+            &network,
+        )
+        .unwrap();
+        if is_partial(&repository) {
+            checkout(&repository, commit, Some(&["/a".to_string()]), &network).unwrap();
+        }
+        repository
+    }
+
+    /// Tests that a partial clone only fetches and checks out the files
+    /// under the sparse-checkout patterns, until it is checked out in full.
+    #[test]
+    fn test_sparse_clone() {
+        if !git_available() {
+            return;
+        }
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commit = create_origin(&origin, true);
+        let repository = sparse_clone(directory.path(), &origin, commit);
+
+        assert!(is_partial(&repository));
+        let workdir = repository.workdir().unwrap();
+        assert!(workdir.join("a/a.c").is_file());
+        assert!(!workdir.join("b/b.c").exists());
+        let tree = repository.find_commit(commit).unwrap().tree().unwrap();
+        let blob = |path| tree.get_path(Path::new(path)).unwrap().id();
+        let odb = repository.odb().unwrap();
+        assert!(odb.exists(blob("a/a.c")));
+        assert!(!odb.exists(blob("b/b.c")));
+
+        // A full checkout fetches the files the sparse one skipped.
+        checkout(&repository, commit, None, &NetworkConfig::default()).unwrap();
+        assert!(workdir.join("b/b.c").is_file());
+    }
+
+    /// Tests that a server without partial clones leaves a full clone.
+    #[test]
+    fn test_sparse_clone_unsupported() {
+        if !git_available() {
+            return;
+        }
+        let directory = TempDir::new().unwrap();
+        let origin = directory.path().join("origin");
+        let commit = create_origin(&origin, false);
+        let repository = sparse_clone(directory.path(), &origin, commit);
+
+        assert!(!is_partial(&repository));
+        let tree = repository.find_commit(commit).unwrap().tree().unwrap();
+        let blob = tree.get_path(Path::new("b/b.c")).unwrap().id();
+        assert!(repository.odb().unwrap().exists(blob));
+    }
+
+    /// Tests that every path and glob base is checked out, together with
+    /// the crate manifests above them when the program asks for them.
+    #[test]
+    fn test_patterns() {
+        let mut program = Program {
+            language: Language::Rust,
+            documentation_url: "https://example.com".to_string(),
+            repository_url: "https://example.com/repository".to_string(),
+            source_paths: vec![
+                "src/uu/ls/src".to_string(),
+                "src/uu/cat/**/*.rs".to_string(),
+                "!src/uu/ls/src/tests".to_string(),
+            ],
+            revision: None,
+            archive: None,
+            extensions: DEFAULT_EXTENSIONS.map(str::to_string).to_vec(),
+            crate_manifest: false,
+        };
+        assert_eq!(
+            patterns([&program]).unwrap(),
+            ["/src/uu/cat", "/src/uu/ls/src"]
+        );
+
+        program.crate_manifest = true;
+        assert_eq!(
+            patterns([&program]).unwrap(),
+            [
+                "/Cargo.toml",
+                "/src/Cargo.toml",
+                "/src/uu/Cargo.toml",
+                "/src/uu/cat",
+                "/src/uu/ls/Cargo.toml",
+                "/src/uu/ls/src",
+            ]
+        );
+
+        // A glob pattern at the root of the repository matches files
+        // anywhere, so everything is checked out.
+        program.crate_manifest = false;
+        program.source_paths = vec!["**/*.rs".to_string(), "src/uu/ls/src".to_string()];
+        assert_eq!(patterns([&program]).unwrap(), ["/*", "/src/uu/ls/src"]);
+    }
+}
@@ -3,112 +3,90 @@
 //! This module provides utility functions used in other parts of our code.
 
 use std::{
+    ffi::OsStr,
     fs,
-    path::{Path, MAIN_SEPARATOR_STR},
+    path::{Path, PathBuf},
 };
 
 use walkdir::WalkDir;
 
-use crate::corpus::errors::DownloaderError;
+use crate::{
+    corpus::{archive, downloader, errors::DownloaderError},
+    paths::{DIRECTORY_MARKER_FILE, PAIR_MANIFEST_FILE},
+};
 
-/// Count the number of immediate files in a directory, not including any
-/// files in sub-directories.
+/// Finds all files with one of the given extensions in a directory and its
+/// subdirectories.
 ///
 /// # Arguments
 ///
-/// `directory` - A path to the directory.
+/// - `directory` - The directory to search.
+/// - `extensions` - The extensions, without the dot, of the files to find.
+///   An entry may also be a whole file name, such as `Makefile`.
 ///
 /// # Returns
 ///
-/// The number of immediate files in the directory, or a [`DownloaderError`]
-/// if the operation fails.
-pub fn count_files(directory: &Path) -> Result<usize, DownloaderError> {
-    let entries = directory
-        .read_dir()
-        .map_err(|error| DownloaderError::IoRead {
-            path: directory.to_path_buf(),
-            error,
-        })?;
+/// The paths of the files found, sorted.
+pub fn find_source_files(directory: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    let mut source_files = Vec::new();
 
-    let mut total_files = 0;
-    for entry in entries {
-        let entry = entry.map_err(|error| DownloaderError::IoRead {
-            path: directory.to_path_buf(),
-            error,
-        })?;
-
-        let file_type = entry.file_type().map_err(|error| DownloaderError::IoRead {
-            path: directory.to_path_buf(),
-            error,
-        })?;
+    // Iterate recursively through every file in `directory`.
+    for entry in WalkDir::new(directory)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
 
-        if file_type.is_file() {
-            total_files += 1;
+        // Keep files whose extension or whole name was asked for.
+        let matches = |name: Option<&OsStr>| {
+            name.is_some_and(|name| {
+                extensions
+                    .iter()
+                    .any(|extension| name == extension.as_str())
+            })
+        };
+        if matches(path.extension()) || matches(path.file_name()) {
+            source_files.push(path.to_path_buf());
         }
     }
 
-    Ok(total_files)
+    source_files
 }
 
-/// Copies all .c, .h, and .rs files from a directory to the destination.
-///
-/// Copied files will all be directly under the destination directory;
-/// nested directories will not be copied. Files will have their paths
-/// included in their name. For example, a file found in a subdirectory
-/// "module/file.txt" will have a final name of module-file.txt.
+/// Finds all metadata files in a directory.
 ///
 /// # Arguments
 ///
-/// - `source` - The source directory to copy files from.
-/// - `destination` - The destination directory to copy files to.
+/// - `directory` - The directory to search, such as `metadata/project/`.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success and [`DownloaderError`] on failure.
-pub fn copy_files_from_directory(source: &Path, destination: &Path) -> Result<(), DownloaderError> {
-    // Create destination directory in case it doesn't exist.
-    fs::create_dir_all(destination).map_err(|error| DownloaderError::IoCopy {
-        source: source.to_path_buf(),
-        destination: destination.to_path_buf(),
-        error,
-    })?;
-
-    // Iterate recursively through every file in `source`.
-    for entry in WalkDir::new(source).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(extension) = path.extension() {
-                let extension = extension.to_str().ok_or_else(|| {
-                    DownloaderError::Io("Failed to retrieve file extension".to_string())
-                })?;
-
-                // Copy all `.c`, `.h`, and `.rs` files.
-                if matches!(extension, "c" | "h" | "rs") {
-                    // Include full path as filename but replace path
-                    // separator with '-' in filename.
-                    let relative_path = path.strip_prefix(source).unwrap_or(path);
-                    let filename = relative_path
-                        .to_str()
-                        .ok_or_else(|| {
-                            DownloaderError::Io(format!(
-                                "Failed to get filename for '{}'",
-                                path.display()
-                            ))
-                        })?
-                        .replace(MAIN_SEPARATOR_STR, "-");
-                    fs::copy(path, destination.join(filename)).map_err(|error| {
-                        DownloaderError::IoCopy {
-                            source: source.to_path_buf(),
-                            destination: destination.to_path_buf(),
-                            error,
-                        }
-                    })?;
-                }
-            }
-        }
-    }
+/// The paths of the `.json` files in `directory`, sorted, on success and
+/// [`DownloaderError`] on failure.
+pub fn find_metadata_files(directory: &Path) -> Result<Vec<PathBuf>, DownloaderError> {
+    let mut metadata_files = directory
+        .read_dir()
+        .map_err(|error| DownloaderError::IoRead {
+            path: directory.to_path_buf(),
+            error,
+        })?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| DownloaderError::IoRead {
+            path: directory.to_path_buf(),
+            error,
+        })?;
+    metadata_files.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "json")
+    });
+    metadata_files.sort();
 
-    Ok(())
+    Ok(metadata_files)
 }
 
 /// Extract a repository's name from its URL.
@@ -129,16 +107,365 @@ pub fn get_repository_name(url: &str) -> Result<String, DownloaderError> {
     let last_segment = url
         .trim_end_matches('/')
         .split('/')
-        .last()
+        .next_back()
         .expect("Unreachable because split always returns at least 1 element");
     let name = last_segment.strip_suffix(".git").unwrap_or(last_segment);
     Ok(name.to_string())
 }
 
+/// Normalizes a repository URL so that different spellings of the same
+/// repository compare equal.
+///
+/// The scheme, user name, port, trailing `/` and `.git` suffix are removed
+/// and the host is lowercased, leaving `host/owner/repository`.  Local
+/// repositories are placed under the pseudo-host `file`, by their absolute
+/// path.
+///
+/// # Example
+///
+/// "https://github.com/uutils/coreutils.git",
+/// "https://GitHub.com/uutils/coreutils/" and
+/// "git@github.com:uutils/coreutils" all normalize to
+/// "github.com/uutils/coreutils".
+///
+/// # Arguments
+///
+/// - `url` - Git repository URL or path.
+///
+/// # Returns
+///
+/// The normalized URL on success, or [`DownloaderError`] if the URL has no
+/// path or a remote URL contains a `..` component.
+pub fn normalize_repository_url(url: &str) -> Result<String, DownloaderError> {
+    let trimmed = url.trim().trim_end_matches('/');
+    let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+
+    let (host, path) = match trimmed.split_once("://") {
+        Some(("file", path)) => ("file".to_string(), path),
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+            (normalize_host(authority), path)
+        }
+        None if !is_local_url(trimmed) => {
+            // An scp-style address such as `git@github.com:owner/repository`.
+            let (authority, path) = trimmed.split_once(':').unwrap_or((trimmed, ""));
+            (normalize_host(authority), path)
+        }
+        None => ("file".to_string(), trimmed),
+    };
+
+    // Local paths may be relative, so resolve them against the working
+    // directory.  Only their `..` components can be resolved lexically.
+    let local = host == "file";
+    let path = if local {
+        std::path::absolute(path)
+            .map_err(|error| DownloaderError::Io(error.to_string()))?
+            .to_string_lossy()
+            .replace('\\', "/")
+    } else {
+        path.to_string()
+    };
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if local => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    if host.is_empty() || components.is_empty() || components.contains(&"..") {
+        return Err(DownloaderError::Io(format!(
+            "Failed to normalize repository URL '{url}'"
+        )));
+    }
+    Ok(format!("{host}/{}", components.join("/")))
+}
+
+/// Returns where a repository is cached, relative to the cache directory.
+///
+/// This is the normalized URL with its path escaped into a single
+/// directory name, so that the clone of one repository is never nested in
+/// the clone of another, such as `group/repository` and the GitLab subgroup
+/// `group/repository/subproject`.
+///
+/// # Example
+///
+/// "https://github.com/uutils/coreutils.git" is cached in
+/// "github.com/uutils%2Fcoreutils".
+///
+/// # Arguments
+///
+/// - `url` - Git repository URL or path.
+///
+/// # Returns
+///
+/// The host and escaped path on success, or [`DownloaderError`] if the URL
+/// cannot be normalized.
+pub fn cache_key(url: &str) -> Result<String, DownloaderError> {
+    let normalized_url = normalize_repository_url(url)?;
+    let (host, path) = normalized_url
+        .split_once('/')
+        .expect("Unreachable because a normalized URL has a host and a path");
+    Ok(format!(
+        "{host}/{}",
+        path.replace('%', "%25").replace('/', "%2F")
+    ))
+}
+
+/// Removes the user name and port from a URL authority and lowercases it.
+///
+/// # Arguments
+///
+/// - `authority` - The `user@host:port` part of a URL.
+///
+/// # Returns
+///
+/// The lowercased host.
+fn normalize_host(authority: &str) -> String {
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    let host = host.split(':').next().unwrap_or(host);
+    host.to_lowercase()
+}
+
+/// Converts a relative path to a string that uses `/` as its separator on
+/// every platform.
+///
+/// # Arguments
+///
+/// - `path` - A relative path.
+///
+/// # Returns
+///
+/// The path as a `/`-separated string, or [`DownloaderError`] if the path
+/// is not valid UTF-8.
+pub fn to_slash_path(path: &Path) -> Result<String, DownloaderError> {
+    let components = path
+        .components()
+        .map(|component| {
+            component.as_os_str().to_str().ok_or_else(|| {
+                DownloaderError::Io(format!("Path '{}' is not valid UTF-8", path.display()))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(components.join("/"))
+}
+
+/// Checks whether a repository URL refers to the local file system.
+///
+/// Shallow clones are not supported by libgit2's local transport, so local
+/// repositories are always cloned in full.
+///
+/// # Arguments
+///
+/// - `url` - Git repository URL or path.
+///
+/// # Returns
+///
+/// True if `url` is a `file://` URL, or a path that is neither a URL nor an
+/// scp-style `user@host:path` address.
+pub fn is_local_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => scheme == "file",
+        None => !url.contains('@'),
+    }
+}
+
+/// Returns the directory a clone is created in before it is moved into the
+/// clone cache, so an interrupted clone never leaves a partial repository
+/// there.
+///
+/// # Arguments
+///
+/// - `repository_path` - Where the repository is cached.
+/// - `extension` - Names what is being staged, such as `cloning`.
+///
+/// # Returns
+///
+/// A sibling of `repository_path` named after it.
+pub fn staging_path(repository_path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = repository_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(extension);
+    repository_path.with_file_name(file_name)
+}
+
+/// Creates a directory this tool writes to, and marks it as created by the
+/// tool so that `delete` may remove it.
+///
+/// An existing directory is only marked if it is empty or holds nothing but
+/// the layout the tool creates: program pair directories, or clones with a
+/// `.git` directory nested in directories of their own. This marks output
+/// and cache directories created before the marker existed, while a
+/// directory holding other files is never mistaken for one the tool created.
+///
+/// # Arguments
+///
+/// - `directory` - The output or cache directory.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or a [`DownloaderError`] on failure.
+pub fn mark_directory(directory: &Path) -> Result<(), DownloaderError> {
+    let to_error = |error| DownloaderError::IoCreate {
+        path: directory.to_path_buf(),
+        error,
+    };
+    fs::create_dir_all(directory).map_err(to_error)?;
+    if is_marked(directory) {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(directory).map_err(to_error)?;
+    let is_tool_layout = entries.all(|entry| {
+        entry.is_ok_and(|entry| {
+            let path = entry.path();
+            path.is_dir() && (is_pair_directory(&path) || is_clone_directory(&path))
+        })
+    });
+    if is_tool_layout {
+        fs::write(
+            directory.join(DIRECTORY_MARKER_FILE),
+            "This directory was created by c-rust-program-pairs, which may delete it.\n",
+        )
+        .map_err(to_error)?;
+    }
+    Ok(())
+}
+
+/// Checks whether a directory holds only the files the tool writes for a
+/// program pair.
+///
+/// # Arguments
+///
+/// - `directory` - A directory in the output directory.
+///
+/// # Returns
+///
+/// True if the directory is not empty and every entry is a program or the
+/// pair manifest.
+fn is_pair_directory(directory: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return false;
+    };
+    let names: Vec<_> = entries
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect();
+    !names.is_empty()
+        && names.iter().all(|name| {
+            name.as_ref().is_ok_and(|name| {
+                name == "c-program" || name == "rust-program" || name == PAIR_MANIFEST_FILE
+            })
+        })
+}
+
+/// Checks whether a directory is a clone, or holds only directories that
+/// are clones or hold clones in turn.
+///
+/// # Arguments
+///
+/// - `directory` - A directory in the cache directory.
+///
+/// # Returns
+///
+/// True if the directory has a `.git` directory, is a staging directory of
+/// a clone or archive, or every entry is a directory for which this is true.
+fn is_clone_directory(directory: &Path) -> bool {
+    let is_staging = directory.extension().is_some_and(|extension| {
+        [downloader::STAGING_EXTENSION, archive::STAGING_EXTENSION]
+            .map(OsStr::new)
+            .contains(&extension)
+    });
+    if is_staging || directory.join(".git").is_dir() {
+        return true;
+    }
+    let Ok(mut entries) = fs::read_dir(directory) else {
+        return false;
+    };
+    entries.all(|entry| {
+        entry.is_ok_and(|entry| {
+            let path = entry.path();
+            path.is_dir() && is_clone_directory(&path)
+        })
+    })
+}
+
+/// Checks whether a directory was created by this tool.
+///
+/// # Arguments
+///
+/// - `directory` - The output or cache directory.
+///
+/// # Returns
+///
+/// True if the directory holds the marker file.
+pub fn is_marked(directory: &Path) -> bool {
+    directory.join(DIRECTORY_MARKER_FILE).is_file()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    /// Tests that different spellings of a repository URL normalize to the
+    /// same cache key, and that different repositories do not.
+    fn test_normalize_repository_url() {
+        for url in [
+            "https://github.com/uutils/coreutils",
+            "https://github.com/uutils/coreutils.git",
+            "https://GitHub.com/uutils/coreutils/",
+            "ssh://git@github.com:22/uutils/coreutils.git",
+            "git@github.com:uutils/coreutils.git",
+        ] {
+            assert_eq!(
+                "github.com/uutils/coreutils",
+                normalize_repository_url(url).unwrap()
+            );
+        }
+        assert_eq!(
+            "github.com/coreutils/coreutils",
+            normalize_repository_url("https://github.com/coreutils/coreutils").unwrap()
+        );
+        assert_eq!(
+            "file/srv/mirrors/coreutils",
+            normalize_repository_url("file:///srv/mirrors/coreutils.git").unwrap()
+        );
+        assert_eq!(
+            "file/srv/mirrors/coreutils",
+            normalize_repository_url("/srv/mirrors/coreutils").unwrap()
+        );
+        let working_directory = std::env::current_dir().unwrap();
+        assert_eq!(
+            format!(
+                "file{}/repos/coreutils",
+                working_directory.parent().unwrap().display()
+            ),
+            normalize_repository_url("../repos/coreutils/").unwrap()
+        );
+        assert!(normalize_repository_url("https://github.com/../etc").is_err());
+        assert!(normalize_repository_url("https://github.com/").is_err());
+    }
+
+    #[test]
+    /// Tests that cache keys are one directory per host and repository, so
+    /// that the clones of nested GitLab groups do not nest.
+    fn test_cache_key() {
+        assert_eq!(
+            "github.com/uutils%2Fcoreutils",
+            cache_key("git@github.com:uutils/coreutils.git").unwrap()
+        );
+        assert_eq!(
+            "gitlab.com/group%2Frepository%2Fsubproject",
+            cache_key("https://gitlab.com/group/repository/subproject").unwrap()
+        );
+        assert_eq!(
+            "file/srv%2Fmirrors%2F100%25",
+            cache_key("/srv/mirrors/100%").unwrap()
+        );
+        assert!(cache_key("https://github.com/").is_err());
+    }
+
     #[test]
     /// Tests that a repository name can be extracted from a URL.
     fn test_get_repository_name() {
@@ -151,4 +478,41 @@ mod tests {
             get_repository_name("https://github.com/eza-community/eza").unwrap()
         );
     }
+
+    #[test]
+    /// Tests that a directory is marked if it is empty or holds only program
+    /// pairs or clones, and is not marked if it holds anything else.
+    fn test_mark_directory() {
+        let directory = tempfile::TempDir::new().unwrap();
+
+        let empty = directory.path().join("empty");
+        mark_directory(&empty).unwrap();
+        assert!(is_marked(&empty));
+
+        let output = directory.path().join("program_pairs");
+        fs::create_dir_all(output.join("cat/c-program")).unwrap();
+        fs::create_dir_all(output.join("cat/rust-program")).unwrap();
+        fs::write(output.join("cat").join(PAIR_MANIFEST_FILE), "{}").unwrap();
+        fs::create_dir_all(output.join("ls/c-program")).unwrap();
+        mark_directory(&output).unwrap();
+        assert!(is_marked(&output));
+
+        let cache = directory.path().join("repository_clones");
+        fs::create_dir_all(cache.join("github.com/uutils/coreutils/.git")).unwrap();
+        fs::create_dir_all(cache.join("gitlab.com/group/sub/repo/.git")).unwrap();
+        mark_directory(&cache).unwrap();
+        assert!(is_marked(&cache));
+
+        let other = directory.path().join("other");
+        fs::create_dir_all(other.join("cat/c-program")).unwrap();
+        fs::write(other.join("cat/notes.txt"), "keep").unwrap();
+        mark_directory(&other).unwrap();
+        assert!(!is_marked(&other));
+
+        let stray = directory.path().join("stray");
+        fs::create_dir_all(stray.join("github.com/uutils/coreutils/.git")).unwrap();
+        fs::write(stray.join("github.com/README"), "keep").unwrap();
+        mark_directory(&stray).unwrap();
+        assert!(!is_marked(&stray));
+    }
 }
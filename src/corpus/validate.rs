@@ -0,0 +1,749 @@
+//! # Metadata Validation
+//!
+//! The main entry point is [`validate`], which checks every metadata file in
+//! some directories against the JSON schema and a set of semantic rules.
+//! Unlike [`parse`](crate::corpus::parse), it reports every problem it finds
+//! rather than stopping at the first.
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use jsonschema::Validator;
+use serde_json::{Value, json};
+
+use crate::{
+    corpus::{
+        errors::{DownloaderError, ParserError},
+        parser,
+        patterns::SourcePatterns,
+        utils,
+    },
+    paths::METADATA_SCHEMA_FILE,
+};
+
+/// A problem found in a metadata file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The file containing the problem.
+    pub path: PathBuf,
+    /// The JSON pointer to the offending value, if known.
+    pub pointer: Option<String>,
+    /// The line and column, both starting at 1, of the offending value, if
+    /// known.
+    pub position: Option<(usize, usize)>,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for a whole file.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The file containing the problem.
+    /// - `message` - A description of the problem.
+    fn file(path: &Path, message: String) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            pointer: None,
+            position: None,
+            message,
+        }
+    }
+
+    /// Creates a diagnostic for the value that a JSON pointer refers to.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The file containing the problem.
+    /// - `text` - The contents of the file.
+    /// - `pointer` - The JSON pointer to the offending value.
+    /// - `message` - A description of the problem.
+    fn at(path: &Path, text: &str, pointer: String, message: String) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            position: locate(text, &pointer),
+            pointer: Some(pointer),
+            message,
+        }
+    }
+
+    /// Returns the file, line and column of the problem, such as
+    /// `metadata/project/coreutils.json:12:17`.
+    fn location(&self) -> String {
+        match self.position {
+            Some((line, column)) => format!("{}:{line}:{column}", self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{}: ", self.location())?;
+        if let Some(pointer) = self
+            .pointer
+            .as_deref()
+            .filter(|pointer| !pointer.is_empty())
+        {
+            write!(formatter, "{pointer}: ")?;
+        }
+        write!(formatter, "{}", self.message)
+    }
+}
+
+/// The JSON schema split into one validator per kind of metadata file.
+///
+/// The schema accepts either kind through a top-level `oneOf`, so
+/// validating against it directly only reports that neither kind matched.
+/// Validating against the matching kind instead reports where the file
+/// went wrong.
+struct SchemaValidators {
+    /// Validates individual-type metadata.
+    individual: Validator,
+    /// Validates project-type metadata.
+    project: Validator,
+}
+
+impl SchemaValidators {
+    /// Reads the JSON schema and creates its validators.
+    ///
+    /// # Returns
+    ///
+    /// The validators on success and [`ParserError`] on failure.
+    fn new() -> Result<Self, ParserError> {
+        let schema = parser::read_schema()?;
+        Ok(Self {
+            individual: validator_for_kind(&schema, false)?,
+            project: validator_for_kind(&schema, true)?,
+        })
+    }
+}
+
+/// Creates a validator for one kind of metadata file.
+///
+/// # Arguments
+///
+/// - `schema` - The project's JSON schema.
+/// - `project` - Whether to validate project-type, rather than
+///   individual-type, metadata.
+///
+/// # Returns
+///
+/// The validator on success and [`ParserError`] on failure.
+fn validator_for_kind(schema: &Value, project: bool) -> Result<Validator, ParserError> {
+    let mut schema = schema.clone();
+    if let Some(object) = schema.as_object_mut()
+        && let Some(Value::Array(kinds)) = object.remove("oneOf")
+    {
+        let kind = kinds
+            .into_iter()
+            .find(|kind| kind.pointer("/properties/project_information").is_some() == project);
+        if let Some(kind) = kind {
+            object.insert("allOf".to_string(), json!([kind]));
+        }
+    }
+
+    jsonschema::validator_for(&schema).map_err(|error| ParserError::Validation {
+        error: error.to_string(),
+    })
+}
+
+/// Validates every metadata file in the given directories.
+///
+/// Besides the JSON schema, the following rules are checked:
+///
+/// - No two program pairs share a `program_name`.
+/// - No `source_paths` is empty or contains a malformed glob pattern.
+/// - Every `repository_url` is an HTTP(S) URL, a `file://` URL or a local
+///   path.
+/// - No program sets more than one of `commit`, `tag` and `branch`.
+/// - No `rust_program` uses the same repository as its `c_program`.
+///
+/// # Arguments
+///
+/// - `directories` - The directories containing the metadata JSON files,
+///   typically `metadata/individual/` and `metadata/projects/`.
+///
+/// # Returns
+///
+/// Every problem found, in file order; empty if the metadata is valid.
+pub fn validate(directories: &[PathBuf]) -> Vec<Diagnostic> {
+    let validators = match SchemaValidators::new() {
+        Ok(validators) => validators,
+        Err(error) => {
+            return vec![Diagnostic::file(
+                Path::new(METADATA_SCHEMA_FILE),
+                error.to_string(),
+            )];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    let mut program_names = HashMap::new();
+    for directory in directories {
+        match utils::find_metadata_files(directory) {
+            Ok(metadata_files) => {
+                for metadata_file in metadata_files {
+                    validate_file(
+                        &metadata_file,
+                        &validators,
+                        &mut program_names,
+                        &mut diagnostics,
+                    );
+                }
+            }
+            Err(error) => diagnostics.push(Diagnostic::file(directory, error.to_string())),
+        }
+    }
+    diagnostics
+}
+
+/// Validates a single metadata file.
+///
+/// The semantic rules are only checked once the file matches the schema.
+///
+/// # Arguments
+///
+/// - `path` - The metadata file.
+/// - `validators` - The JSON schema validators.
+/// - `program_names` - The location of every program name seen so far;
+///   updated with the names in this file.
+/// - `diagnostics` - The problems found so far; updated with the problems
+///   in this file.
+fn validate_file(
+    path: &Path,
+    validators: &SchemaValidators,
+    program_names: &mut HashMap<String, String>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            diagnostics.push(Diagnostic::file(path, format!("Failed to read: {error}")));
+            return;
+        }
+    };
+    let value: Value = match serde_json::from_str(&text) {
+        Ok(value) => value,
+        Err(error) => {
+            diagnostics.push(Diagnostic {
+                position: Some((error.line(), error.column())),
+                ..Diagnostic::file(path, format!("Invalid JSON: {error}"))
+            });
+            return;
+        }
+    };
+
+    // Check the file against the schema for its kind.
+    let project = value.get("project_information").is_some();
+    let validator = if project {
+        &validators.project
+    } else {
+        &validators.individual
+    };
+    let schema_diagnostics: Vec<_> = validator
+        .iter_errors(&value)
+        .map(|error| {
+            let pointer = error.instance_path().as_str().to_string();
+            Diagnostic::at(path, &text, pointer, error.to_string())
+        })
+        .collect();
+    if !schema_diagnostics.is_empty() {
+        diagnostics.extend(schema_diagnostics);
+        return;
+    }
+
+    // Conflicting revisions fail parsing, so they are located in the file
+    // itself first.
+    let programs: Vec<String> = if project {
+        vec![
+            "/project_information/c_program".to_string(),
+            "/project_information/rust_program".to_string(),
+        ]
+    } else {
+        let pairs = value["pairs"].as_array().map_or(0, Vec::len);
+        (0..pairs)
+            .flat_map(|index| {
+                ["c_program", "rust_program"].map(|side| format!("/pairs/{index}/{side}"))
+            })
+            .collect()
+    };
+    let mut conflicting = false;
+    for program in programs {
+        let revisions: Vec<_> = ["commit", "tag", "branch"]
+            .into_iter()
+            .filter(|field| value.pointer(&format!("{program}/{field}")).is_some())
+            .collect();
+        if let [first, second, ..] = revisions[..] {
+            diagnostics.push(Diagnostic::at(
+                path,
+                &text,
+                format!("{program}/{second}"),
+                format!("'{second}' cannot be combined with '{first}'; set only one revision"),
+            ));
+            conflicting = true;
+        }
+    }
+    if conflicting {
+        return;
+    }
+
+    let metadata = match parser::parse(path) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            diagnostics.push(Diagnostic::file(path, error.to_string()));
+            return;
+        }
+    };
+
+    // Project-type metadata shares one pair of repositories between all
+    // pairs, so the same problem is only reported once.
+    let mut push = |diagnostic: Diagnostic| {
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    };
+    for (index, pair) in metadata.pairs.iter().enumerate() {
+        let pair_pointer = format!("/pairs/{index}");
+        // Points to a field given per program, which project metadata gives
+        // once for all pairs.
+        let program_pointer = |side: &str, field: &str| {
+            if project {
+                format!("/project_information/{side}/{field}")
+            } else {
+                format!("{pair_pointer}/{side}/{field}")
+            }
+        };
+
+        let name_diagnostic = Diagnostic::at(
+            path,
+            &text,
+            format!("{pair_pointer}/program_name"),
+            format!("Duplicate program name '{}'", pair.program_name),
+        );
+        match program_names.get(&pair.program_name) {
+            Some(first) => push(Diagnostic {
+                message: format!("{}; first defined at {first}", name_diagnostic.message),
+                ..name_diagnostic
+            }),
+            None => {
+                program_names.insert(pair.program_name.clone(), name_diagnostic.location());
+            }
+        }
+
+        for (side, program) in [
+            ("c_program", &pair.c_program),
+            ("rust_program", &pair.rust_program),
+        ] {
+            if program.source_paths.is_empty() {
+                push(Diagnostic::at(
+                    path,
+                    &text,
+                    format!("{pair_pointer}/{side}/source_paths"),
+                    "'source_paths' is empty".to_string(),
+                ));
+            }
+            if let Err(DownloaderError::InvalidPattern { pattern, error }) =
+                SourcePatterns::new(&program.source_paths)
+            {
+                let index = program
+                    .source_paths
+                    .iter()
+                    .position(|source_path| *source_path == pattern)
+                    .unwrap_or_default();
+                push(Diagnostic::at(
+                    path,
+                    &text,
+                    format!("{pair_pointer}/{side}/source_paths/{index}"),
+                    format!("Invalid pattern '{pattern}': {error}"),
+                ));
+            }
+            if !is_http_url(&program.repository_url)
+                && !utils::is_local_url(&program.repository_url)
+            {
+                push(Diagnostic::at(
+                    path,
+                    &text,
+                    program_pointer(side, "repository_url"),
+                    format!(
+                        "Repository URL '{}' is neither an HTTP(S) URL nor a local path",
+                        program.repository_url
+                    ),
+                ));
+            }
+            if program.archive.is_some() && program.revision.is_some() {
+                push(Diagnostic::at(
+                    path,
+                    &text,
+                    program_pointer(side, "archive_url"),
+                    "'archive_url' cannot be combined with 'commit', 'tag' or 'branch'".to_string(),
+                ));
+            }
+        }
+
+        if let (Ok(c_repository), Ok(rust_repository)) = (
+            utils::normalize_repository_url(&pair.c_program.repository_url),
+            utils::normalize_repository_url(&pair.rust_program.repository_url),
+        ) && c_repository == rust_repository
+        {
+            push(Diagnostic::at(
+                path,
+                &text,
+                program_pointer("rust_program", "repository_url"),
+                format!(
+                    "'rust_program' uses the same repository as 'c_program': '{}'",
+                    pair.rust_program.repository_url
+                ),
+            ));
+        }
+    }
+}
+
+/// Checks whether a repository URL is an HTTP(S) URL.
+///
+/// # Arguments
+///
+/// - `url` - The repository URL.
+///
+/// # Returns
+///
+/// `true` if `url` has an `http` or `https` scheme and a host.
+fn is_http_url(url: &str) -> bool {
+    url.split_once("://").is_some_and(|(scheme, rest)| {
+        (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+            && !rest.starts_with('/')
+            && !rest.is_empty()
+    })
+}
+
+/// Finds the line and column of the value that a JSON pointer refers to.
+///
+/// # Arguments
+///
+/// - `text` - A JSON document.
+/// - `pointer` - A JSON pointer into `text`, such as `/pairs/0/program_name`.
+///
+/// # Returns
+///
+/// The line and column, both starting at 1, or `None` if `text` has no
+/// such value.
+fn locate(text: &str, pointer: &str) -> Option<(usize, usize)> {
+    let target: Vec<String> = pointer
+        .split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    let mut scanner = Scanner {
+        text: text.as_bytes(),
+        position: 0,
+    };
+    let offset = scanner.find(&mut Vec::new(), &target)?;
+
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    let column = before[line_start..].chars().count() + 1;
+    Some((line, column))
+}
+
+/// A minimal JSON scanner that tracks the byte offset of each value.
+///
+/// `serde_json` does not keep the position of values it parses, so
+/// [`locate`] walks the document itself.
+struct Scanner<'a> {
+    /// The JSON document.
+    text: &'a [u8],
+    /// The byte offset of the next unread byte.
+    position: usize,
+}
+
+impl Scanner<'_> {
+    /// Scans the value at the current position for the value at `target`.
+    ///
+    /// # Arguments
+    ///
+    /// - `path` - The path of the value at the current position.
+    /// - `target` - The path of the value to find.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset of the value at `target`, or `None` if it is not
+    /// within the current value.
+    fn find(&mut self, path: &mut Vec<String>, target: &[String]) -> Option<usize> {
+        self.skip_whitespace();
+        if path.as_slice() == target {
+            return Some(self.position);
+        }
+
+        match self.text.get(self.position)? {
+            b'{' => {
+                self.position += 1;
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(b'}') {
+                        break;
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.eat(b':') {
+                        return None;
+                    }
+                    path.push(key);
+                    let found = self.find(path, target);
+                    path.pop();
+                    if found.is_some() {
+                        return found;
+                    }
+                    self.skip_whitespace();
+                    if self.eat(b'}') {
+                        break;
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            b'[' => {
+                self.position += 1;
+                let mut index = 0;
+                loop {
+                    self.skip_whitespace();
+                    if self.eat(b']') {
+                        break;
+                    }
+                    path.push(index.to_string());
+                    let found = self.find(path, target);
+                    path.pop();
+                    if found.is_some() {
+                        return found;
+                    }
+                    index += 1;
+                    self.skip_whitespace();
+                    if self.eat(b']') {
+                        break;
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            b'"' => {
+                self.string()?;
+            }
+            _ => {
+                // Numbers, booleans and `null`.
+                while let Some(byte) = self.text.get(self.position)
+                    && !matches!(byte, b',' | b'}' | b']')
+                    && !byte.is_ascii_whitespace()
+                {
+                    self.position += 1;
+                }
+            }
+        }
+        None
+    }
+
+    /// Reads the string at the current position.
+    ///
+    /// # Returns
+    ///
+    /// The unescaped string, or `None` if there is no string at the current
+    /// position.
+    fn string(&mut self) -> Option<String> {
+        let start = self.position;
+        if !self.eat(b'"') {
+            return None;
+        }
+        while let Some(&byte) = self.text.get(self.position) {
+            self.position += 1;
+            match byte {
+                b'\\' => self.position += 1,
+                b'"' => return serde_json::from_slice(&self.text[start..self.position]).ok(),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Skips any whitespace at the current position.
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.position)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Skips `byte` if it is at the current position.
+    ///
+    /// # Returns
+    ///
+    /// Whether `byte` was skipped.
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.text.get(self.position) == Some(&byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::DEMO_METADATA_DIRECTORY;
+
+    /// Returns an individual-type metadata file with a single pair.
+    fn individual_metadata(
+        program_name: &str,
+        c_repository_url: &str,
+        rust_repository_url: &str,
+        rust_source_paths: &[&str],
+    ) -> String {
+        let metadata = json!({
+            "pairs": [{
+                "program_name": program_name,
+                "program_description": "A test program.",
+                "translation_tools": ["manual"],
+                "feature_relationship": "rust_equivalent_to_c",
+                "c_program": {
+                    "documentation_url": "https://example.com/c",
+                    "repository_url": c_repository_url,
+                    "source_paths": ["src/main.c"]
+                },
+                "rust_program": {
+                    "documentation_url": "https://example.com/rust",
+                    "repository_url": rust_repository_url,
+                    "source_paths": rust_source_paths
+                }
+            }]
+        });
+        serde_json::to_string_pretty(&metadata).unwrap()
+    }
+
+    /// Tests that JSON pointers are mapped to the line and column of their
+    /// value.
+    #[test]
+    fn test_locate() {
+        let text = "{\n  \"a\": [1, {\"b/c\": \"x\\\"y\"}],\n  \"d\": true\n}";
+        assert_eq!(locate(text, ""), Some((1, 1)));
+        assert_eq!(locate(text, "/a"), Some((2, 8)));
+        assert_eq!(locate(text, "/a/0"), Some((2, 9)));
+        assert_eq!(locate(text, "/a/1/b~1c"), Some((2, 20)));
+        assert_eq!(locate(text, "/d"), Some((3, 8)));
+        assert_eq!(locate(text, "/e"), None);
+    }
+
+    /// Tests that the demo metadata is valid.
+    #[test]
+    fn test_validate_demo_metadata() {
+        let diagnostics = validate(&[PathBuf::from(DEMO_METADATA_DIRECTORY)]);
+        assert_eq!(diagnostics, Vec::new());
+    }
+
+    /// Tests that schema violations are reported with their location.
+    #[test]
+    fn test_validate_schema() {
+        let directory = tempfile::tempdir().unwrap();
+        let metadata = individual_metadata(
+            "test",
+            "https://example.com/c",
+            "https://example.com/rust",
+            &["src/main.rs"],
+        )
+        .replace("rust_equivalent_to_c", "unknown");
+        fs::write(directory.path().join("test.json"), metadata).unwrap();
+
+        let diagnostics = validate(&[directory.path().to_path_buf()]);
+        assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+        assert_eq!(
+            diagnostics[0].pointer.as_deref(),
+            Some("/pairs/0/feature_relationship")
+        );
+        assert_eq!(diagnostics[0].position, Some((11, 31)));
+    }
+
+    /// Tests that every semantic rule is checked, across files.
+    #[test]
+    fn test_validate_semantics() {
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(
+            directory.path().join("a.json"),
+            individual_metadata(
+                "test",
+                "https://example.com/repository",
+                "https://example.com/repository.git",
+                &[],
+            ),
+        )
+        .unwrap();
+        fs::write(
+            directory.path().join("b.json"),
+            individual_metadata(
+                "test",
+                "ftp://example.com/c.git",
+                "https://example.com/rust",
+                &["src/main.rs", "src/[a-.rs"],
+            ),
+        )
+        .unwrap();
+        let mut archived: Value = serde_json::from_str(&individual_metadata(
+            "archived",
+            "https://example.com/c",
+            "https://example.com/rust",
+            &["src/main.rs"],
+        ))
+        .unwrap();
+        let c_program = &mut archived["pairs"][0]["c_program"];
+        c_program["archive_url"] = json!("https://example.com/c-1.0.tar.gz");
+        c_program["archive_sha256"] = json!("0".repeat(64));
+        c_program["tag"] = json!("v1.0");
+        fs::write(directory.path().join("c.json"), archived.to_string()).unwrap();
+        let mut pinned: Value = serde_json::from_str(&individual_metadata(
+            "pinned",
+            "https://example.com/c",
+            "https://example.com/rust",
+            &["src/main.rs"],
+        ))
+        .unwrap();
+        let rust_program = &mut pinned["pairs"][0]["rust_program"];
+        rust_program["commit"] = json!("0".repeat(40));
+        rust_program["branch"] = json!("main");
+        fs::write(directory.path().join("d.json"), pinned.to_string()).unwrap();
+        // Local repositories are accepted, like the downloader does.
+        fs::write(
+            directory.path().join("e.json"),
+            individual_metadata("local", "file:///srv/c", "../rust", &["src/main.rs"]),
+        )
+        .unwrap();
+
+        let messages: Vec<_> = validate(&[directory.path().to_path_buf()])
+            .into_iter()
+            .map(|diagnostic| (diagnostic.pointer.unwrap(), diagnostic.message))
+            .collect();
+        assert_eq!(messages.len(), 7, "{messages:?}");
+        assert_eq!(messages[0].0, "/pairs/0/rust_program/source_paths");
+        assert_eq!(messages[1].0, "/pairs/0/rust_program/repository_url");
+        assert!(messages[1].1.contains("same repository"));
+        assert_eq!(messages[2].0, "/pairs/0/program_name");
+        assert!(messages[2].1.contains("a.json:13:"));
+        assert_eq!(messages[3].0, "/pairs/0/c_program/repository_url");
+        assert!(messages[3].1.contains("neither an HTTP(S) URL"));
+        assert_eq!(messages[4].0, "/pairs/0/rust_program/source_paths/1");
+        assert!(messages[4].1.contains("Invalid pattern"));
+        assert_eq!(messages[5].0, "/pairs/0/c_program/archive_url");
+        assert!(messages[5].1.contains("cannot be combined"));
+        assert_eq!(messages[6].0, "/pairs/0/rust_program/branch");
+        assert!(
+            messages[6]
+                .1
+                .contains("'branch' cannot be combined with 'commit'")
+        );
+    }
+}
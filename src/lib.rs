@@ -1,18 +1,40 @@
 //! # C-Rust program pair downloader
+//!
+//! Besides the command-line tool, this crate is a library for tools that
+//! analyze the corpus.  [`Corpus`] loads program pairs from metadata, looks
+//! them up by name, filters them with a [`PairFilter`], and lists the files
+//! each pair was downloaded to; [`download_program_pairs`] downloads them.
 
 mod cli;
 mod corpus;
 mod paths;
+mod table;
 
-use std::{fs, io::Error, path::Path};
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
 
 use clap::Parser;
 
-pub use corpus::download_program_pairs;
+pub use corpus::{
+    Corpus, DownloadOptions, Layout, PairFiles, ProgramFiles, UpdateSummary,
+    config::Directories,
+    download_program_pairs,
+    downloader::metadata_directories,
+    errors::{DownloaderError, ParserError},
+    filter::PairFilter,
+    report::{
+        DownloadReport, ErrorReport, MetadataFileReport, PairReport, PairStatus, ProgramReport,
+    },
+    schema::{Archive, Features, Language, Program, ProgramPair, Revision},
+    update_program_pairs,
+};
 
 use crate::{
-    cli::{Cli, Commands},
-    paths::{PROGRAM_PAIRS_DIRECTORY, REPOSITORY_CLONES_DIRECTORY},
+    cli::{Cli, Commands, DirectoryArgs, ListArgs, ShowArgs},
+    corpus::{DeleteOptions, ExportOptions, Removal, config::Config, downloader},
+    paths::CONFIG_FILE,
 };
 
 /// Downloads program pairs.
@@ -22,12 +44,521 @@ use crate::{
 /// specified within the `demo/` directory.
 pub fn run() {
     let cli = Cli::parse();
+    let directories = directories(cli.directories);
     match cli.command {
-        None => corpus::download_program_pairs(false).expect("Failed to download program pairs"),
-        Some(Commands::Demo) => corpus::download_program_pairs(true).expect("Failed to run demo"),
-        Some(Commands::Download) => {
-            corpus::download_program_pairs(false).expect("Failed to download program pairs")
+        None => {
+            let report = corpus::download_program_pairs(
+                &directories.metadata(false),
+                &PairFilter::default(),
+                &DownloadOptions {
+                    directories,
+                    ..DownloadOptions::default()
+                },
+            )
+            .expect("Failed to download program pairs");
+            finish_download(&report, None, false);
+        }
+        Some(Commands::Demo) => {
+            let report = corpus::download_program_pairs(
+                &directories.metadata(true),
+                &PairFilter::default(),
+                &DownloadOptions {
+                    directories,
+                    ..DownloadOptions::default()
+                },
+            )
+            .expect("Failed to run demo");
+            finish_download(&report, None, false);
+        }
+        Some(Commands::Download(arguments)) => {
+            let metadata = if arguments.metadata.is_empty() {
+                directories.metadata(false)
+            } else {
+                arguments.metadata
+            };
+            let filter = PairFilter {
+                names: arguments.pairs,
+                ..arguments.filter.into()
+            };
+            let options = DownloadOptions {
+                locked: arguments.locked,
+                layout: arguments.layout,
+                jobs: arguments.jobs,
+                offline: arguments.offline,
+                sparse: arguments.sparse,
+                directories,
+            };
+            let report = corpus::download_program_pairs(&metadata, &filter, &options)
+                .expect("Failed to download program pairs");
+            finish_download(&report, arguments.report.as_deref(), arguments.keep_going);
+        }
+        Some(Commands::Update(arguments)) => {
+            let options = DownloadOptions {
+                layout: arguments.layout,
+                jobs: arguments.jobs,
+                sparse: arguments.sparse,
+                directories,
+                ..DownloadOptions::default()
+            };
+            let summary =
+                corpus::update_program_pairs(&options.directories.metadata(false), &options)
+                    .expect("Failed to update program pairs");
+            print_update_summary(&summary);
+        }
+        Some(Commands::Delete(arguments)) => {
+            let options = DeleteOptions {
+                pairs: arguments.pairs,
+                repositories: arguments.repositories,
+                outputs_only: arguments.outputs_only,
+                cache_only: arguments.cache_only,
+                dry_run: arguments.dry_run,
+                force: arguments.force,
+            };
+            let removals =
+                corpus::delete(&directories, &options).expect("Failed to delete directories");
+            print_removals(&removals, options.dry_run);
+        }
+        Some(Commands::Gc(arguments)) => {
+            let removals = corpus::gc(&directories, arguments.dry_run, arguments.force)
+                .expect("Failed to remove unused clones");
+            print_removals(&removals, arguments.dry_run);
+        }
+        Some(Commands::Validate) => validate(&directories),
+        Some(Commands::CheckPaths(arguments)) => check_paths(&directories, arguments.demo),
+        Some(Commands::List(arguments)) => list(&directories, arguments),
+        Some(Commands::Show(arguments)) => show(&directories, arguments),
+        Some(Commands::Export(arguments)) => {
+            let options = ExportOptions {
+                format: arguments.format,
+                split_files: arguments.split_files,
+                skip_records_over_bytes: arguments.skip_records_over_bytes,
+            };
+            let output = arguments
+                .output
+                .unwrap_or_else(|| PathBuf::from(options.format.default_output()));
+            let summary = corpus::export(&directories.output, &options, &output)
+                .expect("Failed to export the corpus");
+            println!(
+                "Exported {} program pair(s) to '{}'",
+                summary.pairs,
+                output.display()
+            );
+            for record in &summary.skipped_records {
+                match &record.path {
+                    Some(path) => println!(
+                        "Skipped the record of '{path}' in '{}', which is {} bytes long",
+                        record.program_name, record.bytes
+                    ),
+                    None => println!(
+                        "Skipped pair '{}', whose record is {} bytes long",
+                        record.program_name, record.bytes
+                    ),
+                }
+            }
+            if !summary.skipped_records.is_empty() {
+                println!(
+                    "Skipped {} record(s) longer than {} bytes",
+                    summary.skipped_records.len(),
+                    options.skip_records_over_bytes.unwrap_or_default()
+                );
+            }
+            if !summary.missing_pairs.is_empty() {
+                println!(
+                    "{} program pair(s) are missing from the dataset: {}",
+                    summary.missing_pairs.len(),
+                    summary.missing_pairs.join(", ")
+                );
+            }
+        }
+        Some(Commands::Import(arguments)) => {
+            let count = corpus::import(&arguments.archive, &directories.output)
+                .expect("Failed to import the corpus");
+            println!("Imported {count} program pair(s)");
+        }
+    }
+}
+
+/// Resolves the directories the corpus is read from and written to.
+///
+/// Command-line flags and environment variables take precedence over the
+/// configuration file, which takes precedence over the defaults.
+///
+/// # Arguments
+///
+/// - `arguments` - The directory arguments given on the command line.
+///
+/// # Returns
+///
+/// The resolved [`Directories`].
+fn directories(arguments: DirectoryArgs) -> Directories {
+    let root = arguments.root.unwrap_or_default();
+    let config = Config::read(&root.join(CONFIG_FILE)).expect("Failed to read configuration");
+    let mut directories = config.directories(&root);
+    if !arguments.metadata_directories.is_empty() {
+        directories.metadata = arguments.metadata_directories;
+    }
+    if let Some(output) = arguments.output_directory {
+        directories.output = output;
+    }
+    if let Some(cache) = arguments.cache_directory {
+        directories.cache = cache;
+    }
+    directories
+}
+
+/// Prints a summary of a download, writes its report and exits with a
+/// non-zero status if it failed.
+///
+/// # Arguments
+///
+/// - `report` - The report of the download.
+/// - `report_path` - Where to write the report as JSON, if anywhere.
+/// - `keep_going` - Whether to exit successfully even if some pairs or
+///   metadata files failed.
+fn finish_download(report: &DownloadReport, report_path: Option<&Path>, keep_going: bool) {
+    print_download_report(report);
+    if let Some(report_path) = report_path {
+        report
+            .write(report_path)
+            .expect("Failed to write the download report");
+        println!("Wrote the download report to '{}'", report_path.display());
+    }
+    if !report.is_success() && !keep_going {
+        process::exit(1);
+    }
+}
+
+/// Prints a table of every downloaded pair, followed by the metadata files
+/// that failed to parse and the totals.
+///
+/// # Arguments
+///
+/// - `report` - The report of the download.
+fn print_download_report(report: &DownloadReport) {
+    let rows: Vec<Vec<String>> = report
+        .pairs
+        .iter()
+        .map(|pair| {
+            let status = match pair.status {
+                PairStatus::Downloaded => "downloaded",
+                PairStatus::Failed => "failed",
+            };
+            vec![
+                pair.program_name.clone(),
+                status.to_string(),
+                pair.c_program.files.to_string(),
+                pair.rust_program.files.to_string(),
+                format!(
+                    "{:.2}",
+                    pair.c_program.clone_seconds + pair.rust_program.clone_seconds
+                ),
+                format!(
+                    "{:.2}",
+                    pair.c_program.copy_seconds + pair.rust_program.copy_seconds
+                ),
+                format_bytes(pair.c_program.received_bytes + pair.rust_program.received_bytes),
+                pair.error
+                    .as_ref()
+                    .map_or_else(|| "-".to_string(), |error| error.kind.clone()),
+            ]
+        })
+        .collect();
+    print!(
+        "{}",
+        table::format_table(
+            &[
+                "PAIR",
+                "STATUS",
+                "C FILES",
+                "RUST FILES",
+                "CLONE (s)",
+                "COPY (s)",
+                "RECEIVED",
+                "ERROR"
+            ],
+            &rows
+        )
+    );
+    for metadata_file in &report.metadata_files {
+        if let Some(error) = &metadata_file.error {
+            println!(
+                "Failed to parse '{}': {}",
+                metadata_file.path.display(),
+                error.kind
+            );
         }
-        Some(Commands::Delete) => corpus::delete().expect("Failed to delete directories"),
+    }
+    println!(
+        "{} downloaded, {} failed, {} metadata file(s) failed to parse",
+        report.pairs.len() - report.failed_pairs(),
+        report.failed_pairs(),
+        report.failed_metadata_files()
+    );
+}
+
+/// Formats a number of bytes with a binary unit, such as `1.5 MiB`.
+///
+/// # Arguments
+///
+/// - `bytes` - The number of bytes.
+///
+/// # Returns
+///
+/// The formatted size.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Prints what `delete` or `gc` removed, with the space each path took up.
+///
+/// # Arguments
+///
+/// - `removals` - What was removed, or would be in a dry run.
+/// - `dry_run` - Whether nothing was actually removed.
+fn print_removals(removals: &[Removal], dry_run: bool) {
+    let (removed, freed) = if dry_run {
+        ("Would remove", "Would free")
+    } else {
+        ("Removed", "Freed")
+    };
+    for removal in removals {
+        println!(
+            "{removed} '{}' ({})",
+            removal.path.display(),
+            format_bytes(removal.bytes)
+        );
+    }
+    let bytes = removals.iter().map(|removal| removal.bytes).sum();
+    println!(
+        "{freed} {} in {} path(s)",
+        format_bytes(bytes),
+        removals.len()
+    );
+}
+
+/// Prints what `update` did, naming every pair that was added, updated,
+/// removed or failed.
+///
+/// # Arguments
+///
+/// - `summary` - The result of the update.
+fn print_update_summary(summary: &UpdateSummary) {
+    for (label, names) in [
+        ("Added", &summary.added),
+        ("Updated", &summary.updated),
+        ("Removed", &summary.removed),
+        ("Failed", &summary.failed),
+    ] {
+        if !names.is_empty() {
+            println!("{label}: {}", names.join(", "));
+        }
+    }
+    println!(
+        "{} added, {} updated, {} unchanged, {} removed, {} failed",
+        summary.added.len(),
+        summary.updated.len(),
+        summary.unchanged.len(),
+        summary.removed.len(),
+        summary.failed.len()
+    );
+}
+
+/// Validates all metadata files and prints every problem found.
+///
+/// The demo metadata is validated separately from the rest, since its pairs
+/// are copies of pairs in the full corpus.  Exits with a non-zero status if
+/// any problem is found.
+///
+/// # Arguments
+///
+/// - `directories` - Where the metadata is.
+fn validate(directories: &Directories) {
+    let mut diagnostics = corpus::validate(&directories.metadata(false));
+    diagnostics.extend(corpus::validate(&directories.metadata(true)));
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}");
+    }
+
+    if diagnostics.is_empty() {
+        println!("All metadata is valid");
+    } else {
+        eprintln!("Found {} problem(s) in the metadata", diagnostics.len());
+        process::exit(1);
+    }
+}
+
+/// Checks the source paths of all program pairs against the cached clones,
+/// and prints every missing path with suggested replacements.
+///
+/// Exits with a non-zero status if any path is missing or any program could
+/// not be checked.
+///
+/// # Arguments
+///
+/// - `directories` - Where the metadata and clones are.
+/// - `demo` - Whether to check the demo program pairs.
+fn check_paths(directories: &Directories, demo: bool) {
+    let pairs = downloader::read_program_pairs(&directories.metadata(demo))
+        .expect("Failed to read metadata");
+
+    let mut problems = 0;
+    for check in corpus::check_paths(&pairs, &directories.cache) {
+        let program = format!("{} ({})", check.program_name, check.language);
+        match check.missing_paths {
+            Ok(missing_paths) => {
+                for missing_path in missing_paths {
+                    problems += 1;
+                    eprintln!("{program}: '{}' does not exist", missing_path.source_path);
+                    if !missing_path.suggestions.is_empty() {
+                        eprintln!("    did you mean: {}", missing_path.suggestions.join(", "));
+                    }
+                }
+            }
+            Err(error) => {
+                problems += 1;
+                eprintln!("{program}: {error}");
+            }
+        }
+    }
+
+    if problems == 0 {
+        println!("All source paths exist");
+    } else {
+        eprintln!("Found {problems} problem(s) in the source paths");
+        process::exit(1);
+    }
+}
+
+/// Prints the program pairs selected by a filter, as a table or as JSON.
+///
+/// # Arguments
+///
+/// - `directories` - Where the metadata is.
+/// - `arguments` - The arguments of the `list` command.
+fn list(directories: &Directories, arguments: ListArgs) {
+    let filter = PairFilter::from(arguments.filter);
+    let pairs: Vec<ProgramPair> =
+        downloader::read_program_pairs(&directories.metadata(arguments.demo))
+            .expect("Failed to read metadata")
+            .into_iter()
+            .filter(|pair| filter.matches(pair))
+            .collect();
+
+    if arguments.json {
+        let json = serde_json::to_string_pretty(&pairs).expect("Failed to serialize pairs");
+        println!("{json}");
+        return;
+    }
+    let rows: Vec<Vec<String>> = pairs
+        .iter()
+        .map(|pair| {
+            vec![
+                pair.program_name.clone(),
+                pair.project.clone().unwrap_or_else(|| "-".to_string()),
+                pair.feature_relationship.to_string(),
+                or_dash(pair.translation_tools.join(", ")),
+                pair.c_program.source_paths.len().to_string(),
+                pair.rust_program.source_paths.len().to_string(),
+            ]
+        })
+        .collect();
+    print!(
+        "{}",
+        table::format_table(
+            &[
+                "NAME",
+                "PROJECT",
+                "FEATURES",
+                "TOOLS",
+                "C PATHS",
+                "RUST PATHS"
+            ],
+            &rows
+        )
+    );
+}
+
+/// Prints the full metadata of one program pair, including the fields it
+/// inherits from its project.
+///
+/// Exits with a non-zero status if there is no pair with the given name.
+///
+/// # Arguments
+///
+/// - `directories` - Where the metadata is.
+/// - `arguments` - The arguments of the `show` command.
+fn show(directories: &Directories, arguments: ShowArgs) {
+    let pairs = downloader::read_program_pairs(&directories.metadata(arguments.demo))
+        .expect("Failed to read metadata");
+    let Some(pair) = pairs
+        .iter()
+        .find(|pair| pair.program_name == arguments.program_name)
+    else {
+        eprintln!("No program pair named '{}'", arguments.program_name);
+        process::exit(1);
+    };
+
+    if arguments.json {
+        let json = serde_json::to_string_pretty(pair).expect("Failed to serialize pair");
+        println!("{json}");
+        return;
+    }
+    println!("Name: {}", pair.program_name);
+    println!("Description: {}", pair.program_description);
+    println!("Project: {}", pair.project.as_deref().unwrap_or("-"));
+    println!("Feature relationship: {}", pair.feature_relationship);
+    println!(
+        "Translation tools: {}",
+        or_dash(pair.translation_tools.join(", "))
+    );
+    for program in [&pair.c_program, &pair.rust_program] {
+        println!();
+        print_program(program);
+    }
+}
+
+/// Prints the metadata of one C or Rust program for the `show` command.
+///
+/// # Arguments
+///
+/// - `program` - The program to print.
+fn print_program(program: &Program) {
+    println!("{} program:", program.language);
+    println!("  Documentation: {}", program.documentation_url);
+    println!("  Repository: {}", program.repository_url);
+    println!(
+        "  Revision: {}",
+        downloader::describe_revision(program.revision.as_ref())
+    );
+    if let Some(archive) = &program.archive {
+        println!("  Archive: {} (SHA-256 {})", archive.url, archive.sha256);
+    }
+    println!("  Extensions: {}", program.extensions.join(", "));
+    println!(
+        "  Crate manifest: {}",
+        if program.crate_manifest { "yes" } else { "no" }
+    );
+    println!("  Source paths:");
+    for source_path in &program.source_paths {
+        println!("    {source_path}");
+    }
+}
+
+/// Returns a cell value, or `-` if it is empty.
+fn or_dash(value: String) -> String {
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
     }
 }
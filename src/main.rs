@@ -15,7 +15,6 @@
 //! ```sh
 //! cargo run demo
 //! ```
-use c_rust_program_pairs;
 
 fn main() {
     c_rust_program_pairs::run();
@@ -1,9 +1,11 @@
 //! # Paths
 //!
 //! This module defines file system paths used throughout the project.
-//! All paths are relative to the project root.
+//! All paths are relative to the project root, and the directories among
+//! them are the defaults of [`Directories`](crate::corpus::config::Directories).
 
-/// Path to the JSON schema for metadata files.
+/// Path to the JSON schema for metadata files.  The schema is embedded into
+/// the binary at build time, so this path is only read by `build.rs`.
 pub const METADATA_SCHEMA_FILE: &str = "metadata/metadata.schema.json";
 
 /// Directory containing metadata files for projects containing multiple
@@ -22,5 +24,29 @@ pub const DEMO_METADATA_DIRECTORY: &str = "metadata/demo";
 pub const PROGRAM_PAIRS_DIRECTORY: &str = "program_pairs";
 
 /// Directory used as a local cache for repository clones to avoid repeatedly
-/// cloning repositories.
+/// cloning repositories.  Clones are stored under their normalized URL, for
+/// example `repository_clones/github.com/uutils/coreutils`.
 pub const REPOSITORY_CLONES_DIRECTORY: &str = "repository_clones";
+
+/// Lockfile recording the commits and file hashes of the downloaded corpus.
+pub const CORPUS_LOCKFILE: &str = "corpus.lock.json";
+
+/// Name of the manifest written into each program pair's directory under
+/// [`PROGRAM_PAIRS_DIRECTORY`].
+pub const PAIR_MANIFEST_FILE: &str = "pair.json";
+
+/// Name of the manifest at the top of an exported corpus, listing every
+/// program pair it contains.
+pub const EXPORT_MANIFEST_FILE: &str = "corpus.json";
+
+/// The manifest of a Rust crate, copied alongside a Rust program's source
+/// files when its metadata sets `crate_manifest`.
+pub const CRATE_MANIFEST_FILE: &str = "Cargo.toml";
+
+/// Optional configuration file holding machine-specific settings, such as
+/// mirrors to fetch repositories from.
+pub const CONFIG_FILE: &str = "corpus.config.json";
+
+/// Marker file placed in the output and cache directories when this tool
+/// creates them, so `delete` and `gc` only remove directories it created.
+pub const DIRECTORY_MARKER_FILE: &str = ".c-rust-program-pairs";
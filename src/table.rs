@@ -0,0 +1,64 @@
+//! # Tables
+//!
+//! This module formats rows of text as a table with aligned columns, for
+//! printing to the terminal.
+
+/// The space between two columns.
+const COLUMN_SEPARATOR: &str = "  ";
+
+/// Formats rows as a table whose columns are left-aligned.
+///
+/// # Arguments
+///
+/// - `headers` - The title of each column.
+/// - `rows` - The cells of each row, one per column.
+///
+/// # Returns
+///
+/// The table, one line per row after the header line, each ending with a
+/// newline.
+pub fn format_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header_row: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    let mut table = String::new();
+    for row in std::iter::once(&header_row).chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join(COLUMN_SEPARATOR);
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that columns are as wide as their widest cell.
+    #[test]
+    fn test_format_table() {
+        let rows = [
+            vec!["ls".to_string(), "coreutils".to_string(), "2".to_string()],
+            vec!["gitoxide".to_string(), "-".to_string(), "10".to_string()],
+        ];
+        assert_eq!(
+            format_table(&["NAME", "PROJECT", "PATHS"], &rows),
+            "NAME      PROJECT    PATHS\n\
+             ls        coreutils  2\n\
+             gitoxide  -          10\n"
+        );
+    }
+}